@@ -0,0 +1,59 @@
+use std::str::FromStr;
+
+/// A request as the client sent it: the command name and its arguments, with the protocol framing removed.
+#[derive(Debug)]
+pub struct Request {
+    pub name: String, // As sent; compare case-insensitively
+    pub args: Vec<String>,
+}
+
+impl Request {
+    /// Splits an argument vector into its command name and arguments. None if it's empty.
+    pub fn from_argv(mut argv: Vec<String>) -> Option<Self> {
+        if argv.is_empty() {
+            return None;
+        }
+        let name = argv.remove(0);
+        Some(Request { name, args: argv })
+    }
+}
+
+/// Walks a command's optional arguments, matching option names case-insensitively.
+pub struct Options<'a> {
+    args: std::slice::Iter<'a, &'a str>,
+}
+
+impl<'a> Options<'a> {
+    pub fn new(args: &'a [&'a str]) -> Self {
+        Options { args: args.iter() }
+    }
+
+    /// The next option name, uppercased.
+    pub fn next_option(&mut self) -> Option<String> {
+        self.args.next().map(|arg| arg.to_uppercase())
+    }
+
+    /// The next argument as-is, e.g. an option's value.
+    pub fn value(&mut self) -> Option<&'a str> {
+        self.args.next().copied()
+    }
+
+    /// The next argument parsed as `T`. None if it's missing or doesn't parse.
+    pub fn parse<T: FromStr>(&mut self) -> Option<T> {
+        self.value()?.parse().ok()
+    }
+
+    /// Whether the next argument is `option`; consumes it if so.
+    pub fn next_is(&mut self, option: &str) -> bool {
+        let matches = self.args.as_slice().first().is_some_and(|arg| arg.eq_ignore_ascii_case(option));
+        if matches {
+            self.args.next();
+        }
+        matches
+    }
+
+    /// Everything not consumed yet.
+    pub fn rest(&self) -> &'a [&'a str] {
+        self.args.as_slice()
+    }
+}
@@ -0,0 +1,129 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::{Entry, dict::Dict, resp::Reply};
+
+/// A blocking command's timeout in seconds as an upper bound on the wait, None
+/// (no limit) for 0.
+pub fn timeout_from_secs(seconds: f64) -> Option<Duration> {
+    Duration::try_from_secs_f64(seconds).ok().filter(|timeout| !timeout.is_zero())
+}
+
+/// Clients blocked on keys by BLPOP and friends, in the order they blocked. Each one
+/// waits on its own Condvar, queued under every key it's waiting for, and a push
+/// only wakes the client at the front of that key's queue.
+#[derive(Default)]
+pub struct BlockedClients {
+    waiting: Mutex<HashMap<String, VecDeque<Arc<Condvar>>>>,
+}
+
+impl BlockedClients {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs a blocking command: calls `serve` until it has a reply, blocking on `keys`
+    /// in between, or gives up with None once `timeout` (None for no limit) has passed.
+    /// The keyspace lock is only held while `serve` runs. Commands that take elements
+    /// away should only take from a key when the check they're handed says it's their
+    /// turn on it, so clients blocked on the same key are served in the order they came.
+    pub fn serve(
+        &self,
+        mut map: MutexGuard<'_, Dict<Entry>>,
+        keys: &[String],
+        timeout: Option<Duration>,
+        mut serve: impl FnMut(&mut Dict<Entry>, &dyn Fn(&str) -> bool) -> Option<Reply>,
+    ) -> Option<Reply> {
+        // Dropped before `map`, so the next client in line can't miss its turn
+        let waiter = self.block(keys);
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+        loop {
+            if let Some(reply) = serve(&mut map, &|key| waiter.is_next(key)) {
+                return Some(reply);
+            }
+
+            let remaining = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(remaining) if !remaining.is_zero() => Some(remaining),
+                    _ => return None,
+                },
+                None => None,
+            };
+            map = waiter.wait(map, remaining);
+        }
+    }
+
+    // Queues the caller on `keys` behind any clients already blocked on them
+    fn block(&self, keys: &[String]) -> Waiter<'_> {
+        let condvar = Arc::new(Condvar::new());
+        let mut waiting = self.waiting.lock().unwrap();
+        for key in keys {
+            waiting.entry(key.clone()).or_default().push_back(Arc::clone(&condvar));
+        }
+        Waiter { clients: self, keys: keys.to_vec(), condvar }
+    }
+
+    /// Wakes the client that has been blocked on `key` the longest. Call with the
+    /// keyspace lock held, after the change, so it can't be missed.
+    pub fn signal(&self, key: &str) {
+        if let Some(first) = self.waiting.lock().unwrap().get(key).and_then(VecDeque::front) {
+            first.notify_one();
+        }
+    }
+
+    /// Wakes every client blocked on `key`, for changes that don't get used up, like
+    /// a new stream entry.
+    pub fn signal_all(&self, key: &str) {
+        if let Some(waiters) = self.waiting.lock().unwrap().get(key) {
+            waiters.iter().for_each(|waiter| waiter.notify_one());
+        }
+    }
+}
+
+// A client's place in line on the keys it's blocked on, given up when dropped
+struct Waiter<'a> {
+    clients: &'a BlockedClients,
+    keys: Vec<String>,
+    condvar: Arc<Condvar>,
+}
+
+impl Waiter<'_> {
+    // Whether nobody blocked on `key` before us is still waiting
+    fn is_next(&self, key: &str) -> bool {
+        let waiting = self.clients.waiting.lock().unwrap();
+        waiting.get(key).and_then(VecDeque::front).is_some_and(|first| Arc::ptr_eq(first, &self.condvar))
+    }
+
+    // Releases the keyspace lock until we're signalled or `timeout` passes. Wakeups
+    // can be spurious, so the caller checks its keys again afterwards.
+    fn wait<'m>(&self, map: MutexGuard<'m, Dict<Entry>>, timeout: Option<Duration>) -> MutexGuard<'m, Dict<Entry>> {
+        match timeout {
+            Some(timeout) => self.condvar.wait_timeout(map, timeout).unwrap().0,
+            None => self.condvar.wait(map).unwrap(),
+        }
+    }
+}
+
+impl Drop for Waiter<'_> {
+    fn drop(&mut self) {
+        let mut waiting = self.clients.waiting.lock().unwrap();
+        for key in &self.keys {
+            let Some(waiters) = waiting.get_mut(key) else {
+                continue;
+            };
+            let was_first = waiters.front().is_some_and(|first| Arc::ptr_eq(first, &self.condvar));
+            waiters.retain(|waiter| !Arc::ptr_eq(waiter, &self.condvar));
+
+            // Whatever we left behind is now the next client's turn
+            match waiters.front() {
+                Some(next) if was_first => next.notify_one(),
+                Some(_) => {}
+                None => {
+                    waiting.remove(key);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,160 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+// Defaults used when BF.ADD / BF.MADD create the filter implicitly
+pub const DEFAULT_ERROR_RATE: f64 = 0.01;
+pub const DEFAULT_CAPACITY: u64 = 100;
+pub const DEFAULT_EXPANSION: u32 = 2;
+
+// Each new sub-filter gets a tighter error rate so the compound rate stays bounded
+const TIGHTENING_RATIO: f64 = 0.5;
+
+// Largest bit array one sub-filter may allocate, 512MB
+const MAX_LAYER_BITS: u64 = 1 << 32;
+
+// Bits a sub-filter needs to hold `capacity` items at `error_rate`
+fn bits_needed(capacity: u64, error_rate: f64) -> f64 {
+    let ln2 = std::f64::consts::LN_2;
+    ((-(capacity as f64) * error_rate.ln()) / (ln2 * ln2)).ceil().max(64.0)
+}
+
+/// Whether a filter for `capacity` items at `error_rate` is small enough to create.
+pub fn fits(capacity: u64, error_rate: f64) -> bool {
+    bits_needed(capacity, error_rate) <= MAX_LAYER_BITS as f64
+}
+
+#[derive(Debug)]
+struct BloomLayer {
+    bits: Vec<u64>,
+    num_bits: u64,
+    num_hashes: u32,
+    capacity: u64,
+    count: u64,
+}
+
+impl BloomLayer {
+    // Callers check `fits` first
+    fn new(capacity: u64, error_rate: f64) -> Self {
+        let num_bits = bits_needed(capacity, error_rate) as u64;
+        let num_hashes = (-error_rate.log2()).ceil().max(1.0) as u32;
+
+        BloomLayer {
+            bits: vec![0; num_bits.div_ceil(64) as usize],
+            num_bits,
+            num_hashes,
+            capacity,
+            count: 0,
+        }
+    }
+
+    // Double hashing: bit_i = h1 + i * h2 (mod m)
+    fn positions(&self, hashes: (u64, u64)) -> impl Iterator<Item = u64> + '_ {
+        let (h1, h2) = hashes;
+        (0..self.num_hashes as u64).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % self.num_bits)
+    }
+
+    fn contains(&self, hashes: (u64, u64)) -> bool {
+        self.positions(hashes)
+            .all(|bit| self.bits[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+
+    fn insert(&mut self, hashes: (u64, u64)) {
+        let positions: Vec<u64> = self.positions(hashes).collect();
+        for bit in positions {
+            self.bits[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+        self.count += 1;
+    }
+
+    fn size_in_bytes(&self) -> usize {
+        self.bits.len() * 8
+    }
+}
+
+#[derive(Debug)]
+pub struct ScalableBloom {
+    layers: Vec<BloomLayer>,
+    error_rate: f64,
+    expansion: u32,
+    nonscaling: bool,
+}
+
+#[derive(Debug)]
+pub enum BloomError {
+    Full,
+    TooLarge, // The next sub-filter would be too big to allocate
+}
+
+impl ScalableBloom {
+    pub fn new(error_rate: f64, capacity: u64, expansion: u32, nonscaling: bool) -> Self {
+        ScalableBloom {
+            layers: vec![BloomLayer::new(capacity, error_rate)],
+            error_rate,
+            expansion,
+            nonscaling,
+        }
+    }
+
+    fn hash_item(item: &str) -> (u64, u64) {
+        let mut hasher = DefaultHasher::new();
+        item.hash(&mut hasher);
+        let h1 = hasher.finish();
+
+        let mut hasher = DefaultHasher::new();
+        (item, 0x9e37_79b9_7f4a_7c15u64).hash(&mut hasher);
+        // An even step could cycle over only half of the bits
+        let h2 = hasher.finish() | 1;
+
+        (h1, h2)
+    }
+
+    pub fn exists(&self, item: &str) -> bool {
+        let hashes = Self::hash_item(item);
+        self.layers.iter().any(|layer| layer.contains(hashes))
+    }
+
+    /// Returns Ok(true) if the item was newly added, Ok(false) if it may already exist.
+    pub fn add(&mut self, item: &str) -> Result<bool, BloomError> {
+        let hashes = Self::hash_item(item);
+        if self.layers.iter().any(|layer| layer.contains(hashes)) {
+            return Ok(false);
+        }
+
+        let last = self.layers.last().unwrap();
+        if last.count >= last.capacity {
+            if self.nonscaling {
+                return Err(BloomError::Full);
+            }
+            let error_rate = self.error_rate * TIGHTENING_RATIO.powi(self.layers.len() as i32);
+            let capacity = last
+                .capacity
+                .checked_mul(self.expansion as u64)
+                .filter(|&capacity| fits(capacity, error_rate))
+                .ok_or(BloomError::TooLarge)?;
+            self.layers.push(BloomLayer::new(capacity, error_rate));
+        }
+
+        self.layers.last_mut().unwrap().insert(hashes);
+        Ok(true)
+    }
+
+    pub fn capacity(&self) -> u64 {
+        self.layers.iter().map(|layer| layer.capacity).sum()
+    }
+
+    pub fn size_in_bytes(&self) -> usize {
+        self.layers.iter().map(|layer| layer.size_in_bytes()).sum()
+    }
+
+    pub fn num_filters(&self) -> usize {
+        self.layers.len()
+    }
+
+    pub fn num_items(&self) -> u64 {
+        self.layers.iter().map(|layer| layer.count).sum()
+    }
+
+    pub fn expansion(&self) -> u32 {
+        self.expansion
+    }
+}
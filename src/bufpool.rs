@@ -0,0 +1,135 @@
+use std::io::{Read, Result as IoResult, Write};
+use std::ops::{Deref, DerefMut};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Buffers are pooled by capacity; a request is served from the smallest class that fits
+const SIZE_CLASSES: [usize; 4] = [1024, 4096, 16 * 1024, 64 * 1024];
+
+// Idle buffers kept per class. Anything returned beyond this is freed, so a burst
+// of connections can't pin its peak memory forever.
+const MAX_IDLE_PER_CLASS: usize = 1024;
+
+static POOLS: [Mutex<Vec<Vec<u8>>>; SIZE_CLASSES.len()] = [const { Mutex::new(Vec::new()) }; SIZE_CLASSES.len()];
+
+static HITS: AtomicU64 = AtomicU64::new(0);
+static MISSES: AtomicU64 = AtomicU64::new(0);
+static DISCARDS: AtomicU64 = AtomicU64::new(0);
+
+fn class_for(capacity: usize) -> Option<usize> {
+    SIZE_CLASSES.iter().position(|&size| size >= capacity)
+}
+
+/// A buffer checked out of the pool, returned to it on drop.
+pub struct PooledBuf {
+    buf: Vec<u8>,
+}
+
+/// Checks out an empty buffer with at least `capacity` bytes of room.
+pub fn get(capacity: usize) -> PooledBuf {
+    let class = class_for(capacity);
+    let pooled = class.and_then(|class| POOLS[class].lock().unwrap().pop());
+
+    let buf = match pooled {
+        Some(buf) => {
+            HITS.fetch_add(1, Ordering::Relaxed);
+            buf
+        }
+        None => {
+            MISSES.fetch_add(1, Ordering::Relaxed);
+            Vec::with_capacity(class.map_or(capacity, |class| SIZE_CLASSES[class]))
+        }
+    };
+    PooledBuf { buf }
+}
+
+impl Deref for PooledBuf {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.buf
+    }
+}
+
+impl DerefMut for PooledBuf {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.buf
+    }
+}
+
+impl Drop for PooledBuf {
+    fn drop(&mut self) {
+        // A buffer that grew is filed under the largest class it can still serve;
+        // one beyond the largest class would bloat the pool, so it's freed
+        let capacity = self.buf.capacity();
+        if capacity > SIZE_CLASSES[SIZE_CLASSES.len() - 1] {
+            DISCARDS.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        let Some(class) = SIZE_CLASSES.iter().rposition(|&size| size <= capacity) else { return };
+
+        let mut pool = POOLS[class].lock().unwrap();
+        if pool.len() < MAX_IDLE_PER_CLASS {
+            let mut buf = std::mem::take(&mut self.buf);
+            buf.clear();
+            pool.push(buf);
+        } else {
+            DISCARDS.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Wraps a connection so replies are gathered in a pooled buffer and sent with
+/// a single write, just before the next read.
+pub struct PooledStream<S> {
+    inner: S,
+    out: PooledBuf,
+}
+
+impl<S: Read + Write> PooledStream<S> {
+    pub fn new(inner: S) -> Self {
+        PooledStream { inner, out: get(SIZE_CLASSES[0]) }
+    }
+}
+
+impl<S: Read + Write> Read for PooledStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        self.flush()?;
+        self.inner.read(buf)
+    }
+}
+
+impl<S: Read + Write> Write for PooledStream<S> {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        self.out.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        if !self.out.is_empty() {
+            self.inner.write_all(&self.out)?;
+            self.out.clear();
+            // Don't hold on to the room a large reply needed for the rest of the connection
+            if self.out.capacity() > SIZE_CLASSES[0] {
+                self.out = get(SIZE_CLASSES[0]);
+            }
+        }
+        self.inner.flush()
+    }
+}
+
+// Lines for the bufpool part of the "# Memory" INFO section
+pub fn info_fields() -> Vec<(&'static str, String)> {
+    let hits = HITS.load(Ordering::Relaxed);
+    let misses = MISSES.load(Ordering::Relaxed);
+    let idle: usize = POOLS.iter().map(|pool| pool.lock().unwrap().len()).sum();
+    let hit_rate = if hits + misses > 0 { hits as f64 * 100.0 / (hits + misses) as f64 } else { 0.0 };
+
+    vec![
+        ("bufpool_hits", hits.to_string()),
+        ("bufpool_misses", misses.to_string()),
+        ("bufpool_hit_rate_perc", format!("{:.2}", hit_rate)),
+        ("bufpool_discards", DISCARDS.load(Ordering::Relaxed).to_string()),
+        ("bufpool_idle_buffers", idle.to_string()),
+    ]
+}
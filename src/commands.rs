@@ -0,0 +1,217 @@
+use crate::args::Request;
+
+// Arity of every command, as in Redis' command table: N means exactly N
+// arguments counting the command name, -N means at least N. Subcommands of
+// container commands are listed as "container|subcommand".
+const COMMAND_TABLE: &[(&str, i64)] = &[
+    ("ping", -1),
+    ("echo", 2),
+    ("set", -3),
+    ("get", 2),
+    ("getset", 3),
+    ("setnx", 3),
+    ("setex", 4),
+    ("psetex", 4),
+    ("getdel", 2),
+    ("getex", -2),
+    ("lcs", -3),
+    ("incr", 2),
+    ("decr", 2),
+    ("incrby", 3),
+    ("decrby", 3),
+    ("incrbyfloat", 3),
+    ("append", 3),
+    ("strlen", 2),
+    ("getrange", 4),
+    ("setrange", 4),
+    ("del", -2),
+    ("unlink", -2),
+    ("exists", -2),
+    ("keys", 2),
+    ("scan", -2),
+    ("rename", 3),
+    ("renamenx", 3),
+    ("randomkey", 1),
+    ("touch", -2),
+    ("dump", 2),
+    ("restore", -4),
+    ("object", -2),
+    ("object|encoding", 3),
+    ("object|refcount", 3),
+    ("object|idletime", 3),
+    ("object|freq", 3),
+    ("memory", -2),
+    ("memory|usage", -3),
+    ("ttl", 2),
+    ("pttl", 2),
+    ("expiretime", 2),
+    ("pexpiretime", 2),
+    ("expire", -3),
+    ("pexpire", -3),
+    ("expireat", -3),
+    ("pexpireat", -3),
+    ("rpush", -3),
+    ("lpush", -3),
+    ("lrange", 4),
+    ("ltrim", 4),
+    ("llen", 2),
+    ("lindex", 3),
+    ("lset", 4),
+    ("lpos", -3),
+    ("linsert", 5),
+    ("lrem", 4),
+    ("lpop", -2),
+    ("rpop", -2),
+    ("blpop", -3),
+    ("brpop", -3),
+    ("lmpop", -4),
+    ("blmpop", -5),
+    ("blmove", 6),
+    ("brpoplpush", 4),
+    ("hset", -4),
+    ("hget", 3),
+    ("hdel", -3),
+    ("hgetall", 2),
+    ("hmget", -3),
+    ("hkeys", 2),
+    ("hvals", 2),
+    ("hlen", 2),
+    ("hsetnx", 4),
+    ("hexists", 3),
+    ("hstrlen", 3),
+    ("hrandfield", -2),
+    ("hscan", -3),
+    ("hincrby", 4),
+    ("hincrbyfloat", 4),
+    ("hexpire", -6),
+    ("hpexpire", -6),
+    ("hexpireat", -6),
+    ("hpexpireat", -6),
+    ("httl", -5),
+    ("hpttl", -5),
+    ("hpersist", -5),
+    ("sadd", -3),
+    ("srem", -3),
+    ("smembers", 2),
+    ("sismember", 3),
+    ("smismember", -3),
+    ("scard", 2),
+    ("sscan", -3),
+    ("sinter", -2),
+    ("sunion", -2),
+    ("sdiff", -2),
+    ("sinterstore", -3),
+    ("sunionstore", -3),
+    ("sdiffstore", -3),
+    ("sintercard", -3),
+    ("smove", 4),
+    ("spop", -2),
+    ("srandmember", -2),
+    ("zadd", -4),
+    ("zscore", 3),
+    ("zrange", -4),
+    ("xadd", -5),
+    ("xrange", -4),
+    ("xread", -4),
+    ("type", 2),
+    ("hello", -1),
+    ("info", -1),
+    ("readonly", 1),
+    ("readwrite", 1),
+    ("client", -2),
+    ("client|setinfo", 4),
+    ("client|list", -2),
+    ("client|info", 2),
+    ("latency", -2),
+    ("latency|histogram", -2),
+    ("bf.reserve", -4),
+    ("bf.add", 3),
+    ("bf.madd", -3),
+    ("bf.exists", 3),
+    ("bf.info", -2),
+    ("topk.reserve", -3),
+    ("topk.add", -3),
+    ("topk.query", -3),
+    ("topk.list", -2),
+    ("cms.initbydim", 4),
+    ("cms.incrby", -4),
+    ("cms.query", -3),
+    ("ts.create", -2),
+    ("ts.add", -4),
+    ("ts.range", -4),
+    ("ts.mrange", -5),
+    ("vadd", -5),
+    ("vsim", -4),
+    ("vcard", 2),
+    ("vdim", 2),
+    ("vrem", 3),
+    ("vemb", -3),
+    ("vgetattr", 3),
+    ("vsetattr", 4),
+];
+
+/// How many entries the command table has, subcommands included.
+pub const COUNT: usize = COMMAND_TABLE.len();
+
+/// Where `name` sits in the command table, a stable index for per-command state.
+pub fn index(name: &str) -> Option<usize> {
+    COMMAND_TABLE.iter().position(|(command, _)| *command == name)
+}
+
+/// The name at `index` in the command table.
+pub fn name(index: usize) -> &'static str {
+    COMMAND_TABLE[index].0
+}
+
+fn arity(name: &str) -> Option<i64> {
+    COMMAND_TABLE.iter().find(|(command, _)| *command == name).map(|&(_, arity)| arity)
+}
+
+/// Whether `name` is a command, as opposed to a subcommand or something unknown.
+pub fn is_command(name: &str) -> bool {
+    !name.contains('|') && arity(name).is_some()
+}
+
+fn is_container(name: &str) -> bool {
+    COMMAND_TABLE.iter().any(|(command, _)| command.strip_prefix(name).is_some_and(|rest| rest.starts_with('|')))
+}
+
+/// Checks that `request` names a known command with a valid argument count,
+/// returning the error Redis would send otherwise.
+pub fn check(request: &Request) -> Result<(), String> {
+    let name = request.name.to_lowercase();
+    let Some(mut arity) = arity(&name) else {
+        // Redis quotes the first 128 bytes of each argument
+        let args: String = request.args.iter().map(|arg| format!("'{}' ", truncate(arg, 128))).collect();
+        return Err(format!(
+            "ERR unknown command '{}', with args beginning with: {}",
+            truncate(&request.name, 128),
+            args
+        ));
+    };
+
+    // Container commands are checked by their subcommand
+    let mut full_name = name.clone();
+    if let Some(sub) = request.args.first()
+        && is_container(&name)
+    {
+        full_name = format!("{}|{}", name, sub.to_lowercase());
+        arity = match self::arity(&full_name) {
+            Some(arity) => arity,
+            None => return Err(format!("ERR unknown subcommand '{}'. Try {} HELP.", sub, name.to_uppercase())),
+        };
+    }
+
+    let argc = request.args.len() as i64 + 1;
+    if (arity > 0 && argc != arity) || argc < arity.abs() {
+        return Err(format!("ERR wrong number of arguments for '{}' command", full_name));
+    }
+    Ok(())
+}
+
+fn truncate(s: &str, max: usize) -> &str {
+    match s.char_indices().nth(max) {
+        Some((end, _)) => &s[..end],
+        None => s,
+    }
+}
@@ -0,0 +1,234 @@
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Port the RESP listener binds when not socket-activated
+pub const DEFAULT_PORT: u16 = 6379;
+
+// Same defaults as Redis
+const DEFAULT_QUERY_BUFFER_LIMIT: usize = 1024 * 1024 * 1024;
+const DEFAULT_PROTO_MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+const DEFAULT_SET_MAX_INTSET_ENTRIES: usize = 512;
+
+// Limits read on the request path, set once from the config at startup
+static QUERY_BUFFER_LIMIT: AtomicUsize = AtomicUsize::new(DEFAULT_QUERY_BUFFER_LIMIT);
+static PROTO_MAX_BULK_LEN: AtomicUsize = AtomicUsize::new(DEFAULT_PROTO_MAX_BULK_LEN);
+static SET_MAX_INTSET_ENTRIES: AtomicUsize = AtomicUsize::new(DEFAULT_SET_MAX_INTSET_ENTRIES);
+
+/// Largest amount of unexecuted input a connection may buffer before it's dropped.
+pub fn query_buffer_limit() -> usize {
+    QUERY_BUFFER_LIMIT.load(Ordering::Relaxed)
+}
+
+/// Largest bulk string a client may announce.
+pub fn proto_max_bulk_len() -> usize {
+    PROTO_MAX_BULK_LEN.load(Ordering::Relaxed)
+}
+
+/// Most members a set of integers may have before it's stored as a hash table.
+pub fn set_max_intset_entries() -> usize {
+    SET_MAX_INTSET_ENTRIES.load(Ordering::Relaxed)
+}
+
+#[derive(Debug)]
+pub struct Config {
+    pub ws_port: Option<u16>,            // Optional RESP-over-WebSocket listener
+    pub ws_allowed_origins: Vec<String>, // Origins browsers may open WebSockets from
+    pub http_port: Option<u16>,          // Optional HTTP/JSON gateway
+    pub http_token: Option<String>,      // Token the gateway and WebSocket listener require
+    pub memcached_port: Option<u16>,     // Optional memcached text protocol listener
+    pub load_commands: Option<String>,   // RESP protocol file replayed at startup
+    pub client_query_buffer_limit: usize,
+    pub proto_max_bulk_len: usize,
+    pub set_max_intset_entries: usize,
+    pub check_config: bool, // Validate and exit without serving
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            ws_port: None,
+            http_port: None,
+            http_token: None,
+            ws_allowed_origins: Vec::new(),
+            memcached_port: None,
+            load_commands: None,
+            client_query_buffer_limit: DEFAULT_QUERY_BUFFER_LIMIT,
+            proto_max_bulk_len: DEFAULT_PROTO_MAX_BULK_LEN,
+            set_max_intset_entries: DEFAULT_SET_MAX_INTSET_ENTRIES,
+            check_config: false,
+        }
+    }
+}
+
+// Port directives use 0 to mean "disabled", like Redis' own port settings
+fn parse_port(value: &str) -> Result<Option<u16>, String> {
+    match value.parse::<u16>() {
+        Ok(0) => Ok(None),
+        Ok(port) => Ok(Some(port)),
+        Err(_) => Err(format!("'{}' is not a valid port (0-65535)", value)),
+    }
+}
+
+// Memory sizes as Redis writes them: a byte count with an optional k/kb/m/mb/g/gb suffix
+fn parse_memory(value: &str) -> Result<usize, String> {
+    let lower = value.to_lowercase();
+    let split = lower.find(|c: char| !c.is_ascii_digit()).unwrap_or(lower.len());
+    let (digits, unit) = lower.split_at(split);
+    let multiplier = match unit {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return Err(format!("'{}' is not a valid memory size", value)),
+    };
+    digits
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| format!("'{}' is not a valid memory size", value))
+}
+
+impl Config {
+    /// Builds the configuration from `[config-file] [--directive value ...] [--check-config]`.
+    /// Command-line directives override the file. Every problem is reported, not just the first.
+    pub fn from_args(args: impl Iterator<Item = String>) -> Result<Self, Vec<String>> {
+        let mut config = Config::default();
+        let mut errors = Vec::new();
+        let mut args = args.skip(1).peekable();
+
+        // Like redis-server, a leading non-option argument is the config file
+        if let Some(path) = args.next_if(|arg| !arg.starts_with("--")) {
+            match std::fs::read_to_string(&path) {
+                Ok(contents) => config.apply_file(&path, &contents, &mut errors),
+                Err(e) => errors.push(format!("can't read config file {}: {}", path, e)),
+            }
+        }
+
+        while let Some(arg) = args.next() {
+            let Some(name) = arg.strip_prefix("--") else {
+                errors.push(format!("unexpected argument '{}'", arg));
+                continue;
+            };
+            if name == "check-config" {
+                config.check_config = true;
+                continue;
+            }
+            match args.next() {
+                Some(value) => {
+                    if let Err(e) = config.apply(name, &value) {
+                        errors.push(format!("--{}: {}", name, e));
+                    }
+                }
+                None => errors.push(format!("--{} requires a value", name)),
+            }
+        }
+
+        errors.extend(config.validate());
+        if errors.is_empty() { Ok(config) } else { Err(errors) }
+    }
+
+    fn apply_file(&mut self, path: &str, contents: &str, errors: &mut Vec<String>) {
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (name, value) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let value = value.trim().trim_matches('"');
+            if let Err(e) = self.apply(&name.to_lowercase(), value) {
+                errors.push(format!("{}:{}: {}: {}", path, number + 1, name, e));
+            }
+        }
+    }
+
+    fn apply(&mut self, name: &str, value: &str) -> Result<(), String> {
+        match name {
+            "ws-port" => self.ws_port = parse_port(value)?,
+            "http-port" => self.http_port = parse_port(value)?,
+            "memcached-port" => self.memcached_port = parse_port(value)?,
+            "ws-allowed-origins" => self.ws_allowed_origins = value.split_whitespace().map(str::to_string).collect(),
+            "http-token" => {
+                if value.is_empty() {
+                    return Err(String::from("requires a token"));
+                }
+                self.http_token = Some(value.to_string());
+            }
+            "load-commands" => {
+                if value.is_empty() {
+                    return Err(String::from("requires a file"));
+                }
+                self.load_commands = Some(value.to_string());
+            }
+            "client-query-buffer-limit" => {
+                self.client_query_buffer_limit = parse_memory(value)?;
+                // Redis refuses limits this small since no real client could work within them
+                if self.client_query_buffer_limit < 1024 * 1024 {
+                    return Err(String::from("must be at least 1mb"));
+                }
+            }
+            "proto-max-bulk-len" => {
+                self.proto_max_bulk_len = parse_memory(value)?;
+                if self.proto_max_bulk_len < 1024 * 1024 {
+                    return Err(String::from("must be at least 1mb"));
+                }
+            }
+            "set-max-intset-entries" => {
+                self.set_max_intset_entries = value.parse().map_err(|_| format!("'{}' is not a valid number", value))?;
+            }
+            _ => return Err(String::from("unknown directive")),
+        }
+        Ok(())
+    }
+
+    /// Makes the settings read on the request path take effect.
+    pub fn apply_limits(&self) {
+        QUERY_BUFFER_LIMIT.store(self.client_query_buffer_limit, Ordering::Relaxed);
+        PROTO_MAX_BULK_LEN.store(self.proto_max_bulk_len, Ordering::Relaxed);
+        SET_MAX_INTSET_ENTRIES.store(self.set_max_intset_entries, Ordering::Relaxed);
+    }
+
+    // Checks combinations the individual directives can't catch on their own
+    fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        let ports = [
+            ("port", Some(DEFAULT_PORT)),
+            ("ws-port", self.ws_port),
+            ("http-port", self.http_port),
+            ("memcached-port", self.memcached_port),
+        ];
+        for (i, (name, port)) in ports.iter().enumerate() {
+            let Some(port) = port else { continue };
+            if let Some((other, _)) = ports[..i].iter().find(|(_, p)| *p == Some(*port)) {
+                errors.push(format!("{} {} conflicts with {}", name, port, other));
+            }
+        }
+
+        // The gateway runs commands for anyone who can reach it, so it's never left open
+        if self.http_port.is_some() && self.http_token.is_none() {
+            errors.push(String::from("http-port requires http-token"));
+        }
+        if self.ws_port.is_some() && self.http_token.is_none() {
+            errors.push(String::from("ws-port requires http-token"));
+        }
+
+        // A bulk string that big could never fit in the query buffer anyway
+        if self.proto_max_bulk_len > self.client_query_buffer_limit {
+            errors.push(String::from("proto-max-bulk-len can't be larger than client-query-buffer-limit"));
+        }
+
+        if let Some(path) = &self.load_commands {
+            match std::fs::File::open(Path::new(path)) {
+                Ok(file) if file.metadata().map(|m| m.is_file()).unwrap_or(false) => {}
+                Ok(_) => errors.push(format!("load-commands: {} is not a regular file", path)),
+                Err(e) => errors.push(format!("load-commands: can't open {}: {}", path, e)),
+            }
+        }
+
+        errors
+    }
+}
@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+use std::collections::hash_map::{self, DefaultHasher, RandomState};
+use std::hash::{BuildHasher, Hash, Hasher};
+
+// Keys are spread over this many independent tables. A table only grows when
+// its own share of keys outgrows it, so a resize rehashes 1/NUM_SHARDS of the
+// keyspace instead of all of it while the global lock is held.
+const NUM_SHARDS: usize = 1024;
+
+/// Keyspace dictionary: a HashMap split into fixed shards that resize independently.
+#[derive(Debug)]
+pub struct Dict<V> {
+    shards: Vec<HashMap<String, V>>,
+}
+
+impl<V> Dict<V> {
+    pub fn new() -> Self {
+        Dict {
+            shards: (0..NUM_SHARDS).map(|_| HashMap::new()).collect(),
+        }
+    }
+
+    // Uses a fixed hasher so a key always maps to the same shard
+    fn key_hash(key: &str) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn shard_index(key: &str) -> usize {
+        (Self::key_hash(key) % NUM_SHARDS as u64) as usize
+    }
+
+    // Where a key sits in the order SCAN walks its shard: the hash bits the shard
+    // index doesn't use
+    fn scan_position(key: &str) -> u64 {
+        Self::key_hash(key) / NUM_SHARDS as u64
+    }
+
+    pub fn get(&self, key: &str) -> Option<&V> {
+        self.shards[Self::shard_index(key)].get(key)
+    }
+
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut V> {
+        self.shards[Self::shard_index(key)].get_mut(key)
+    }
+
+    pub fn insert(&mut self, key: String, value: V) -> Option<V> {
+        self.shards[Self::shard_index(&key)].insert(key, value)
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<V> {
+        self.shards[Self::shard_index(key)].remove(key)
+    }
+
+    pub fn entry(&mut self, key: String) -> hash_map::Entry<'_, String, V> {
+        self.shards[Self::shard_index(&key)].entry(key)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &V)> {
+        self.shards.iter().flat_map(|shard| shard.iter())
+    }
+
+    /// A key picked uniformly at random, None if the dict is empty.
+    pub fn random_key(&self) -> Option<&String> {
+        let len: usize = self.shards.iter().map(HashMap::len).sum();
+        if len == 0 {
+            return None;
+        }
+
+        // Each RandomState is freshly keyed, which is all the randomness this needs
+        let mut index = (RandomState::new().hash_one(0) % len as u64) as usize;
+        for shard in &self.shards {
+            if index < shard.len() {
+                return shard.keys().nth(index);
+            }
+            index -= shard.len();
+        }
+        None
+    }
+
+    /// About `count` entries from `cursor` on, plus the cursor to continue from (0 once
+    /// every shard has been visited). Shards are walked in turn, and each one in order of
+    /// its keys' hashes; the cursor holds the shard in its low bits and the position in
+    /// that order above them. A key never changes shard or position, so one that exists
+    /// for the whole scan is returned at least once however the tables resize in between.
+    /// A call looks at no more than the shards it returns keys from.
+    pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<(&String, &V)>) {
+        let mut shard = (cursor % NUM_SHARDS as u64) as usize;
+        let mut from = cursor / NUM_SHARDS as u64;
+        let mut entries = Vec::new();
+
+        while shard < NUM_SHARDS && entries.len() < count {
+            let mut pending: Vec<(u64, (&String, &V))> = self.shards[shard]
+                .iter()
+                .map(|entry| (Self::scan_position(entry.0), entry))
+                .filter(|(position, _)| *position >= from)
+                .collect();
+
+            // Only the lowest positions are wanted, so partition around them
+            let wanted = count - entries.len();
+            let mut next = None;
+            if pending.len() > wanted {
+                pending.select_nth_unstable_by_key(wanted - 1, |(position, _)| *position);
+                let last = pending[wanted - 1].0;
+                let rest = pending.split_off(wanted);
+
+                // Never split keys at the same position between calls
+                let (ties, rest): (Vec<_>, Vec<_>) = rest.into_iter().partition(|(position, _)| *position == last);
+                pending.extend(ties);
+                next = rest.iter().map(|(position, _)| *position).min();
+            }
+            entries.extend(pending.into_iter().map(|(_, entry)| entry));
+
+            if let Some(next) = next {
+                return (next * NUM_SHARDS as u64 + shard as u64, entries);
+            }
+            shard += 1;
+            from = 0;
+        }
+        (if shard >= NUM_SHARDS { 0 } else { shard as u64 }, entries)
+    }
+}
+
+impl<V> Default for Dict<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
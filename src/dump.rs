@@ -0,0 +1,359 @@
+use std::collections::HashMap;
+
+use crate::{RedisValue, StreamEntry, hash::RedisHash, set::RedisSet, zset::SortedSet};
+
+// Bumped whenever the encoding changes; RESTORE refuses payloads from other versions
+const DUMP_VERSION: u16 = 1;
+
+// Reflected form of the CRC-64/Jones polynomial Redis checksums its payloads with
+const CRC64_POLY: u64 = 0x95ac_9329_ac4b_c9b5;
+
+const TYPE_STRING: u8 = 0;
+const TYPE_LIST: u8 = 1;
+const TYPE_STREAM: u8 = 2;
+const TYPE_HASH: u8 = 3;
+const TYPE_HASH_WITH_TTLS: u8 = 4; // Then how many fields have a TTL, and each one's field and expiry time
+const TYPE_SET: u8 = 5;
+const TYPE_ZSET: u8 = 6;
+
+#[derive(Debug)]
+pub enum RestoreError {
+    Checksum, // Wrong version or corrupted
+    Format,
+}
+
+fn crc64(data: &[u8]) -> u64 {
+    let mut crc = 0u64;
+    for &byte in data {
+        crc ^= byte as u64;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ CRC64_POLY } else { crc >> 1 };
+        }
+    }
+    crc
+}
+
+fn put_str(out: &mut Vec<u8>, s: &str) {
+    out.extend_from_slice(&(s.len() as u64).to_le_bytes());
+    out.extend_from_slice(s.as_bytes());
+}
+
+/// Serializes `value` as the payload DUMP replies with: the encoded value followed by
+/// the format version and a checksum, hex-encoded so it travels as a plain string.
+/// None for types that can't be dumped yet.
+pub fn dump(value: &RedisValue) -> Option<String> {
+    let mut out = Vec::new();
+    match value {
+        RedisValue::String(s) => {
+            out.push(TYPE_STRING);
+            put_str(&mut out, s);
+        }
+        RedisValue::List(list) => {
+            out.push(TYPE_LIST);
+            out.extend_from_slice(&(list.len() as u64).to_le_bytes());
+            list.iter().for_each(|item| put_str(&mut out, item));
+        }
+        RedisValue::Hash(hash) => {
+            let expiring = hash.expiring();
+            out.push(if expiring.len() == 0 { TYPE_HASH } else { TYPE_HASH_WITH_TTLS });
+            out.extend_from_slice(&(hash.len() as u64).to_le_bytes());
+            for (field, value) in hash.iter() {
+                put_str(&mut out, field);
+                put_str(&mut out, value);
+            }
+            if expiring.len() > 0 {
+                out.extend_from_slice(&(expiring.len() as u64).to_le_bytes());
+                for (field, at) in expiring {
+                    put_str(&mut out, field);
+                    out.extend_from_slice(&at.to_le_bytes());
+                }
+            }
+        }
+        RedisValue::Set(set) => {
+            out.push(TYPE_SET);
+            out.extend_from_slice(&(set.len() as u64).to_le_bytes());
+            set.iter().for_each(|member| put_str(&mut out, &member));
+        }
+        RedisValue::SortedSet(zset) => {
+            out.push(TYPE_ZSET);
+            out.extend_from_slice(&(zset.len() as u64).to_le_bytes());
+            for (member, score) in zset.iter() {
+                put_str(&mut out, member);
+                out.extend_from_slice(&score.to_le_bytes());
+            }
+        }
+        RedisValue::Stream(entries) => {
+            out.push(TYPE_STREAM);
+            out.extend_from_slice(&(entries.len() as u64).to_le_bytes());
+            for entry in entries {
+                out.extend_from_slice(&entry.id_ms.to_le_bytes());
+                out.extend_from_slice(&entry.id_seq.to_le_bytes());
+                out.extend_from_slice(&(entry.fields.len() as u64).to_le_bytes());
+                for (field, value) in &entry.fields {
+                    put_str(&mut out, field);
+                    put_str(&mut out, value);
+                }
+            }
+        }
+        _ => return None,
+    }
+
+    out.extend_from_slice(&DUMP_VERSION.to_le_bytes());
+    let checksum = crc64(&out);
+    out.extend_from_slice(&checksum.to_le_bytes());
+    Some(out.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+// Reads fields back out of a payload, failing on anything truncated or malformed
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn bytes(&mut self, len: usize) -> Result<&[u8], RestoreError> {
+        if len > self.data.len() {
+            return Err(RestoreError::Format);
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, RestoreError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u64(&mut self) -> Result<u64, RestoreError> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    fn len(&mut self) -> Result<usize, RestoreError> {
+        // Never more items than bytes left, so a corrupt length can't trigger a huge allocation
+        usize::try_from(self.u64()?).ok().filter(|&len| len <= self.data.len()).ok_or(RestoreError::Format)
+    }
+
+    fn string(&mut self) -> Result<String, RestoreError> {
+        let len = self.len()?;
+        String::from_utf8(self.bytes(len)?.to_vec()).map_err(|_| RestoreError::Format)
+    }
+}
+
+/// Rebuilds a value from a DUMP payload.
+pub fn restore(payload: &str) -> Result<RedisValue, RestoreError> {
+    let data = (0..payload.len())
+        .step_by(2)
+        .map(|i| payload.get(i..i + 2).and_then(|hex| u8::from_str_radix(hex, 16).ok()))
+        .collect::<Option<Vec<u8>>>()
+        .ok_or(RestoreError::Checksum)?;
+    if data.len() < 10 {
+        return Err(RestoreError::Checksum);
+    }
+
+    let (body, checksum) = data.split_at(data.len() - 8);
+    let (encoded, version) = body.split_at(body.len() - 2);
+    if u16::from_le_bytes(version.try_into().unwrap()) != DUMP_VERSION
+        || u64::from_le_bytes(checksum.try_into().unwrap()) != crc64(body)
+    {
+        return Err(RestoreError::Checksum);
+    }
+
+    let mut reader = Reader { data: encoded };
+    let value = match reader.u8()? {
+        TYPE_STRING => RedisValue::String(reader.string()?),
+        TYPE_LIST => {
+            let len = reader.len()?;
+            RedisValue::List((0..len).map(|_| reader.string()).collect::<Result<_, _>>()?)
+        }
+        kind @ (TYPE_HASH | TYPE_HASH_WITH_TTLS) => {
+            let len = reader.len()?;
+            let mut hash = RedisHash::with_capacity(len);
+            for _ in 0..len {
+                hash.insert(reader.string()?, reader.string()?);
+            }
+            if kind == TYPE_HASH_WITH_TTLS {
+                let expiring = reader.len()?;
+                for _ in 0..expiring {
+                    let field = reader.string()?;
+                    let at = reader.u64()? as i64;
+                    if !hash.contains_key(&field) {
+                        return Err(RestoreError::Format);
+                    }
+                    hash.set_expires_at(&field, at);
+                }
+            }
+            RedisValue::Hash(hash)
+        }
+        TYPE_SET => {
+            let len = reader.len()?;
+            RedisValue::Set((0..len).map(|_| reader.string()).collect::<Result<RedisSet, _>>()?)
+        }
+        TYPE_ZSET => {
+            let len = reader.len()?;
+            let mut zset = SortedSet::new();
+            for _ in 0..len {
+                let member = reader.string()?;
+                let score = f64::from_bits(reader.u64()?);
+                if score.is_nan() {
+                    return Err(RestoreError::Format);
+                }
+                zset.insert(member, score);
+            }
+            RedisValue::SortedSet(zset)
+        }
+        TYPE_STREAM => {
+            let len = reader.len()?;
+            let mut entries = Vec::with_capacity(len);
+            for _ in 0..len {
+                let id_ms = reader.u64()?;
+                let id_seq = reader.u64()?;
+                let num_fields = reader.len()?;
+                let mut fields = HashMap::with_capacity(num_fields);
+                for _ in 0..num_fields {
+                    fields.insert(reader.string()?, reader.string()?);
+                }
+                entries.push(StreamEntry { id_ms, id_seq, fields });
+            }
+            RedisValue::Stream(entries)
+        }
+        _ => return Err(RestoreError::Format),
+    };
+    if !reader.data.is_empty() {
+        return Err(RestoreError::Format);
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    fn round_trip(value: &RedisValue) -> RedisValue {
+        restore(&dump(value).unwrap()).unwrap()
+    }
+
+    // A payload for `encoded` with a valid version and checksum, to get past them
+    fn sealed(encoded: &[u8]) -> String {
+        let mut out = encoded.to_vec();
+        out.extend_from_slice(&DUMP_VERSION.to_le_bytes());
+        let checksum = crc64(&out);
+        out.extend_from_slice(&checksum.to_le_bytes());
+        out.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    #[test]
+    fn crc64_check_value() {
+        // The standard check input for CRC-64/Jones, the variant Redis uses
+        assert_eq!(crc64(b"123456789"), 0xe9c6_d914_c4b8_d9ca);
+    }
+
+    #[test]
+    fn string_and_list_round_trip() {
+        let RedisValue::String(s) = round_trip(&RedisValue::String(String::from("héllo"))) else { panic!() };
+        assert_eq!(s, "héllo");
+
+        let list: VecDeque<String> = ["a", "", "c"].iter().map(|s| s.to_string()).collect();
+        let RedisValue::List(restored) = round_trip(&RedisValue::List(list.clone())) else { panic!() };
+        assert_eq!(restored, list);
+    }
+
+    #[test]
+    fn hash_round_trips_with_field_ttls() {
+        let mut hash = RedisHash::new();
+        hash.insert(String::from("a"), String::from("1"));
+        hash.insert(String::from("b"), String::from("2"));
+        hash.set_expires_at("b", 1_700_000_000_000);
+
+        let RedisValue::Hash(restored) = round_trip(&RedisValue::Hash(hash)) else { panic!() };
+        assert_eq!(restored.len(), 2);
+        assert_eq!(restored.get("a").map(String::as_str), Some("1"));
+        assert_eq!(restored.get("b").map(String::as_str), Some("2"));
+        assert_eq!(restored.expires_at("a"), None);
+        assert_eq!(restored.expires_at("b"), Some(1_700_000_000_000));
+    }
+
+    #[test]
+    fn set_and_zset_round_trip() {
+        let set: RedisSet = ["1", "2", "x"].iter().map(|s| s.to_string()).collect();
+        let RedisValue::Set(restored) = round_trip(&RedisValue::Set(set)) else { panic!() };
+        assert_eq!(restored.len(), 3);
+        assert!(["1", "2", "x"].iter().all(|member| restored.contains(member)));
+
+        let mut zset = SortedSet::new();
+        zset.insert(String::from("b"), 2.5);
+        zset.insert(String::from("a"), -1.0);
+        let RedisValue::SortedSet(restored) = round_trip(&RedisValue::SortedSet(zset)) else { panic!() };
+        let members: Vec<(&String, f64)> = restored.iter().collect();
+        assert_eq!(members, vec![(&String::from("a"), -1.0), (&String::from("b"), 2.5)]);
+    }
+
+    #[test]
+    fn stream_round_trips() {
+        let entry = StreamEntry { id_ms: 5, id_seq: 1, fields: HashMap::from([(String::from("f"), String::from("v"))]) };
+        let RedisValue::Stream(restored) = round_trip(&RedisValue::Stream(vec![entry])) else { panic!() };
+        assert_eq!(restored.len(), 1);
+        assert_eq!((restored[0].id_ms, restored[0].id_seq), (5, 1));
+        assert_eq!(restored[0].fields.get("f").map(String::as_str), Some("v"));
+    }
+
+    #[test]
+    fn restore_rejects_corruption() {
+        let payload = dump(&RedisValue::String(String::from("value"))).unwrap();
+
+        // Flip one bit of the encoded value
+        let mut bytes = payload.clone().into_bytes();
+        bytes[4] = if bytes[4] == b'0' { b'1' } else { b'0' };
+        assert!(matches!(restore(&String::from_utf8(bytes).unwrap()), Err(RestoreError::Checksum)));
+
+        assert!(matches!(restore(&payload[..payload.len() - 2]), Err(RestoreError::Checksum)));
+        assert!(matches!(restore("not hex at all"), Err(RestoreError::Checksum)));
+        assert!(matches!(restore(""), Err(RestoreError::Checksum)));
+    }
+
+    #[test]
+    fn restore_rejects_a_different_version() {
+        let mut out = vec![TYPE_STRING];
+        out.extend_from_slice(&0u64.to_le_bytes());
+        out.extend_from_slice(&(DUMP_VERSION + 1).to_le_bytes());
+        let checksum = crc64(&out);
+        out.extend_from_slice(&checksum.to_le_bytes());
+        let payload: String = out.iter().map(|byte| format!("{:02x}", byte)).collect();
+        assert!(matches!(restore(&payload), Err(RestoreError::Checksum)));
+    }
+
+    #[test]
+    fn restore_rejects_malformed_bodies() {
+        // Unknown type, a length running past the end, a huge count, and trailing bytes
+        assert!(matches!(restore(&sealed(&[99])), Err(RestoreError::Format)));
+
+        let mut truncated = vec![TYPE_STRING];
+        truncated.extend_from_slice(&10u64.to_le_bytes());
+        truncated.extend_from_slice(b"abc");
+        assert!(matches!(restore(&sealed(&truncated)), Err(RestoreError::Format)));
+
+        let mut huge = vec![TYPE_LIST];
+        huge.extend_from_slice(&u64::MAX.to_le_bytes());
+        assert!(matches!(restore(&sealed(&huge)), Err(RestoreError::Format)));
+
+        let mut trailing = vec![TYPE_STRING];
+        trailing.extend_from_slice(&1u64.to_le_bytes());
+        trailing.extend_from_slice(b"ab");
+        assert!(matches!(restore(&sealed(&trailing)), Err(RestoreError::Format)));
+
+        // A NaN score and a TTL for a field the hash doesn't have
+        let mut nan = vec![TYPE_ZSET];
+        nan.extend_from_slice(&1u64.to_le_bytes());
+        nan.extend_from_slice(&1u64.to_le_bytes());
+        nan.push(b'm');
+        nan.extend_from_slice(&f64::NAN.to_bits().to_le_bytes());
+        assert!(matches!(restore(&sealed(&nan)), Err(RestoreError::Format)));
+
+        let mut ttl = vec![TYPE_HASH_WITH_TTLS];
+        ttl.extend_from_slice(&0u64.to_le_bytes());
+        ttl.extend_from_slice(&1u64.to_le_bytes());
+        ttl.extend_from_slice(&1u64.to_le_bytes());
+        ttl.push(b'f');
+        ttl.extend_from_slice(&0u64.to_le_bytes());
+        assert!(matches!(restore(&sealed(&ttl)), Err(RestoreError::Format)));
+    }
+}
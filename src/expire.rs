@@ -0,0 +1,73 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::{Db, lazyfree, unix_time_ms};
+
+// How often the cycle runs, Redis' default hz of 10
+const CYCLE_INTERVAL: Duration = Duration::from_millis(100);
+
+// Keys checked per pass, and how long one cycle may keep going pass after pass. The
+// lock is only held for a single pass, so clients get in between. Finding a pass's keys
+// means hashing the rest of each shard it walks, so a pass costs KEYS_PER_PASS checks
+// plus up to one shard's worth of hashing, about 1/1024 of the keyspace.
+const KEYS_PER_PASS: usize = 20;
+const CYCLE_TIME_LIMIT: Duration = Duration::from_millis(25);
+
+// Another pass runs straight away while more than this share of the keys with a
+// TTL turned out to be expired, since there are probably plenty more
+const ACCEPTABLE_STALE_PERCENT: usize = 10;
+
+static EXPIRED: AtomicU64 = AtomicU64::new(0);
+
+/// Counts keys deleted because their TTL ran out, whichever path found them.
+pub fn record_expired(count: usize) {
+    EXPIRED.fetch_add(count as u64, Ordering::Relaxed);
+}
+
+/// Starts the background thread that reclaims expired keys nobody reads again.
+/// It walks the keyspace KEYS_PER_PASS keys at a time, resuming where it left off.
+pub fn start(db: Db) {
+    std::thread::spawn(move || {
+        let mut cursor = 0;
+        loop {
+            std::thread::sleep(CYCLE_INTERVAL);
+            cycle(&db, &mut cursor);
+        }
+    });
+}
+
+fn cycle(db: &Db, cursor: &mut u64) {
+    let started = Instant::now();
+
+    loop {
+        let mut map = db.lock().unwrap();
+
+        let now = unix_time_ms();
+        let (next, entries) = map.scan(*cursor, KEYS_PER_PASS);
+        let volatile = entries.iter().filter(|(_, entry)| entry.expires_at.is_some()).count();
+        let expired: Vec<String> = entries
+            .into_iter()
+            .filter(|(_, entry)| entry.expires_at.is_some_and(|at| at < now))
+            .map(|(key, _)| key.clone())
+            .collect();
+        let removed: Vec<_> = expired.iter().filter_map(|key| map.remove(key)).collect();
+        drop(map);
+
+        *cursor = next;
+        record_expired(removed.len());
+        for entry in removed {
+            let effort = entry.value.free_effort();
+            lazyfree::free(entry, effort);
+        }
+
+        // A cycle never wraps around; the next one picks up from the first shard
+        let mostly_fresh = expired.len() * 100 <= volatile * ACCEPTABLE_STALE_PERCENT;
+        if mostly_fresh || next == 0 || started.elapsed() > CYCLE_TIME_LIMIT {
+            break;
+        }
+    }
+}
+
+pub fn info_fields() -> Vec<(&'static str, String)> {
+    vec![("expired_keys", EXPIRED.load(Ordering::Relaxed).to_string())]
+}
@@ -0,0 +1,254 @@
+use crate::config;
+
+// Same cap Redis puts on the number of arguments in one command
+const MAX_MULTIBULK_LEN: i64 = 1024 * 1024;
+
+// Longest inline command, or '*'/'$' header line, Redis accepts before giving up on
+// finding its end
+const MAX_INLINE_LEN: usize = 64 * 1024;
+
+fn parse_number(digits: &[u8]) -> Option<i64> {
+    std::str::from_utf8(digits).ok()?.parse().ok()
+}
+
+// Returns the length of the RESP array frame at the start of `data`,
+// Ok(None) if the frame is incomplete, or Err on malformed input.
+// Error messages match the ones Redis sends after "Protocol error: ".
+pub fn frame_len(data: &[u8]) -> Result<Option<usize>, String> {
+    // Only the first MAX_INLINE_LEN bytes are searched, so a header that never ends
+    // can't make each new read rescan everything buffered so far
+    let read_line = |pos: usize, too_big: &str| -> Result<Option<(&[u8], usize)>, String> {
+        let window = &data[pos..data.len().min(pos + MAX_INLINE_LEN + 2)];
+        match window.windows(2).position(|w| w == b"\r\n") {
+            Some(end) => Ok(Some((&data[pos..pos + end], pos + end + 2))),
+            None if data.len() - pos > MAX_INLINE_LEN => Err(too_big.to_string()),
+            None => Ok(None),
+        }
+    };
+
+    let Some((line, mut pos)) = read_line(0, "too big mbulk count string")? else {
+        return Ok(None);
+    };
+    let count = match line.split_first() {
+        Some((b'*', digits)) => match parse_number(digits) {
+            Some(count) if count <= MAX_MULTIBULK_LEN => count.max(0), // Redis skips empty and negative counts
+            _ => return Err(String::from("invalid multibulk length")),
+        },
+        _ => return Err(format!("expected '*', got '{}'", String::from_utf8_lossy(&line[..line.len().min(1)]))),
+    };
+
+    for _ in 0..count {
+        let Some((line, next)) = read_line(pos, "too big bulk count string")? else {
+            return Ok(None);
+        };
+        let len = match line.split_first() {
+            Some((b'$', digits)) => match parse_number(digits) {
+                Some(len) if len >= 0 && len as usize <= config::proto_max_bulk_len() => len as usize,
+                _ => return Err(String::from("invalid bulk length")),
+            },
+            _ => return Err(format!("expected '$', got '{}'", String::from_utf8_lossy(&line[..line.len().min(1)]))),
+        };
+        pos = next + len + 2;
+        if pos > data.len() {
+            return Ok(None);
+        }
+        if &data[pos - 2..pos] != b"\r\n" {
+            return Err(String::from("bulk string not terminated by CRLF"));
+        }
+    }
+    Ok(Some(pos))
+}
+
+/// Splits an inline command line into arguments the way redis-cli and Redis do:
+/// whitespace separates arguments, double quotes allow \n, \r, \t, \b, \a and \xHH
+/// escapes, single quotes only allow \'. None if the quotes don't balance.
+pub fn split_args(line: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let hex_value = |b: u8| (b as char).to_digit(16).map(|d| d as u8);
+    let mut args = Vec::new();
+    let mut i = 0;
+
+    loop {
+        while line.get(i).is_some_and(|b| b.is_ascii_whitespace()) {
+            i += 1;
+        }
+        let Some(&first) = line.get(i) else {
+            return Some(args);
+        };
+
+        let mut arg = Vec::new();
+        if first == b'"' || first == b'\'' {
+            i += 1;
+            loop {
+                match (*line.get(i)?, first) {
+                    (b'"', b'"') | (b'\'', b'\'') => break,
+                    (b'\\', b'"') => {
+                        let escaped = *line.get(i + 1)?;
+                        let hex = line.get(i + 2).and_then(|&b| hex_value(b)).zip(line.get(i + 3).and_then(|&b| hex_value(b)));
+                        match (escaped, hex) {
+                            (b'x', Some((high, low))) => {
+                                arg.push(high << 4 | low);
+                                i += 2;
+                            }
+                            (b'n', _) => arg.push(b'\n'),
+                            (b'r', _) => arg.push(b'\r'),
+                            (b't', _) => arg.push(b'\t'),
+                            (b'b', _) => arg.push(0x08),
+                            (b'a', _) => arg.push(0x07),
+                            (other, _) => arg.push(other),
+                        }
+                        i += 1;
+                    }
+                    (b'\\', b'\'') if line.get(i + 1) == Some(&b'\'') => {
+                        arg.push(b'\'');
+                        i += 1;
+                    }
+                    (byte, _) => arg.push(byte),
+                }
+                i += 1;
+            }
+            i += 1;
+            // A closing quote must end the argument
+            if line.get(i).is_some_and(|b| !b.is_ascii_whitespace()) {
+                return None;
+            }
+        } else {
+            while let Some(&byte) = line.get(i).filter(|b| !b.is_ascii_whitespace()) {
+                arg.push(byte);
+                i += 1;
+            }
+        }
+        args.push(arg);
+    }
+}
+
+// The bulk strings of a frame frame_len has already validated
+fn frame_args(frame: &[u8]) -> Vec<String> {
+    let line_end = |pos: usize| pos + frame[pos..].windows(2).position(|w| w == b"\r\n").unwrap();
+
+    let mut args = Vec::new();
+    let mut pos = line_end(0) + 2;
+    while pos < frame.len() {
+        let end = line_end(pos);
+        let len = parse_number(&frame[pos + 1..end]).unwrap() as usize;
+        args.push(String::from_utf8_lossy(&frame[end + 2..end + 2 + len]).into_owned());
+        pos = end + 2 + len + 2;
+    }
+    args
+}
+
+/// Finds the next complete request at the start of `data`, returning how many bytes it
+/// used and its arguments, command name first. Anything not starting with '*' is an
+/// inline command: one line, split like redis-cli does.
+pub fn next_request(data: &[u8]) -> Result<Option<(usize, Vec<String>)>, String> {
+    if data.first().is_none_or(|&b| b == b'*') {
+        return Ok(frame_len(data)?.map(|len| (len, frame_args(&data[..len]))));
+    }
+
+    let Some(end) = data.iter().position(|&b| b == b'\n') else {
+        if data.len() > MAX_INLINE_LEN {
+            return Err(String::from("too big inline request"));
+        }
+        return Ok(None);
+    };
+    let line = data[..end].strip_suffix(b"\r").unwrap_or(&data[..end]);
+    let args = split_args(line).ok_or("unbalanced quotes in request")?;
+    Ok(Some((end + 1, args.iter().map(|arg| String::from_utf8_lossy(arg).into_owned()).collect())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_len_of_a_complete_frame() {
+        let frame = b"*2\r\n$3\r\nGET\r\n$3\r\nkey\r\n";
+        assert_eq!(frame_len(frame), Ok(Some(frame.len())));
+
+        // Whatever follows the frame is left alone
+        let mut data = frame.to_vec();
+        data.extend_from_slice(b"*1\r\n$4\r\nPI");
+        assert_eq!(frame_len(&data), Ok(Some(frame.len())));
+    }
+
+    #[test]
+    fn frame_len_waits_for_partial_frames() {
+        let frame = b"*2\r\n$3\r\nGET\r\n$3\r\nkey\r\n";
+        for end in 0..frame.len() {
+            assert_eq!(frame_len(&frame[..end]), Ok(None), "prefix of {} bytes", end);
+        }
+    }
+
+    #[test]
+    fn frame_len_skips_empty_and_negative_counts() {
+        assert_eq!(frame_len(b"*0\r\n"), Ok(Some(4)));
+        assert_eq!(frame_len(b"*-1\r\n"), Ok(Some(5)));
+    }
+
+    #[test]
+    fn frame_len_rejects_bad_lengths() {
+        assert_eq!(frame_len(b"*1\r\n$-1\r\n"), Err(String::from("invalid bulk length")));
+        assert_eq!(frame_len(b"*1\r\n$abc\r\n"), Err(String::from("invalid bulk length")));
+        assert_eq!(frame_len(b"*1\r\n$9999999999999\r\n"), Err(String::from("invalid bulk length")));
+        assert_eq!(frame_len(b"*9999999999\r\n"), Err(String::from("invalid multibulk length")));
+        assert_eq!(frame_len(b"*x\r\n"), Err(String::from("invalid multibulk length")));
+    }
+
+    #[test]
+    fn frame_len_rejects_malformed_frames() {
+        assert_eq!(frame_len(b"*1\r\n+OK\r\n"), Err(String::from("expected '$', got '+'")));
+        assert_eq!(frame_len(b"*1\r\n$2\r\nabcd\r\n"), Err(String::from("bulk string not terminated by CRLF")));
+    }
+
+    #[test]
+    fn frame_len_rejects_oversized_headers() {
+        let mut data = b"*".to_vec();
+        data.resize(MAX_INLINE_LEN + 10, b'1');
+        assert_eq!(frame_len(&data), Err(String::from("too big mbulk count string")));
+
+        let mut data = b"*1\r\n$".to_vec();
+        data.resize(MAX_INLINE_LEN + 10, b'1');
+        assert_eq!(frame_len(&data), Err(String::from("too big bulk count string")));
+
+        // Up to the limit it's just incomplete
+        let mut data = b"*".to_vec();
+        data.resize(MAX_INLINE_LEN, b'1');
+        assert_eq!(frame_len(&data), Ok(None));
+    }
+
+    fn split(line: &str) -> Option<Vec<String>> {
+        split_args(line.as_bytes()).map(|args| args.into_iter().map(|arg| String::from_utf8(arg).unwrap()).collect())
+    }
+
+    #[test]
+    fn split_args_on_whitespace() {
+        assert_eq!(split("  SET  key\tvalue "), Some(vec![String::from("SET"), String::from("key"), String::from("value")]));
+        assert_eq!(split(""), Some(Vec::new()));
+    }
+
+    #[test]
+    fn split_args_with_quotes() {
+        assert_eq!(split(r#"SET "a b" 'c d'"#), Some(vec![String::from("SET"), String::from("a b"), String::from("c d")]));
+        assert_eq!(split(r#""\x41\n\t\"" 'it\'s'"#), Some(vec![String::from("A\n\t\""), String::from("it's")]));
+        assert_eq!(split(r#""""#), Some(vec![String::new()]));
+    }
+
+    #[test]
+    fn split_args_rejects_unbalanced_quotes() {
+        assert_eq!(split(r#"SET "key"#), None);
+        assert_eq!(split("SET 'key"), None);
+        assert_eq!(split(r#"SET "key"value"#), None);
+    }
+
+    #[test]
+    fn next_request_inline_and_framed() {
+        assert_eq!(
+            next_request(b"SET k v\r\nGET k\r\n"),
+            Ok(Some((9, vec![String::from("SET"), String::from("k"), String::from("v")])))
+        );
+        assert_eq!(next_request(b"*1\r\n$4\r\nPING\r\n"), Ok(Some((14, vec![String::from("PING")]))));
+        assert_eq!(next_request(b"PING"), Ok(None));
+
+        let long = vec![b'a'; MAX_INLINE_LEN + 1];
+        assert_eq!(next_request(&long), Err(String::from("too big inline request")));
+    }
+}
@@ -0,0 +1,139 @@
+/// Matches `string` against a Redis glob pattern: `*` matches any run of bytes, `?` any
+/// single byte, `[abc]`, `[^abc]` and `[a-z]` a byte from (or not from) a set, and `\`
+/// makes the next character literal.
+pub fn matches(pattern: &[u8], string: &[u8]) -> bool {
+    let (mut p, mut s) = (0, 0);
+    // Where to pick up after the latest star: the pattern just past it, and how far
+    // into the string it reaches. Only the latest star ever needs to give ground, so
+    // this runs in O(pattern * string) without recursing.
+    let mut star = None;
+
+    while p < pattern.len() || s < string.len() {
+        if p < pattern.len() {
+            match pattern[p] {
+                b'*' => {
+                    // Let the star match nothing for now; each mismatch makes it take one more byte
+                    p += 1;
+                    star = Some((p, s));
+                    continue;
+                }
+                b'?' if s < string.len() => {
+                    p += 1;
+                    s += 1;
+                    continue;
+                }
+                b'[' if s < string.len() => {
+                    let (matched, end) = match_class(pattern, p + 1, string[s]);
+                    if matched {
+                        p = end + 1;
+                        s += 1;
+                        continue;
+                    }
+                }
+                literal => {
+                    // A trailing backslash is matched literally
+                    let (literal, width) = if literal == b'\\' && p + 1 < pattern.len() {
+                        (pattern[p + 1], 2)
+                    } else {
+                        (literal, 1)
+                    };
+                    if string.get(s) == Some(&literal) {
+                        p += width;
+                        s += 1;
+                        continue;
+                    }
+                }
+            }
+        }
+
+        match star {
+            Some((after_star, reach)) if reach < string.len() => {
+                star = Some((after_star, reach + 1));
+                p = after_star;
+                s = reach + 1;
+            }
+            _ => return false,
+        }
+    }
+    true
+}
+
+// Checks `byte` against the class starting at `pattern[start]` (just past the '['),
+// returning whether it matched and the index of the closing ']'. An unterminated
+// class runs to the end of the pattern.
+fn match_class(pattern: &[u8], start: usize, byte: u8) -> (bool, usize) {
+    let mut p = start;
+    let negate = pattern.get(p) == Some(&b'^');
+    if negate {
+        p += 1;
+    }
+
+    let mut matched = false;
+    while p < pattern.len() && pattern[p] != b']' {
+        if pattern[p] == b'\\' && p + 1 < pattern.len() {
+            p += 1;
+            matched |= pattern[p] == byte;
+        } else if pattern.get(p + 1) == Some(&b'-') && p + 2 < pattern.len() {
+            // Ranges work either way round
+            let (low, high) = (pattern[p].min(pattern[p + 2]), pattern[p].max(pattern[p + 2]));
+            matched |= (low..=high).contains(&byte);
+            p += 2;
+        } else {
+            matched |= pattern[p] == byte;
+        }
+        p += 1;
+    }
+
+    (matched != negate, p.min(pattern.len() - 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn glob(pattern: &str, string: &str) -> bool {
+        matches(pattern.as_bytes(), string.as_bytes())
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(glob("h?llo", "hello"));
+        assert!(!glob("h?llo", "hllo"));
+        assert!(glob("h*llo", "hllo"));
+        assert!(glob("h*llo", "heeeello"));
+        assert!(glob("*", ""));
+        assert!(glob("**a**", "bab"));
+        assert!(!glob("*a", "ab"));
+        assert!(glob("*e*l*o", "hello"));
+        assert!(!glob("", "a"));
+    }
+
+    #[test]
+    fn classes() {
+        assert!(glob("h[ae]llo", "hallo"));
+        assert!(!glob("h[ae]llo", "hillo"));
+        assert!(glob("h[^e]llo", "hallo"));
+        assert!(!glob("h[^e]llo", "hello"));
+        assert!(glob("h[a-c]llo", "hbllo"));
+        assert!(glob("h[c-a]llo", "hbllo"));
+        assert!(glob("[\\]]", "]"));
+        assert!(glob("a[bc", "ab"), "an unterminated class runs to the end");
+    }
+
+    #[test]
+    fn escapes() {
+        assert!(glob("h\\*llo", "h*llo"));
+        assert!(!glob("h\\*llo", "hello"));
+        assert!(glob("x\\[y", "x[y"));
+        assert!(glob("ab\\", "ab\\"), "a trailing backslash is literal");
+    }
+
+    #[test]
+    fn many_stars_stay_fast() {
+        // Recursive matching takes exponential time on this
+        let pattern = "a*".repeat(30) + "b";
+        let string = "a".repeat(100);
+        assert!(!glob(&pattern, &string));
+        assert!(glob(&"a*".repeat(30), &string));
+    }
+}
@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+use std::collections::hash_map::{Iter, Keys, Values};
+
+use crate::scan_order::ScanOrder;
+
+/// A hash's fields, plus when the ones given a TTL by HEXPIRE and friends expire.
+/// Expired fields stay put until `expire_fields` drops them, so callers purge first.
+#[derive(Debug, Default)]
+pub struct RedisHash {
+    fields: HashMap<String, String>,
+    expires_at: HashMap<String, i64>, // Unix time in milliseconds
+    order: ScanOrder,
+}
+
+impl RedisHash {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        RedisHash { fields: HashMap::with_capacity(capacity), ..Self::default() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.fields.capacity()
+    }
+
+    pub fn get(&self, field: &str) -> Option<&String> {
+        self.fields.get(field)
+    }
+
+    pub fn contains_key(&self, field: &str) -> bool {
+        self.fields.contains_key(field)
+    }
+
+    pub fn iter(&self) -> Iter<'_, String, String> {
+        self.fields.iter()
+    }
+
+    pub fn keys(&self) -> Keys<'_, String, String> {
+        self.fields.keys()
+    }
+
+    pub fn values(&self) -> Values<'_, String, String> {
+        self.fields.values()
+    }
+
+    /// Sets a field, returning its old value. As with HSET, the field loses any TTL.
+    pub fn insert(&mut self, field: String, value: String) -> Option<String> {
+        self.expires_at.remove(&field);
+        self.update(field, value)
+    }
+
+    /// Sets a field but keeps its TTL, the way HINCRBY changes a value in place.
+    pub fn update(&mut self, field: String, value: String) -> Option<String> {
+        if !self.fields.contains_key(&field) {
+            self.order.insert(&field);
+        }
+        self.fields.insert(field, value)
+    }
+
+    pub fn remove(&mut self, field: &str) -> Option<String> {
+        self.expires_at.remove(field);
+        let value = self.fields.remove(field)?;
+        self.order.remove(field);
+        Some(value)
+    }
+
+    /// One HSCAN page: fields and values from `cursor` on, and the cursor to continue from.
+    pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<(&str, &String)>) {
+        let (next, fields) = self.order.scan(cursor, count);
+        (next, fields.into_iter().map(|field| (field, &self.fields[field])).collect())
+    }
+
+    /// When `field` expires, None if it has no TTL (or doesn't exist).
+    pub fn expires_at(&self, field: &str) -> Option<i64> {
+        self.expires_at.get(field).copied()
+    }
+
+    /// Gives `field` a TTL ending at `at`, if the field exists.
+    pub fn set_expires_at(&mut self, field: &str, at: i64) {
+        if self.fields.contains_key(field) {
+            self.expires_at.insert(field.to_string(), at);
+        }
+    }
+
+    /// Takes away `field`'s TTL. Returns whether it had one.
+    pub fn persist(&mut self, field: &str) -> bool {
+        self.expires_at.remove(field).is_some()
+    }
+
+    /// The fields with a TTL, and when they expire.
+    pub fn expiring(&self) -> Iter<'_, String, i64> {
+        self.expires_at.iter()
+    }
+
+    /// Drops the fields whose TTL ran out before `now`, returning how many went.
+    pub fn expire_fields(&mut self, now: i64) -> usize {
+        if self.expires_at.is_empty() {
+            return 0;
+        }
+        let before = self.fields.len();
+        let (fields, order) = (&mut self.fields, &mut self.order);
+        self.expires_at.retain(|field, at| {
+            let alive = *at >= now;
+            if !alive {
+                fields.remove(field);
+                order.remove(field);
+            }
+            alive
+        });
+        before - self.fields.len()
+    }
+}
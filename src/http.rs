@@ -0,0 +1,438 @@
+use std::io::{Cursor, Read, Result as IoResult, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+
+use crate::{Clients, Blocked, Db, commands, handle_connection, peer_addr};
+
+// Commands POST /command won't run, out of everything in the command table.
+// Blocking and connection-level commands make no sense since every HTTP
+// request runs on a throwaway session.
+const BLOCKED_COMMANDS: &[&str] = &[
+    "blpop", "brpop", "blmpop", "blmove", "brpoplpush", "xread", "hello", "client", "readonly", "readwrite",
+];
+
+// Requests larger than this are rejected before the body is read
+const MAX_BODY_LEN: usize = 1024 * 1024;
+
+struct Request {
+    method: String,
+    path: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Response {
+    fn json(status: &'static str, body: String) -> Self {
+        Response { status, content_type: "application/json", body }
+    }
+
+    fn error(status: &'static str, message: &str) -> Self {
+        Response::json(status, format!("{{\"error\":{}}}", json_string(message)))
+    }
+}
+
+// Feeds one pre-encoded command into handle_connection and captures the reply
+struct MemoryStream {
+    input: Cursor<Vec<u8>>,
+    output: Vec<u8>,
+}
+
+impl Read for MemoryStream {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        self.input.read(buf)
+    }
+}
+
+impl Write for MemoryStream {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        self.output.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        Ok(())
+    }
+}
+
+/// Answers HTTP requests on `listener`. Each must carry `Authorization: Bearer <token>`;
+/// with no token configured every request is turned away.
+pub fn serve(listener: TcpListener, token: Option<String>, db: Db, blocked: Blocked, clients: Clients) {
+    let token: Option<Arc<str>> = token.map(Arc::from);
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let token_clone = token.clone();
+                let db_clone = Arc::clone(&db);
+                let blocked_clone = Arc::clone(&blocked);
+                let clients_clone = Arc::clone(&clients);
+                std::thread::spawn(move || {
+                    if let Err(e) = handle_http(stream, token_clone, db_clone, blocked_clone, clients_clone) {
+                        println!("http error: {}", e);
+                    }
+                });
+            }
+            Err(e) => {
+                println!("error: {}", e);
+            }
+        }
+    }
+}
+
+// Bundles what each HTTP request needs to run commands
+struct Session {
+    addr: String,
+    db: Db,
+    blocked: Blocked,
+    clients: Clients,
+}
+
+fn handle_http(
+    mut stream: TcpStream,
+    token: Option<Arc<str>>,
+    db: Db,
+    blocked: Blocked,
+    clients: Clients,
+) -> IoResult<()> {
+    let session = Session { addr: peer_addr(&stream), db, blocked, clients };
+    let response = match read_request(&mut stream)? {
+        Some(request) if !authorized(&request, token.as_deref()) => {
+            Response::error("401 Unauthorized", "missing or invalid bearer token")
+        }
+        Some(request) => route(request, &session),
+        None => Response::error("400 Bad Request", "malformed request"),
+    };
+
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        response.content_type,
+        response.body.len()
+    );
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(response.body.as_bytes())
+}
+
+fn authorized(request: &Request, token: Option<&str>) -> bool {
+    token_matches(request.header("Authorization").and_then(|v| v.strip_prefix("Bearer ")), token)
+}
+
+/// Whether a client presented the configured token. Nothing matches when no token is set.
+pub fn token_matches(given: Option<&str>, token: Option<&str>) -> bool {
+    let (Some(given), Some(token)) = (given, token) else {
+        return false;
+    };
+    // Compare every byte so the time taken doesn't give away how much of the token was right
+    given.len() == token.len() && given.bytes().zip(token.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+fn read_request(stream: &mut TcpStream) -> IoResult<Option<Request>> {
+    let mut data = Vec::new();
+    let mut buffer = [0; 1024];
+
+    let header_end = loop {
+        if let Some(pos) = data.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos;
+        }
+        if data.len() > 8192 {
+            return Ok(None);
+        }
+        let n = stream.read(&mut buffer)?;
+        if n == 0 {
+            return Ok(None);
+        }
+        data.extend_from_slice(&buffer[..n]);
+    };
+
+    let head = String::from_utf8_lossy(&data[..header_end]).to_string();
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or("").split(' ');
+    let (Some(method), Some(path)) = (request_line.next(), request_line.next()) else {
+        return Ok(None);
+    };
+
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            Some((name.trim().to_string(), value.trim().to_string()))
+        })
+        .collect();
+
+    let mut request = Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        headers,
+        body: data[header_end + 4..].to_vec(),
+    };
+
+    let content_length = match request.header("Content-Length") {
+        Some(len) => match len.parse::<usize>() {
+            Ok(len) if len <= MAX_BODY_LEN => len,
+            _ => return Ok(None),
+        },
+        None => 0,
+    };
+    while request.body.len() < content_length {
+        let n = stream.read(&mut buffer)?;
+        if n == 0 {
+            return Ok(None);
+        }
+        request.body.extend_from_slice(&buffer[..n]);
+    }
+    request.body.truncate(content_length);
+
+    Ok(Some(request))
+}
+
+fn route(request: Request, session: &Session) -> Response {
+    if let Some(key) = request.path.strip_prefix("/keys/") {
+        let Some(key) = percent_decode(key) else {
+            return Response::error("400 Bad Request", "invalid key encoding");
+        };
+
+        return match request.method.as_str() {
+            "GET" => match execute(&[String::from("GET"), key], session) {
+                RespValue::Bulk(Some(value)) => Response { status: "200 OK", content_type: "text/plain", body: value },
+                RespValue::Bulk(None) => Response::error("404 Not Found", "no such key"),
+                RespValue::Error(e) => Response::error("409 Conflict", &e),
+                other => Response::json("200 OK", other.to_json()),
+            },
+            "PUT" => {
+                let value = String::from_utf8_lossy(&request.body).to_string();
+                let mut args = vec![String::from("SET"), key, value];
+
+                // Optional expiry in milliseconds
+                if let Some(ttl) = request.header("X-TTL-Ms") {
+                    if ttl.parse::<u64>().is_err() {
+                        return Response::error("400 Bad Request", "X-TTL-Ms must be a non-negative integer");
+                    }
+                    args.push(String::from("PX"));
+                    args.push(ttl.to_string());
+                }
+
+                match execute(&args, session) {
+                    RespValue::Error(e) => Response::error("409 Conflict", &e),
+                    other => Response::json("200 OK", other.to_json()),
+                }
+            }
+            _ => Response::error("405 Method Not Allowed", "use GET or PUT"),
+        };
+    }
+
+    if request.path == "/command" {
+        if request.method != "POST" {
+            return Response::error("405 Method Not Allowed", "use POST");
+        }
+
+        let Some(args) = parse_json_string_array(&String::from_utf8_lossy(&request.body)) else {
+            return Response::error("400 Bad Request", "body must be a JSON array of strings");
+        };
+        let Some(name) = args.first() else {
+            return Response::error("400 Bad Request", "empty command");
+        };
+        let lower = name.to_lowercase();
+        if !commands::is_command(&lower) || BLOCKED_COMMANDS.contains(&lower.as_str()) {
+            return Response::error("403 Forbidden", &format!("command '{}' is not allowed over HTTP", name));
+        }
+
+        return match execute(&args, session) {
+            RespValue::Error(e) => Response::error("400 Bad Request", &e),
+            other => Response::json("200 OK", format!("{{\"result\":{}}}", other.to_json())),
+        };
+    }
+
+    Response::error("404 Not Found", "unknown endpoint")
+}
+
+// Runs a single command through the regular dispatcher
+fn execute(args: &[String], session: &Session) -> RespValue {
+    let mut command = format!("*{}\r\n", args.len());
+    for arg in args {
+        command.push_str(&format!("${}\r\n{}\r\n", arg.len(), arg));
+    }
+
+    let mut memory = MemoryStream { input: Cursor::new(command.into_bytes()), output: Vec::new() };
+    if let Err(e) = handle_connection(
+        &mut memory,
+        session.addr.clone(),
+        Arc::clone(&session.db),
+        Arc::clone(&session.blocked),
+        Arc::clone(&session.clients),
+    ) {
+        return RespValue::Error(format!("ERR {}", e));
+    }
+
+    match RespValue::parse(&mut memory.output.as_slice()) {
+        Some(value) => value,
+        // The dispatcher answers nothing for commands it can't parse
+        None => RespValue::Error(String::from("ERR invalid command or arguments")),
+    }
+}
+
+enum RespValue {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Option<String>),
+    Array(Option<Vec<RespValue>>),
+}
+
+impl RespValue {
+    // Decodes one reply from the front of `input`, advancing past it
+    fn parse(input: &mut &[u8]) -> Option<RespValue> {
+        let line_end = input.windows(2).position(|w| w == b"\r\n")?;
+        let line = String::from_utf8_lossy(&input[..line_end]).to_string();
+        *input = &input[line_end + 2..];
+
+        let (kind, rest) = (line.get(..1)?, &line[1..]);
+        match kind {
+            "+" => Some(RespValue::Simple(rest.to_string())),
+            "-" => Some(RespValue::Error(rest.to_string())),
+            ":" => Some(RespValue::Integer(rest.parse().ok()?)),
+            "$" if rest == "-1" => Some(RespValue::Bulk(None)),
+            "$" => {
+                let len: usize = rest.parse().ok()?;
+                let data = String::from_utf8_lossy(input.get(..len)?).to_string();
+                *input = input.get(len + 2..)?;
+                Some(RespValue::Bulk(Some(data)))
+            }
+            "*" if rest == "-1" => Some(RespValue::Array(None)),
+            "*" => {
+                let len: usize = rest.parse().ok()?;
+                let items = (0..len).map(|_| RespValue::parse(input)).collect::<Option<Vec<_>>>()?;
+                Some(RespValue::Array(Some(items)))
+            }
+            _ => None,
+        }
+    }
+
+    fn to_json(&self) -> String {
+        match self {
+            RespValue::Simple(s) => json_string(s),
+            RespValue::Error(e) => format!("{{\"error\":{}}}", json_string(e)),
+            RespValue::Integer(i) => i.to_string(),
+            RespValue::Bulk(Some(s)) => json_string(s),
+            RespValue::Bulk(None) | RespValue::Array(None) => String::from("null"),
+            RespValue::Array(Some(items)) => {
+                let items: Vec<String> = items.iter().map(|item| item.to_json()).collect();
+                format!("[{}]", items.join(","))
+            }
+        }
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+// Parses a JSON array whose elements are strings or numbers, e.g. ["SET", "k", 5]
+fn parse_json_string_array(input: &str) -> Option<Vec<String>> {
+    let mut chars = input.trim().chars().peekable();
+    let mut items = Vec::new();
+
+    if chars.next()? != '[' {
+        return None;
+    }
+
+    loop {
+        while chars.peek()?.is_whitespace() {
+            chars.next();
+        }
+        match chars.next()? {
+            ']' if items.is_empty() => break,
+            '"' => {
+                let mut item = String::new();
+                loop {
+                    match chars.next()? {
+                        '"' => break,
+                        '\\' => match chars.next()? {
+                            'n' => item.push('\n'),
+                            'r' => item.push('\r'),
+                            't' => item.push('\t'),
+                            'b' => item.push('\u{8}'),
+                            'f' => item.push('\u{c}'),
+                            'u' => {
+                                let hex: String = (0..4).map(|_| chars.next()).collect::<Option<_>>()?;
+                                item.push(char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?);
+                            }
+                            c => item.push(c),
+                        },
+                        c => item.push(c),
+                    }
+                }
+                items.push(item);
+            }
+            c if c == '-' || c.is_ascii_digit() => {
+                let mut number = String::from(c);
+                while let Some(&c) = chars.peek() {
+                    if !(c.is_ascii_digit() || matches!(c, '.' | 'e' | 'E' | '+' | '-')) {
+                        break;
+                    }
+                    number.push(c);
+                    chars.next();
+                }
+                number.parse::<f64>().ok()?;
+                items.push(number);
+            }
+            _ => return None,
+        }
+
+        while chars.peek()?.is_whitespace() {
+            chars.next();
+        }
+        match chars.next()? {
+            ',' => continue,
+            ']' => break,
+            _ => return None,
+        }
+    }
+
+    if chars.any(|c| !c.is_whitespace()) {
+        return None;
+    }
+    Some(items)
+}
+
+fn percent_decode(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            out.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).ok()
+}
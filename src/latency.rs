@@ -0,0 +1,90 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::commands;
+
+// Bucket i counts calls that took at most 2^i microseconds
+const NUM_BUCKETS: usize = 40;
+
+// One set of counters per command table entry, so recording a call takes no lock
+static HISTOGRAMS: [Counters; commands::COUNT] = [const { Counters::new() }; commands::COUNT];
+
+struct Counters {
+    buckets: [AtomicU64; NUM_BUCKETS],
+}
+
+impl Counters {
+    const fn new() -> Self {
+        Counters { buckets: [const { AtomicU64::new(0) }; NUM_BUCKETS] }
+    }
+
+    fn record(&self, elapsed: Duration) {
+        let usec = elapsed.as_micros().max(1) as u64;
+        // Smallest i with usec <= 2^i
+        let bucket = (64 - (usec - 1).leading_zeros()) as usize;
+        self.buckets[bucket.min(NUM_BUCKETS - 1)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    // A copy of the counts, None if the command was never called
+    fn load(&self) -> Option<Histogram> {
+        let buckets: [u64; NUM_BUCKETS] = std::array::from_fn(|i| self.buckets[i].load(Ordering::Relaxed));
+        let calls = buckets.iter().sum();
+        (calls > 0).then_some(Histogram { calls, buckets })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    pub calls: u64,
+    buckets: [u64; NUM_BUCKETS],
+}
+
+impl Histogram {
+    /// Cumulative (upper bound in usec, calls at or below it) pairs, skipping empty leading
+    /// buckets and stopping once every call is accounted for.
+    pub fn cumulative(&self) -> Vec<(u64, u64)> {
+        let mut result = Vec::new();
+        let mut total = 0;
+        for (i, &count) in self.buckets.iter().enumerate() {
+            total += count;
+            if total > 0 {
+                result.push((1u64 << i, total));
+            }
+            if total == self.calls {
+                break;
+            }
+        }
+        result
+    }
+}
+
+/// Records a call to `command`, which must be a lowercase name from the command table.
+pub fn record(command: &str, elapsed: Duration) {
+    if let Some(index) = commands::index(command) {
+        HISTOGRAMS[index].record(elapsed);
+    }
+}
+
+/// Histograms for the given commands, or for every command seen so far when none are named.
+pub fn snapshot(commands: &[String]) -> Vec<(String, Histogram)> {
+    if commands.is_empty() {
+        let mut result: Vec<(String, Histogram)> = HISTOGRAMS
+            .iter()
+            .enumerate()
+            .filter_map(|(index, counters)| Some((commands::name(index).to_string(), counters.load()?)))
+            .collect();
+        result.sort_by(|(a, _), (b, _)| a.cmp(b));
+        return result;
+    }
+
+    let mut result = Vec::new();
+    for command in commands {
+        let name = command.to_lowercase();
+        if let Some(h) = commands::index(&name).and_then(|index| HISTOGRAMS[index].load())
+            && !result.iter().any(|(n, _): &(String, Histogram)| *n == name)
+        {
+            result.push((name, h));
+        }
+    }
+    result
+}
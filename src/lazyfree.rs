@@ -0,0 +1,43 @@
+use std::sync::LazyLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Sender};
+
+// Values cheaper than this to free are dropped on the spot; queueing them would
+// cost more than it saves. Same cutoff as Redis' LAZYFREE_THRESHOLD.
+const LAZYFREE_THRESHOLD: usize = 64;
+
+static PENDING: AtomicU64 = AtomicU64::new(0);
+static FREED: AtomicU64 = AtomicU64::new(0);
+
+// Started on first use, like Redis' lazyfree bio thread
+static QUEUE: LazyLock<Sender<Box<dyn Send>>> = LazyLock::new(|| {
+    let (tx, rx) = mpsc::channel::<Box<dyn Send>>();
+    std::thread::spawn(move || {
+        for value in rx {
+            drop(value);
+            PENDING.fetch_sub(1, Ordering::Relaxed);
+            FREED.fetch_add(1, Ordering::Relaxed);
+        }
+    });
+    tx
+});
+
+/// Drops `value`, on the background thread if `effort` (roughly how many
+/// allocations it owns) makes that worth it. Call after releasing any locks.
+pub fn free<T: Send + 'static>(value: T, effort: usize) {
+    if effort <= LAZYFREE_THRESHOLD {
+        return;
+    }
+    PENDING.fetch_add(1, Ordering::Relaxed);
+    if let Err(mpsc::SendError(value)) = QUEUE.send(Box::new(value)) {
+        PENDING.fetch_sub(1, Ordering::Relaxed);
+        drop(value);
+    }
+}
+
+pub fn info_fields() -> Vec<(&'static str, String)> {
+    vec![
+        ("lazyfree_pending_objects", PENDING.load(Ordering::Relaxed).to_string()),
+        ("lazyfreed_objects", FREED.load(Ordering::Relaxed).to_string()),
+    ]
+}
@@ -0,0 +1,70 @@
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::SystemTime;
+
+static LOADING: AtomicBool = AtomicBool::new(false);
+static START_TIME: AtomicU64 = AtomicU64::new(0); // Unix seconds
+static TOTAL_BYTES: AtomicU64 = AtomicU64::new(0);
+static LOADED_BYTES: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    // Set on the thread replaying the dataset so its own commands aren't refused
+    static IS_LOADER: Cell<bool> = const { Cell::new(false) };
+}
+
+fn unix_secs() -> u64 {
+    SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs()
+}
+
+/// Marks the dataset as loading; call before any listener starts accepting.
+pub fn begin(total_bytes: u64) {
+    START_TIME.store(unix_secs(), Ordering::Relaxed);
+    TOTAL_BYTES.store(total_bytes, Ordering::Relaxed);
+    LOADED_BYTES.store(0, Ordering::Relaxed);
+    LOADING.store(true, Ordering::Release);
+}
+
+pub fn progress(loaded_bytes: u64) {
+    LOADED_BYTES.store(loaded_bytes, Ordering::Relaxed);
+}
+
+pub fn finish() {
+    LOADING.store(false, Ordering::Release);
+}
+
+/// Runs `f` as the loader, exempt from the -LOADING refusal.
+pub fn run_as_loader<T>(f: impl FnOnce() -> T) -> T {
+    IS_LOADER.with(|flag| flag.set(true));
+    let result = f();
+    IS_LOADER.with(|flag| flag.set(false));
+    result
+}
+
+/// Whether commands from the current client should get -LOADING.
+pub fn refuses_commands() -> bool {
+    LOADING.load(Ordering::Acquire) && !IS_LOADER.with(|flag| flag.get())
+}
+
+// Lines for the "# Persistence" INFO section
+pub fn info_fields() -> Vec<(&'static str, String)> {
+    if !LOADING.load(Ordering::Acquire) {
+        return vec![("loading", String::from("0"))];
+    }
+
+    let start = START_TIME.load(Ordering::Relaxed);
+    let total = TOTAL_BYTES.load(Ordering::Relaxed);
+    let loaded = LOADED_BYTES.load(Ordering::Relaxed);
+    let elapsed = unix_secs().saturating_sub(start);
+    let percent = if total > 0 { loaded as f64 * 100.0 / total as f64 } else { 0.0 };
+    // Extrapolate from the rate so far, like Redis does
+    let eta = (total.saturating_sub(loaded) * elapsed).checked_div(loaded).unwrap_or(1);
+
+    vec![
+        ("loading", String::from("1")),
+        ("loading_start_time", start.to_string()),
+        ("loading_total_bytes", total.to_string()),
+        ("loading_loaded_bytes", loaded.to_string()),
+        ("loading_loaded_perc", format!("{:.2}", percent)),
+        ("loading_eta_seconds", eta.to_string()),
+    ]
+}
@@ -1,10 +1,58 @@
 #![allow(unused_imports)]
-use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
+use std::hash::BuildHasher;
 use std::io::{Read, Result as IoResult, Write};
 use std::net::{TcpListener, TcpStream};
-use std::sync::{Arc, Condvar, Mutex};
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant, SystemTime};
 
+mod args;
+mod blocking;
+mod bloom;
+mod bufpool;
+mod commands;
+mod config;
+mod dict;
+mod dump;
+mod expire;
+mod frame;
+mod glob;
+mod hash;
+mod http;
+mod latency;
+mod lazyfree;
+mod loading;
+mod mass_insert;
+mod memcached;
+mod resp;
+mod scan_order;
+mod set;
+mod sketch;
+mod systemd;
+mod timeseries;
+mod vectorset;
+mod websocket;
+mod zset;
+
+use args::{Options, Request};
+use blocking::BlockedClients;
+use bloom::{BloomError, ScalableBloom};
+use bufpool::PooledStream;
+use config::Config;
+use dict::Dict;
+use hash::RedisHash;
+use resp::{RedisWrite, Reply};
+use set::RedisSet;
+use sketch::{CountMinSketch, TopK};
+use timeseries::{Aggregation, LabelFilter, Labels, TimeSeries, TimeSeriesError};
+use vectorset::{Metric, VectorSet};
+use websocket::WsStream;
+use zset::SortedSet;
+
 #[allow(dead_code)]
 #[derive(Debug)]
 struct StreamEntry {
@@ -16,20 +64,74 @@ struct StreamEntry {
 #[derive(Debug)]
 enum RedisValue {
     String(String),
-    List(Vec<String>),
+    List(VecDeque<String>),
+    Hash(RedisHash),
+    Set(RedisSet),
+    SortedSet(SortedSet),
     Stream(Vec<StreamEntry>),
+    Bloom(ScalableBloom),
+    TimeSeries(TimeSeries),
+    VectorSet(VectorSet),
+    CountMinSketch(CountMinSketch),
+    TopK(TopK),
 }
 
 
 #[derive(Debug)]
 struct Entry {
     value: RedisValue,
-    created_at: Instant,
-    expires_in: Option<Duration>,
+    expires_at: Option<i64>, // Unix time in milliseconds
+    last_access: Instant,    // For OBJECT IDLETIME
+    flags: u32,              // Memcached client flags, 0 unless stored through memcached
+}
+
+type Db = Arc<Mutex<Dict<Entry>>>;
+type Blocked = Arc<BlockedClients>;
+type Clients = Arc<Mutex<HashMap<u64, ClientInfo>>>;
+
+static NEXT_CLIENT_ID: AtomicU64 = AtomicU64::new(1);
+
+// Version reported by HELLO; clients use it to decide which features to rely on
+const REDIS_VERSION: &str = "7.2.0";
+
+// Most picks a negative HRANDFIELD-style count may ask for
+const MAX_REPEATED_PICKS: u64 = 1 << 20;
+
+#[derive(Debug)]
+struct ClientInfo {
+    addr: String,
+    connected_at: Instant,
+    lib_name: Option<String>, // Reported by the client via CLIENT SETINFO
+    lib_ver: Option<String>,
+    name: Option<String>, // Set via HELLO SETNAME
+}
+
+impl ClientInfo {
+    // One line of CLIENT LIST / CLIENT INFO output
+    fn describe(&self, id: u64) -> String {
+        format!(
+            "id={} addr={} name={} age={} lib-name={} lib-ver={}\n",
+            id,
+            self.addr,
+            self.name.as_deref().unwrap_or(""),
+            self.connected_at.elapsed().as_secs(),
+            self.lib_name.as_deref().unwrap_or(""),
+            self.lib_ver.as_deref().unwrap_or(""),
+        )
+    }
 }
 
-type Db = Arc<Mutex<HashMap<String, Entry>>>;
-type Cv = Arc<Condvar>;
+// Removes the client from the registry however the connection ends
+struct ClientGuard {
+    id: u64,
+    clients: Clients,
+}
+
+impl Drop for ClientGuard {
+    fn drop(&mut self) {
+        self.clients.lock().unwrap().remove(&self.id);
+    }
+}
 
 #[derive(Debug)]
 enum Command {
@@ -38,9 +140,101 @@ enum Command {
     Set {
         key: String,
         value: String,
-        px: Option<u64>, // Expiry in milliseconds
+        expiry: Option<Expiry>, // None clears any existing TTL
+        condition: Option<SetCondition>,
+        get: bool, // Reply with the old value instead of OK
+    },
+    Setnx {
+        key: String,
+        value: String,
+    },
+    Get(String),    // Key
+    Getdel(String), // Key
+    Lcs {
+        key1: String,
+        key2: String,
+        len: bool,
+        idx: bool,
+        min_match_len: usize,
+        with_match_len: bool,
+    },
+    Getex {
+        key: String,
+        expiry: Option<Expiry>, // None leaves the TTL alone
+    },
+    Incrby {
+        key: String,
+        increment: Option<i64>, // None if the client's increment isn't an integer
+    },
+    Incrbyfloat {
+        key: String,
+        increment: Option<f64>, // None if the client's increment isn't a float
+    },
+    Append {
+        key: String,
+        value: String,
+    },
+    Strlen(String), // Key
+    Getrange {
+        key: String,
+        start: i64,
+        end: i64,
+    },
+    Setrange {
+        key: String,
+        offset: i64,
+        value: String,
+    },
+    Del(Vec<String>),    // Keys
+    Unlink(Vec<String>), // Keys
+    Exists(Vec<String>), // Keys
+    Keys(String),        // Pattern
+    Touch(Vec<String>),  // Keys
+    Dump(String),        // Key
+    ObjectEncoding(String), // Key
+    ObjectRefcount(String), // Key
+    ObjectIdletime(String), // Key
+    ObjectFreq(String),     // Key
+    MemoryUsage {
+        key: String,
+        samples: usize, // Container elements to measure, 0 for all
+    },
+    Restore {
+        key: String,
+        ttl: i64, // Milliseconds, 0 for none
+        payload: String,
+        replace: bool,
+        absttl: bool,          // ttl is a Unix time in milliseconds
+        idletime: Option<u64>, // Seconds since last access
+    },
+    Randomkey,
+    Rename {
+        key: String,
+        new_key: String,
+        nx: bool, // RENAMENX: don't overwrite an existing key
+    },
+    Scan {
+        cursor: u64,
+        pattern: Option<String>,
+        count: usize,
+        type_name: Option<String>,
+    },
+    Ttl {
+        key: String,
+        millis: bool, // PTTL
+    },
+    Expiretime {
+        key: String,
+        millis: bool, // PEXPIRETIME
+    },
+    Expire {
+        key: String,
+        expiry: Expiry,
+        nx: bool, // Only if the key has no TTL
+        xx: bool, // Only if it has one
+        gt: bool, // Only if the new TTL is longer; no TTL counts as infinite
+        lt: bool, // Only if it's shorter
     },
-    Get(String), // Key
     Rpush {
         key: String,
         values: Vec<String>,
@@ -54,15 +248,202 @@ enum Command {
         start: i64,
         stop: i64,
     },
+    Ltrim {
+        key: String,
+        start: i64,
+        stop: i64,
+    },
     Llen(String),
-    Lpop {
+    Lindex {
+        key: String,
+        index: i64, // Negative counts from the tail
+    },
+    Lset {
+        key: String,
+        index: i64,
+        value: String,
+    },
+    Lpos {
+        key: String,
+        element: String,
+        rank: i64,            // Which match to start from; negative searches from the tail
+        count: Option<usize>, // Reply with up to this many indexes (0 for all) rather than one
+        maxlen: usize,        // Elements to look at, 0 for all
+    },
+    Linsert {
+        key: String,
+        after: bool, // AFTER rather than BEFORE the pivot
+        pivot: String,
+        element: String,
+    },
+    Lrem {
+        key: String,
+        count: i64, // From the head if positive, the tail if negative, all if 0
+        element: String,
+    },
+    Pop {
         key: String,
+        end: ListEnd,
         count: Option<usize>,
     },
-    Blpop {
+    Bpop {
+        keys: Vec<String>,
+        end: ListEnd,
+        timeout: f64,
+    },
+    Lmpop {
         keys: Vec<String>,
+        end: ListEnd,
+        count: usize,
+        timeout: Option<f64>, // BLMPOP's
+    },
+    Blmove {
+        source: String,
+        destination: String,
+        from: ListEnd,
+        to: ListEnd,
         timeout: f64,
     },
+    Hset {
+        key: String,
+        pairs: Vec<(String, String)>, // Field, value
+    },
+    Hget {
+        key: String,
+        field: String,
+    },
+    Hdel {
+        key: String,
+        fields: Vec<String>,
+    },
+    Hgetall(String), // Key
+    Hmget {
+        key: String,
+        fields: Vec<String>,
+    },
+    Hkeys(String), // Key
+    Hvals(String), // Key
+    Hlen(String),  // Key
+    Hsetnx {
+        key: String,
+        field: String,
+        value: String,
+    },
+    Hexists {
+        key: String,
+        field: String,
+    },
+    Hstrlen {
+        key: String,
+        field: String,
+    },
+    Hrandfield {
+        key: String,
+        count: Option<i64>, // Negative allows the same field more than once
+        withvalues: bool,
+    },
+    Hscan {
+        key: String,
+        cursor: u64,
+        pattern: Option<String>,
+        count: usize,
+        novalues: bool,
+    },
+    Hincrby {
+        key: String,
+        field: String,
+        increment: Option<i64>, // None if it wasn't an integer
+    },
+    Hincrbyfloat {
+        key: String,
+        field: String,
+        increment: Option<f64>, // None if it wasn't a number
+    },
+    Hexpire {
+        key: String,
+        expiry: Expiry,
+        nx: bool, // Same conditions as EXPIRE, checked per field
+        xx: bool,
+        gt: bool,
+        lt: bool,
+        fields: Vec<String>,
+    },
+    Httl {
+        key: String,
+        millis: bool, // HPTTL
+        fields: Vec<String>,
+    },
+    Hpersist {
+        key: String,
+        fields: Vec<String>,
+    },
+    Sadd {
+        key: String,
+        members: Vec<String>,
+    },
+    Srem {
+        key: String,
+        members: Vec<String>,
+    },
+    Smembers(String),
+    Sismember {
+        key: String,
+        member: String,
+    },
+    Smismember {
+        key: String,
+        members: Vec<String>,
+    },
+    Scard(String),
+    Smove {
+        source: String,
+        destination: String,
+        member: String,
+    },
+    Sintercard {
+        keys: Vec<String>,
+        limit: usize, // 0 for no limit
+    },
+    Sscan {
+        key: String,
+        cursor: u64,
+        pattern: Option<String>,
+        count: usize,
+    },
+    Setop {
+        op: SetOp,
+        keys: Vec<String>,
+        destination: Option<String>, // The STORE variants
+    },
+    Spop {
+        key: String,
+        count: Option<usize>,
+    },
+    Srandmember {
+        key: String,
+        count: Option<i64>, // Negative allows the same member more than once
+    },
+    Zadd {
+        key: String,
+        nx: bool,   // Only add new members
+        xx: bool,   // Only update existing ones
+        gt: bool,   // Only update a score to a higher one
+        lt: bool,   // Only update a score to a lower one
+        ch: bool,   // Count changed scores as well as added members
+        incr: bool, // Add to the score instead, like ZINCRBY
+        members: Option<Vec<(f64, String)>>, // None if a score wasn't a valid float
+    },
+    Zscore {
+        key: String,
+        member: String,
+    },
+    Zrange {
+        key: String,
+        start: i64,
+        stop: i64,
+        rev: bool, // Indexes count from the highest score
+        withscores: bool,
+    },
     Xadd {
         key: String,
         id: String,
@@ -79,41 +460,209 @@ enum Command {
         block_timeout: Option<u64>, // Block timeout in milliseconds
     },
     Type(String),
+    BfReserve {
+        key: String,
+        error_rate: f64,
+        capacity: u64,
+        expansion: u32,
+        nonscaling: bool,
+    },
+    BfAdd {
+        key: String,
+        items: Vec<String>,
+        multi: bool, // BF.MADD replies with an array even for a single item
+    },
+    BfExists {
+        key: String,
+        item: String,
+    },
+    BfInfo {
+        key: String,
+        field: Option<String>,
+    },
+    ClientSetinfo {
+        attr: String,
+        value: String,
+    },
+    ClientList,
+    ClientInfo,
+    LatencyHistogram(Vec<String>), // Empty means every command
+    Info(Option<String>),          // Optional section name
+    Hello {
+        protover: Option<String>,
+        setname: Option<String>,
+    },
+    Readonly,
+    Readwrite,
+    Vadd {
+        key: String,
+        vector: Vec<f32>,
+        element: String,
+        attributes: Option<String>,
+    },
+    Vsim {
+        key: String,
+        query: VectorQuery,
+        with_scores: bool,
+        count: usize,
+        metric: Metric,
+    },
+    Vcard(String),
+    Vdim(String),
+    Vrem {
+        key: String,
+        element: String,
+    },
+    Vemb {
+        key: String,
+        element: String,
+    },
+    Vgetattr {
+        key: String,
+        element: String,
+    },
+    Vsetattr {
+        key: String,
+        element: String,
+        attributes: String,
+    },
+    TopkReserve {
+        key: String,
+        k: usize,
+        width: usize,
+        depth: usize,
+        decay: f64,
+    },
+    TopkAdd {
+        key: String,
+        items: Vec<String>,
+    },
+    TopkQuery {
+        key: String,
+        items: Vec<String>,
+    },
+    TopkList {
+        key: String,
+        with_count: bool,
+    },
+    CmsInitbydim {
+        key: String,
+        width: usize,
+        depth: usize,
+    },
+    CmsIncrby {
+        key: String,
+        increments: Vec<(String, u64)>,
+    },
+    CmsQuery {
+        key: String,
+        items: Vec<String>,
+    },
+    TsCreate {
+        key: String,
+        retention: u64,
+        labels: Labels,
+    },
+    TsAdd {
+        key: String,
+        timestamp: Option<u64>, // None means '*' (server time)
+        value: f64,
+        retention: Option<u64>,
+        labels: Labels,
+    },
+    TsRange {
+        key: String,
+        range: TsRangeArgs,
+    },
+    TsMrange {
+        range: TsRangeArgs,
+        with_labels: bool,
+        filters: Vec<LabelFilter>,
+    },
 }
 
-struct Resp;
+// SET's NX and XX
+#[derive(Debug, PartialEq)]
+enum SetCondition {
+    IfMissing,
+    IfExists,
+}
 
-impl Resp {
-    fn string(s: &str) -> String {
-        format!("+{}\r\n", s)
-    }
-    fn error(msg: &str) -> String {
-        format!("-{}\r\n", msg)
-    }
-    fn integer(i: usize) -> String {
-        format!(":{}\r\n", i)
-    }
-    fn bulk_string(s: &str) -> String {
-        format!("${}\r\n{}\r\n", s.len(), s)
-    }
-    fn null_bulk() -> &'static str {
-        "$-1\r\n"
-    }
-    fn null_array() -> &'static str {
-        "*-1\r\n"
+// Which end of a list LPOP/RPOP and friends work on
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ListEnd {
+    Left,
+    Right,
+}
+
+impl ListEnd {
+    fn parse(arg: &str) -> Option<Self> {
+        match arg.to_uppercase().as_str() {
+            "LEFT" => Some(ListEnd::Left),
+            "RIGHT" => Some(ListEnd::Right),
+            _ => None,
+        }
     }
-    fn array(len: usize) -> String {
-        format!("*{}\r\n", len)
+}
+
+// What SINTER, SUNION, SDIFF and their STORE variants do with the sets
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SetOp {
+    Inter,
+    Union,
+    Diff, // Members of the first set in none of the others
+}
+
+// SET and GETEX's expiry options, with the values as the client sent them
+#[derive(Debug)]
+enum Expiry {
+    Ex(i64),   // Seconds from now
+    Px(i64),   // Milliseconds from now
+    ExAt(i64), // Unix time in seconds
+    PxAt(i64), // Unix time in milliseconds
+    KeepTtl,   // SET only
+    Persist,   // GETEX only
+}
+
+impl Expiry {
+    // The time as (value, milliseconds per unit, whether it's a Unix time); None for KEEPTTL and PERSIST
+    fn parts(&self) -> Option<(i64, i64, bool)> {
+        match *self {
+            Expiry::Ex(secs) => Some((secs, 1000, false)),
+            Expiry::Px(ms) => Some((ms, 1, false)),
+            Expiry::ExAt(secs) => Some((secs, 1000, true)),
+            Expiry::PxAt(ms) => Some((ms, 1, true)),
+            Expiry::KeepTtl | Expiry::Persist => None,
+        }
     }
 }
 
-trait RedisWrite {
-    fn write_resp(&mut self, resp: impl AsRef<[u8]>) -> IoResult<()>;
+#[derive(Debug)]
+enum VectorQuery {
+    Element(String),
+    Values(Vec<f32>),
+}
+
+#[derive(Debug)]
+struct TsRangeArgs {
+    from: u64,
+    to: u64,
+    count: Option<usize>,
+    aggregation: Option<(Aggregation, u64)>,
 }
 
-impl RedisWrite for TcpStream {
-    fn write_resp(&mut self, resp: impl AsRef<[u8]>) -> IoResult<()> {
-        self.write_all(resp.as_ref())
+impl Command {
+    // Commands Redis still serves while the dataset is being loaded
+    fn allowed_while_loading(&self) -> bool {
+        matches!(
+            self,
+            Command::Info(_)
+                | Command::Hello { .. }
+                | Command::ClientSetinfo { .. }
+                | Command::ClientList
+                | Command::ClientInfo
+                | Command::LatencyHistogram(_)
+        )
     }
 }
 
@@ -122,7 +671,104 @@ impl RedisValue {
         match self {
             RedisValue::String(_) => "string",
             RedisValue::List(_) => "list",
+            RedisValue::Hash(_) => "hash",
+            RedisValue::Set(_) => "set",
+            RedisValue::SortedSet(_) => "zset",
+            RedisValue::Stream(_) => "stream",
+            RedisValue::Bloom(_) => "MBbloom--",
+            RedisValue::TimeSeries(_) => "TSDB-TYPE",
+            RedisValue::VectorSet(_) => "vectorset",
+            RedisValue::CountMinSketch(_) => "CMSk-TYPE",
+            RedisValue::TopK(_) => "TopK-TYPE",
+        }
+    }
+
+    // What OBJECT ENCODING reports: the representation Redis would use for this value
+    fn encoding(&self) -> &'static str {
+        match self {
+            RedisValue::String(s) if s.len() <= 20 && integer_value(s).is_some() => "int",
+            RedisValue::String(s) if s.len() <= 44 => "embstr",
+            RedisValue::List(list) if list.len() <= 128 && list.iter().all(|item| item.len() <= 64) => "listpack",
+            RedisValue::List(_) => "quicklist",
+            RedisValue::Hash(hash) if hash.len() <= 128 && hash.iter().all(|(field, value)| field.len() <= 64 && value.len() <= 64) => {
+                "listpack"
+            }
+            RedisValue::Hash(_) => "hashtable",
+            RedisValue::Set(RedisSet::Ints(_)) => "intset",
+            RedisValue::Set(RedisSet::Members(set, _)) if set.len() <= 128 && set.iter().all(|member| member.len() <= 64) => "listpack",
+            RedisValue::Set(RedisSet::Members(_, _)) => "hashtable",
+            RedisValue::SortedSet(zset) if zset.len() <= 128 && zset.iter().all(|(member, _)| member.len() <= 64) => "listpack",
+            RedisValue::SortedSet(_) => "skiplist",
             RedisValue::Stream(_) => "stream",
+            _ => "raw",
+        }
+    }
+
+    fn is_shared_integer(&self) -> bool {
+        matches!(self, RedisValue::String(s) if integer_value(s).is_some_and(|n| (0..10000).contains(&n)))
+    }
+
+    // Estimated heap bytes the value owns. For collections that's the size of the
+    // first `samples` elements (all of them if 0) scaled up to the whole container.
+    fn memory_usage(&self, samples: usize) -> usize {
+        fn sampled<T>(items: impl ExactSizeIterator<Item = T>, samples: usize, size: impl Fn(T) -> usize) -> usize {
+            let len = items.len();
+            let measured = if samples == 0 { len } else { samples.min(len) };
+            if measured == 0 {
+                return 0;
+            }
+            items.take(measured).map(size).sum::<usize>() * len / measured
+        }
+
+        match self {
+            RedisValue::String(s) => s.capacity(),
+            RedisValue::List(list) => {
+                list.capacity() * std::mem::size_of::<String>() + sampled(list.iter(), samples, |item| item.capacity())
+            }
+            RedisValue::Hash(hash) => {
+                // Each field is held twice, in the map and in the scan order
+                hash.capacity() * 2 * std::mem::size_of::<String>()
+                    + sampled(hash.iter(), samples, |(field, value)| {
+                        std::mem::size_of::<(u64, Vec<String>)>() + 2 * field.capacity() + value.capacity()
+                    })
+                    + hash.expiring().map(|(field, _)| std::mem::size_of::<(String, i64)>() + field.capacity()).sum::<usize>()
+            }
+            RedisValue::Set(RedisSet::Ints(ints)) => ints.capacity() * std::mem::size_of::<i64>(),
+            RedisValue::Set(RedisSet::Members(set, _)) => {
+                set.capacity() * std::mem::size_of::<String>()
+                    + sampled(set.iter(), samples, |member| std::mem::size_of::<(u64, Vec<String>)>() + 2 * member.capacity())
+            }
+            // Each member is held twice, in the score map and in the ordered index
+            RedisValue::SortedSet(zset) => {
+                zset.capacity() * std::mem::size_of::<(String, f64)>()
+                    + sampled(zset.iter(), samples, |(member, _)| std::mem::size_of::<(f64, String)>() + 2 * member.capacity())
+            }
+            RedisValue::Stream(entries) => {
+                entries.capacity() * std::mem::size_of::<StreamEntry>()
+                    + sampled(entries.iter(), samples, |entry| {
+                        entry.fields.iter().map(|(field, value)| 2 * std::mem::size_of::<String>() + field.len() + value.len()).sum()
+                    })
+            }
+            RedisValue::Bloom(bloom) => bloom.size_in_bytes(),
+            RedisValue::TimeSeries(series) => series.size_in_bytes(),
+            RedisValue::VectorSet(set) => set.size_in_bytes(),
+            RedisValue::CountMinSketch(cms) => cms.size_in_bytes(),
+            RedisValue::TopK(topk) => topk.size_in_bytes(),
+        }
+    }
+
+    // Roughly how many allocations dropping this value frees, for lazyfree
+    fn free_effort(&self) -> usize {
+        match self {
+            RedisValue::List(list) => list.len(),
+            RedisValue::Hash(hash) => hash.len(),
+            RedisValue::Set(set) => set.len(),
+            RedisValue::SortedSet(zset) => zset.len(),
+            RedisValue::Stream(entries) => entries.len(),
+            RedisValue::VectorSet(set) => set.len(),
+            // A single allocation, but returning a big one to the OS isn't free either
+            RedisValue::String(s) => s.len() / (64 * 1024),
+            _ => 1,
         }
     }
 }
@@ -131,17 +777,136 @@ fn main() {
     // You can use print statements as follows for debugging, they'll be visible when running tests.
     println!("Logs from your program will appear here!");
 
-    let listener = TcpListener::bind("127.0.0.1:6379").unwrap();
-    let db: Db = Arc::new(Mutex::new(HashMap::new()));
-    let cv = Arc::new(Condvar::new());
+    let config = match Config::from_args(std::env::args()) {
+        Ok(config) => config,
+        Err(errors) => {
+            for e in errors {
+                eprintln!("error: {}", e);
+            }
+            std::process::exit(1);
+        }
+    };
+    // --check-config stops here, before any port is bound
+    if config.check_config {
+        println!("Configuration OK");
+        return;
+    }
+    config.apply_limits();
+
+    // Sockets passed in by systemd take precedence over binding our own
+    let mut activated = match systemd::listeners_from_env() {
+        Ok(activated) => activated.unwrap_or_default(),
+        Err(e) => {
+            eprintln!("error: socket activation: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let listener = activated
+        .redis
+        .take()
+        .unwrap_or_else(|| TcpListener::bind(("127.0.0.1", config::DEFAULT_PORT)).unwrap());
+    let db: Db = Arc::new(Mutex::new(Dict::new()));
+    let blocked = Arc::new(BlockedClients::new());
+    let clients: Clients = Arc::new(Mutex::new(HashMap::new()));
+    expire::start(Arc::clone(&db));
+
+    // Replay the dataset in the background; clients get -LOADING until it's done
+    if let Some(path) = config.load_commands.clone() {
+        let data = std::fs::read(&path).unwrap_or_else(|e| {
+            eprintln!("error: can't read {}: {}", path, e);
+            std::process::exit(1);
+        });
+        loading::begin(data.len() as u64);
+
+        let db = Arc::clone(&db);
+        let blocked = Arc::clone(&blocked);
+        let clients = Arc::clone(&clients);
+        std::thread::spawn(move || {
+            match mass_insert::load_commands(&data, db, blocked, clients) {
+                Ok(report) => {
+                    for (command, message) in &report.errors {
+                        println!("{}: command #{} failed: {}", path, command, message);
+                    }
+                    println!("{}: errors: {}, replies: {}", path, report.errors.len(), report.replies);
+                }
+                Err(e) => {
+                    eprintln!("error: {}: {}", path, e);
+                    std::process::exit(1);
+                }
+            }
+            loading::finish();
+        });
+    }
+
+    let ws_listener = activated
+        .ws
+        .take()
+        .or_else(|| config.ws_port.map(|port| TcpListener::bind(("127.0.0.1", port)).unwrap()));
+    if let Some(ws_listener) = ws_listener {
+        let token: Option<Arc<str>> = config.http_token.as_deref().map(Arc::from);
+        let origins = Arc::new(config.ws_allowed_origins.clone());
+        let db = Arc::clone(&db);
+        let blocked = Arc::clone(&blocked);
+        let clients = Arc::clone(&clients);
+        std::thread::spawn(move || {
+            for stream in ws_listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        println!("accepted new websocket connection");
+                        let addr = peer_addr(&stream);
+                        let db_clone = Arc::clone(&db);
+                        let blocked_clone = Arc::clone(&blocked);
+                        let clients_clone = Arc::clone(&clients);
+                        let token_clone = token.clone();
+                        let origins_clone = Arc::clone(&origins);
+                        std::thread::spawn(move || match WsStream::accept(stream, token_clone.as_deref(), &origins_clone) {
+                            Ok(ws) => {
+                                if let Err(e) = handle_connection(ws, addr, db_clone, blocked_clone, clients_clone) {
+                                    println!("websocket connection error: {}", e);
+                                }
+                            }
+                            Err(e) => println!("websocket handshake failed: {}", e),
+                        });
+                    }
+                    Err(e) => {
+                        println!("error: {}", e);
+                    }
+                }
+            }
+        });
+    }
+
+    let http_listener = activated
+        .http
+        .take()
+        .or_else(|| config.http_port.map(|port| TcpListener::bind(("127.0.0.1", port)).unwrap()));
+    if let Some(http_listener) = http_listener {
+        let db = Arc::clone(&db);
+        let blocked = Arc::clone(&blocked);
+        let clients = Arc::clone(&clients);
+        let token = config.http_token.clone();
+        std::thread::spawn(move || http::serve(http_listener, token, db, blocked, clients));
+    }
+
+    let memcached_listener = activated
+        .memcached
+        .take()
+        .or_else(|| config.memcached_port.map(|port| TcpListener::bind(("127.0.0.1", port)).unwrap()));
+    if let Some(memcached_listener) = memcached_listener {
+        let db = Arc::clone(&db);
+        std::thread::spawn(move || memcached::serve(memcached_listener, db));
+    }
 
     for stream in listener.incoming() {
         match stream {
             Ok(stream) => {
                 println!("accepted new connection");
+                let addr = peer_addr(&stream);
                 let db_clone = Arc::clone(&db);
-                let cv_clone = Arc::clone(&cv);
-                std::thread::spawn(|| handle_connection(stream, db_clone, cv_clone).unwrap());
+                let blocked_clone = Arc::clone(&blocked);
+                let clients_clone = Arc::clone(&clients);
+                std::thread::spawn(|| handle_connection(stream, addr, db_clone, blocked_clone, clients_clone).unwrap());
             }
             Err(e) => {
                 println!("error: {}", e);
@@ -150,271 +915,1880 @@ fn main() {
     }
 }
 
-fn get_valid_entry<'a>(map: &'a mut HashMap<String, Entry>, key: &str) -> Option<&'a Entry> {
-    let expired = if let Some(entry) = map.get(key) {
-        if let Some(duration) = entry.expires_in {
-            entry.created_at.elapsed() > duration
-        } else {
-            false
-        }
-    } else {
-        false
+fn peer_addr(stream: &TcpStream) -> String {
+    stream
+        .peer_addr()
+        .map(|addr| addr.to_string())
+        .unwrap_or_else(|_| String::from("?"))
+}
+
+// Deletes `key` if its TTL has run out, without counting as an access. A hash also
+// loses the fields whose own TTL has run out, and goes too if that empties it.
+fn expire_if_needed(map: &mut Dict<Entry>, key: &str) {
+    let now = unix_time_ms();
+    let Some(entry) = map.get_mut(key) else {
+        return;
     };
 
-    if expired {
+    if entry.expires_at.is_some_and(|at| at < now) {
+        map.remove(key);
+        expire::record_expired(1);
+    } else if let RedisValue::Hash(hash) = &mut entry.value
+        && hash.expire_fields(now) > 0
+        && hash.is_empty()
+    {
         map.remove(key);
-        return None;
     }
-
-    // Now we can safely return a reference because we know it's valid.
-    map.get(key)
 }
 
-fn handle_connection(mut stream: TcpStream, db: Db, cv: Cv) -> IoResult<()> {
-    let mut buffer = [0; 1024];
-    loop {
-        let bytes_read = stream.read(&mut buffer)?;
-        if bytes_read == 0 {
-            break;
-        }
+fn get_valid_entry<'a>(map: &'a mut Dict<Entry>, key: &str) -> Option<&'a Entry> {
+    expire_if_needed(map, key);
 
-        let input = String::from_utf8_lossy(&buffer[..bytes_read]);
+    // Now we can safely return a reference because we know it's valid.
+    // Looking a key up counts as accessing it.
+    let entry = map.get_mut(key)?;
+    entry.last_access = Instant::now();
+    Some(entry)
+}
 
-        if let Some(command) = parse_message(&input) {
-            println!("Received command: {:?}", command);
+// Takes up to `count` elements off `end` of the list, in the order they come off
+fn pop_list(list: &mut VecDeque<String>, end: ListEnd, count: usize) -> Vec<String> {
+    let count = count.min(list.len());
+    match end {
+        ListEnd::Left => list.drain(..count).collect(),
+        ListEnd::Right => list.drain(list.len() - count..).rev().collect(),
+    }
+}
 
-            match command {
-                Command::Ping => {
-                    stream.write_resp(Resp::string("PONG"))?;
+// Deletes `key` if it holds a collection that's been emptied; Redis never keeps empty ones around
+fn delete_if_empty(map: &mut Dict<Entry>, key: &str) {
+    let empty = match map.get(key).map(|entry| &entry.value) {
+        Some(RedisValue::List(list)) => list.is_empty(),
+        Some(RedisValue::Hash(hash)) => hash.is_empty(),
+        Some(RedisValue::Set(set)) => set.is_empty(),
+        Some(RedisValue::SortedSet(zset)) => zset.is_empty(),
+        _ => false,
+    };
+    if empty {
+        map.remove(key);
+    }
+}
+
+// The sets at `keys`, with `empty` standing in for missing keys. WRONGTYPE if any of
+// them holds something else.
+fn sets_at<'a>(map: &'a mut Dict<Entry>, keys: &[String], empty: &'a RedisSet) -> Result<Vec<&'a RedisSet>, Reply> {
+    for key in keys {
+        let _ = get_valid_entry(map, key);
+    }
+    let map = &*map;
+    keys.iter()
+        .map(|key| match map.get(key).map(|entry| &entry.value) {
+            Some(RedisValue::Set(set)) => Ok(set),
+            Some(_) => Err(Reply::WRONGTYPE),
+            None => Ok(empty),
+        })
+        .collect()
+}
+
+// The result of SINTER, SUNION or SDIFF over the sets at `keys`, of which there's at
+// least one. Missing keys count as empty sets.
+fn combine_sets(map: &mut Dict<Entry>, op: SetOp, keys: &[String]) -> Result<RedisSet, Reply> {
+    let empty = RedisSet::new();
+    let sets = sets_at(map, keys, &empty)?;
+
+    let (first, rest) = sets.split_first().expect("at least one key");
+    let members = match op {
+        // Walking the smallest set means the fewest lookups
+        SetOp::Inter => {
+            let smallest = sets.iter().min_by_key(|set| set.len()).unwrap();
+            smallest.iter().filter(|member| sets.iter().all(|set| set.contains(member))).map(Cow::into_owned).collect()
+        }
+        SetOp::Union => sets.iter().flat_map(|set| set.iter()).map(Cow::into_owned).collect(),
+        SetOp::Diff => first.iter().filter(|member| !rest.iter().any(|set| set.contains(member))).map(Cow::into_owned).collect(),
+    };
+    Ok(members)
+}
+
+// A random index below `len`, which mustn't be 0. Each RandomState is freshly keyed,
+// which is all the randomness this needs.
+fn random_index(len: usize) -> usize {
+    (RandomState::new().hash_one(0) % len as u64) as usize
+}
+
+// Counts HRANDFIELD and friends accept: within ±i64::MAX/2 like Redis, and negative ones
+// (which repeat picks rather than running out) no bigger than MAX_REPEATED_PICKS, since
+// the whole reply is built while the keyspace is locked
+fn random_count_in_range(count: i64) -> bool {
+    let limit = if count < 0 { MAX_REPEATED_PICKS } else { i64::MAX as u64 / 2 };
+    count.unsigned_abs() <= limit
+}
+
+// `count` items picked at random the way Redis' *RAND* commands do: distinct ones (all
+// of them at most) if `count` is positive, or with repeats if it's negative
+fn random_picks<T: Clone>(items: &[T], count: i64) -> Vec<T> {
+    if items.is_empty() {
+        return Vec::new();
+    }
+    if count < 0 {
+        return (0..count.unsigned_abs()).map(|_| items[random_index(items.len())].clone()).collect();
+    }
+
+    // A partial Fisher-Yates shuffle
+    let mut items = items.to_vec();
+    let count = (count as usize).min(items.len());
+    for i in 0..count {
+        let j = i + random_index(items.len() - i);
+        items.swap(i, j);
+    }
+    items.truncate(count);
+    items
+}
+
+// The indexes a start/stop pair selects, LRANGE-style: negative ones count from the
+// end and both are clamped to the collection. None if that leaves nothing.
+fn index_range(len: usize, start: i64, stop: i64) -> Option<RangeInclusive<usize>> {
+    let len = len as i64;
+    let start = (if start < 0 { len + start } else { start }).max(0);
+    let stop = (if stop < 0 { len + stop } else { stop }).min(len - 1);
+    (start <= stop).then_some(start as usize..=stop as usize)
+}
+
+// Resolves a possibly negative list index, None if it's out of range
+fn list_position(len: usize, index: i64) -> Option<usize> {
+    let index = if index < 0 { len as i64 + index } else { index };
+    usize::try_from(index).ok().filter(|&index| index < len)
+}
+
+// Overwrites `s` with `value` from byte `offset`, padding with zero bytes if `s` is shorter
+fn splice_string(s: &str, offset: usize, value: &str) -> String {
+    let mut bytes = s.as_bytes().to_vec();
+    if bytes.len() < offset + value.len() {
+        bytes.resize(offset + value.len(), 0);
+    }
+    bytes[offset..offset + value.len()].copy_from_slice(value.as_bytes());
+    // Values are kept as Strings, so a write into the middle of a character can't be kept as-is
+    String::from_utf8(bytes).unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned())
+}
+
+// A run of the LCS as (start, end) byte ranges, inclusive, in each of the two strings
+type LcsMatch = ((usize, usize), (usize, usize));
+
+// The longest common subsequence of `a` and `b`, and the (start, end) byte ranges of
+// the runs it's made of in each, last run first as Redis reports them. Runs shorter
+// than `min_match_len` are left out of the ranges but still count towards the result.
+fn lcs(a: &[u8], b: &[u8], min_match_len: usize) -> (Vec<u8>, Vec<LcsMatch>) {
+    // lengths[i][j] is the LCS length of a[..i] and b[..j]
+    let width = b.len() + 1;
+    let mut lengths = vec![0u32; (a.len() + 1) * width];
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            lengths[i * width + j] = if a[i - 1] == b[j - 1] {
+                lengths[(i - 1) * width + j - 1] + 1
+            } else {
+                lengths[(i - 1) * width + j].max(lengths[i * width + j - 1])
+            };
+        }
+    }
+
+    // Walk back from the end, collecting the subsequence and its contiguous runs
+    let mut common = Vec::new();
+    let mut matches = Vec::new();
+    let mut run: Option<LcsMatch> = None;
+    let (mut i, mut j) = (a.len(), b.len());
+    while i > 0 && j > 0 {
+        let mut run_ended = false;
+        if a[i - 1] == b[j - 1] {
+            common.push(a[i - 1]);
+            run = match run {
+                Some(((a_start, a_end), (b_start, b_end))) if a_start == i && b_start == j => {
+                    Some(((a_start - 1, a_end), (b_start - 1, b_end)))
                 }
-                Command::Echo(content) => {
-                    stream.write_resp(Resp::bulk_string(&content))?;
+                Some(_) => {
+                    run_ended = true;
+                    run
                 }
-                Command::Set { key, value, px } => {
+                None => Some(((i - 1, i - 1), (j - 1, j - 1))),
+            };
+            // A run touching the start of either string can't grow any further
+            run_ended |= i == 1 || j == 1;
+            i -= 1;
+            j -= 1;
+        } else {
+            if lengths[(i - 1) * width + j] > lengths[i * width + j - 1] {
+                i -= 1;
+            } else {
+                j -= 1;
+            }
+            run_ended = run.is_some();
+        }
+
+        // The run is taken even when it's too short to report, so the next one starts afresh
+        if run_ended
+            && let Some(range @ ((a_start, a_end), _)) = run.take()
+            && a_end - a_start + 1 >= min_match_len
+        {
+            matches.push(range);
+        }
+    }
+
+    common.reverse();
+    (common, matches)
+}
+
+// The integer a string holds if it's written the way Redis would print it, with no
+// sign or leading zeros to lose; only those can be stored as integers
+fn integer_value(s: &str) -> Option<i64> {
+    s.parse::<i64>().ok().filter(|n| n.to_string() == s)
+}
+
+fn unix_time_ms() -> i64 {
+    SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_millis() as i64
+}
+
+// The Unix time in milliseconds that `value` units (of `unit_ms` each) name, counted from
+// now or from the epoch. None if it overflows.
+fn expiry_time_ms(value: i64, unit_ms: i64, absolute: bool) -> Option<i64> {
+    let ms = value.checked_mul(unit_ms)?;
+    if absolute { Some(ms) } else { ms.checked_add(unix_time_ms()) }
+}
+
+// When an expiry option makes a key expire, as a Unix time in milliseconds: None for no TTL
+// (or KEEPTTL, which keeps the old one). Times in the past are accepted and expire the key
+// straight away.
+fn expiry_time(expiry: Option<&Expiry>, command: &str) -> Result<Option<i64>, Reply> {
+    let Some((value, unit_ms, absolute)) = expiry.and_then(Expiry::parts) else {
+        return Ok(None);
+    };
+    match expiry_time_ms(value, unit_ms, absolute) {
+        Some(at) if value > 0 => Ok(Some(at)),
+        _ => Err(Reply::error(format!("ERR invalid expire time in '{}' command", command))),
+    }
+}
+
+// Milliseconds until `entry` expires, None if it has no TTL
+fn remaining_ttl_ms(entry: &Entry) -> Option<i64> {
+    entry.expires_at.map(|at| (at - unix_time_ms()).max(0))
+}
+
+// Sorted set members for ZRANGE and friends, with their scores if asked: [member, score]
+// pairs for RESP3 clients, [member, score, ...] for RESP2 ones
+fn scored_members_reply(items: Vec<(&String, f64)>, withscores: bool) -> Reply {
+    if !withscores {
+        Reply::bulks(items.into_iter().map(|(member, _)| member))
+    } else if resp::protocol() == 3 {
+        Reply::Array(items.into_iter().map(|(member, score)| Reply::Array(vec![Reply::bulk(member), Reply::Double(score)])).collect())
+    } else {
+        Reply::Array(items.into_iter().flat_map(|(member, score)| [Reply::bulk(member), Reply::Double(score)]).collect())
+    }
+}
+
+// Encodes time-series samples as [[timestamp, value], ...]
+fn ts_samples_reply(samples: &[(u64, f64)]) -> Reply {
+    Reply::Array(
+        samples
+            .iter()
+            .map(|(ts, value)| Reply::Array(vec![Reply::integer(*ts), Reply::Double(*value)]))
+            .collect(),
+    )
+}
+
+// Each stream entry is a 2-element array: [ID, [field1, value1, field2, value2...]]
+fn stream_entry_reply(entry: &StreamEntry) -> Reply {
+    Reply::Array(vec![
+        Reply::Bulk(format!("{}-{}", entry.id_ms, entry.id_seq)),
+        Reply::bulks(entry.fields.iter().flat_map(|(f, v)| [f, v])),
+    ])
+}
+
+fn handle_connection<S: Read + Write>(stream: S, addr: String, db: Db, blocked: Blocked, clients: Clients) -> IoResult<()> {
+    let client_id = NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed);
+    clients.lock().unwrap().insert(
+        client_id,
+        ClientInfo {
+            addr,
+            connected_at: Instant::now(),
+            lib_name: None,
+            lib_ver: None,
+            name: None,
+        },
+    );
+    // Threads can be reused for several connections (e.g. HTTP sessions); start each at RESP2
+    resp::set_protocol(2);
+    let _guard = ClientGuard { id: client_id, clients: Arc::clone(&clients) };
+
+    let mut stream = PooledStream::new(stream);
+    // Read in the same 16KB chunks as Redis so big values don't take thousands of reads
+    let mut buffer = bufpool::get(16 * 1024);
+    buffer.resize(16 * 1024, 0);
+    // Bytes received but not yet executed. A single read may carry a whole pipeline,
+    // so commands are consumed in place and the buffer is only compacted before the next read.
+    let mut pending = bufpool::get(1024);
+    let mut consumed = 0;
+    loop {
+        let (request_len, argv) = match frame::next_request(&pending[consumed..]) {
+            Ok(Some(request)) => request,
+            Ok(None) => {
+                // Only part of a command left; keep it and wait for the rest
+                pending.drain(..consumed);
+                consumed = 0;
+                let bytes_read = stream.read(&mut buffer)?;
+                if bytes_read == 0 {
+                    break;
+                }
+                pending.extend_from_slice(&buffer[..bytes_read]);
+                if pending.len() > config::query_buffer_limit() {
+                    println!("closing client {} that reached max query buffer length", client_id);
+                    break;
+                }
+                continue;
+            }
+            Err(e) => {
+                // There's no telling where the next command starts after a malformed one,
+                // so like Redis, report it and hang up
+                stream.write_reply(&Reply::error(format!("ERR Protocol error: {}", e)))?;
+                stream.flush()?;
+                break;
+            }
+        };
+
+        consumed += request_len;
+
+        let Some(request) = Request::from_argv(argv) else {
+            continue; // Redis ignores empty multibulk requests
+        };
+        if let Err(e) = commands::check(&request) {
+            stream.write_reply(&Reply::error(e))?;
+            continue;
+        }
+
+        if let Some(command) = parse_message(&request) {
+            println!("Received command: {:?}", command);
+
+            // The name as sent by the client keys the latency histograms
+            let command_name = request.name.to_lowercase();
+            let started = Instant::now();
+
+            if loading::refuses_commands() && !command.allowed_while_loading() {
+                stream.write_reply(&Reply::error("LOADING Redis is loading the dataset in memory"))?;
+                continue;
+            }
+
+            let reply = match command {
+                Command::Ping => Reply::PONG,
+                Command::Echo(content) => Reply::Bulk(content),
+                Command::Set { key, value, expiry, condition, get } => match expiry_time(expiry.as_ref(), &command_name) {
+                    Err(reply) => reply,
+                    Ok(expires_at) => {
+                        let mut db_lock = db.lock().unwrap();
+
+                        let existing = get_valid_entry(&mut db_lock, &key);
+                        let exists = existing.is_some();
+                        let old_value = match existing.map(|entry| &entry.value) {
+                            Some(RedisValue::String(s)) => Reply::bulk(s.as_str()),
+                            Some(_) => Reply::WRONGTYPE,
+                            None => Reply::Null,
+                        };
+                        let expires_at = match (&expiry, existing) {
+                            (Some(Expiry::KeepTtl), Some(entry)) => entry.expires_at,
+                            _ => expires_at,
+                        };
+                        match condition {
+                            // GET refuses to overwrite anything but a string
+                            _ if get && old_value == Reply::WRONGTYPE => Reply::WRONGTYPE,
+                            Some(SetCondition::IfMissing) if exists => if get { old_value } else { Reply::Null },
+                            Some(SetCondition::IfExists) if !exists => Reply::Null,
+                            _ => {
+                                db_lock.insert(
+                                    key,
+                                    Entry {
+                                        value: RedisValue::String(value),
+                                        expires_at,
+                                        last_access: Instant::now(),
+                                        flags: 0,
+                                    },
+                                );
+                                if get { old_value } else { Reply::OK }
+                            }
+                        }
+                    }
+                },
+                Command::Setnx { key, value } => {
                     let mut db_lock = db.lock().unwrap();
 
-                    db_lock.insert(
-                        key,
-                        Entry {
-                            value: RedisValue::String(value),
-                            created_at: Instant::now(),
-                            expires_in: px.map(Duration::from_millis),
-                        },
-                    );
-                    stream.write_resp(Resp::string("OK"))?;
+                    if get_valid_entry(&mut db_lock, &key).is_some() {
+                        Reply::Integer(0)
+                    } else {
+                        db_lock.insert(
+                            key,
+                            Entry {
+                                value: RedisValue::String(value),
+                                expires_at: None,
+                                last_access: Instant::now(),
+                                flags: 0,
+                            },
+                        );
+                        Reply::Integer(1)
+                    }
                 }
                 Command::Get(key) => {
                     let mut db_lock = db.lock().unwrap();
 
                     match get_valid_entry(&mut db_lock, &key) {
                         Some(entry) => match &entry.value {
-                            RedisValue::String(s) => {
-                                stream.write_resp(Resp::bulk_string(s))?;
+                            RedisValue::String(s) => Reply::bulk(s.as_str()),
+                            _ => Reply::WRONGTYPE,
+                        },
+                        None => Reply::Null,
+                    }
+                }
+                Command::Incrby { increment: None, .. } => {
+                    Reply::error("ERR value is not an integer or out of range")
+                }
+                Command::Incrby { key, increment: Some(increment) } => {
+                    let mut map = db.lock().unwrap();
+                    let _ = get_valid_entry(&mut map, &key);
+
+                    // Missing keys count as 0; an existing key keeps its expiry
+                    let entry = map.entry(key).or_insert(Entry {
+                        value: RedisValue::String(String::from("0")),
+                        expires_at: None,
+                        last_access: Instant::now(),
+                        flags: 0,
+                    });
+                    match &mut entry.value {
+                        RedisValue::String(s) => match s.parse::<i64>() {
+                            Ok(n) => match n.checked_add(increment) {
+                                Some(n) => {
+                                    *s = n.to_string();
+                                    Reply::Integer(n)
+                                }
+                                None => Reply::error("ERR increment or decrement would overflow"),
+                            },
+                            Err(_) => Reply::error("ERR value is not an integer or out of range"),
+                        },
+                        _ => Reply::WRONGTYPE,
+                    }
+                }
+                Command::Incrbyfloat { increment: None, .. } => Reply::error("ERR value is not a valid float"),
+                Command::Incrbyfloat { key, increment: Some(increment) } => {
+                    let mut map = db.lock().unwrap();
+                    let _ = get_valid_entry(&mut map, &key);
+
+                    // Work out the new value before touching the key, so errors leave it as it was
+                    let result = match map.get(&key).map(|entry| &entry.value) {
+                        Some(RedisValue::String(s)) => s.parse::<f64>().map_err(|_| Reply::error("ERR value is not a valid float")),
+                        Some(_) => Err(Reply::WRONGTYPE),
+                        None => Ok(0.0),
+                    }
+                    .map(|n| n + increment)
+                    .and_then(|n| {
+                        if n.is_finite() { Ok(n) } else { Err(Reply::error("ERR increment would produce NaN or Infinity")) }
+                    });
+
+                    match result {
+                        Ok(n) => {
+                            // Display gives the shortest form that round-trips: no exponent, no trailing zeros
+                            let value = n.to_string();
+                            // Missing keys start at 0; an existing key keeps its expiry
+                            let entry = map.entry(key).or_insert(Entry {
+                                value: RedisValue::String(String::new()),
+                                expires_at: None,
+                                last_access: Instant::now(),
+                                flags: 0,
+                            });
+                            entry.value = RedisValue::String(value.clone());
+                            Reply::Bulk(value)
+                        }
+                        Err(reply) => reply,
+                    }
+                }
+                Command::Append { key, value } => {
+                    let mut map = db.lock().unwrap();
+                    let _ = get_valid_entry(&mut map, &key);
+
+                    let entry = map.entry(key).or_insert(Entry {
+                        value: RedisValue::String(String::new()),
+                        expires_at: None,
+                        last_access: Instant::now(),
+                        flags: 0,
+                    });
+                    match &mut entry.value {
+                        RedisValue::String(s) => {
+                            s.push_str(&value);
+                            Reply::integer(s.len())
+                        }
+                        _ => Reply::WRONGTYPE,
+                    }
+                }
+                Command::Strlen(key) => {
+                    let mut map = db.lock().unwrap();
+
+                    match get_valid_entry(&mut map, &key) {
+                        Some(entry) => match &entry.value {
+                            RedisValue::String(s) => Reply::integer(s.len()),
+                            _ => Reply::WRONGTYPE,
+                        },
+                        None => Reply::Integer(0),
+                    }
+                }
+                Command::Getrange { key, start, end } => {
+                    let mut map = db.lock().unwrap();
+
+                    match get_valid_entry(&mut map, &key) {
+                        Some(Entry { value: RedisValue::String(s), .. }) => {
+                            // Byte offsets; negative ones count from the end, both ends inclusive
+                            let len = s.len() as i64;
+                            let start = if start < 0 { (len + start).max(0) } else { start };
+                            let end = if end < 0 { (len + end).max(0) } else { end.min(len - 1) };
+                            if start > end || len == 0 {
+                                Reply::bulk("")
+                            } else {
+                                Reply::bulk(String::from_utf8_lossy(&s.as_bytes()[start as usize..=end as usize]))
                             }
-                            _ => {
-                                stream.write_resp(Resp::error("WRONGTYPE Operation against a key holding the wrong kind of value"))?;
+                        }
+                        Some(_) => Reply::WRONGTYPE,
+                        None => Reply::bulk(""),
+                    }
+                }
+                Command::Setrange { key, offset, value } => {
+                    let mut map = db.lock().unwrap();
+                    let _ = get_valid_entry(&mut map, &key);
+
+                    if offset < 0 {
+                        Reply::error("ERR offset is out of range")
+                    } else if offset as usize + value.len() > config::proto_max_bulk_len() {
+                        Reply::error("ERR string exceeds maximum allowed size (proto-max-bulk-len)")
+                    } else {
+                        match map.get_mut(&key) {
+                            Some(Entry { value: RedisValue::String(s), .. }) => {
+                                if !value.is_empty() {
+                                    *s = splice_string(s, offset as usize, &value);
+                                }
+                                Reply::integer(s.len())
                             }
+                            Some(_) => Reply::WRONGTYPE,
+                            // An empty value doesn't create the key
+                            None if value.is_empty() => Reply::Integer(0),
+                            None => {
+                                let s = splice_string("", offset as usize, &value);
+                                let len = s.len();
+                                map.insert(
+                                    key,
+                                    Entry {
+                                        value: RedisValue::String(s),
+                                        expires_at: None,
+                                        last_access: Instant::now(),
+                                        flags: 0,
+                                    },
+                                );
+                                Reply::integer(len)
+                            }
+                        }
+                    }
+                }
+                Command::Getdel(key) => {
+                    let mut map = db.lock().unwrap();
+
+                    match get_valid_entry(&mut map, &key).map(|entry| &entry.value) {
+                        Some(RedisValue::String(_)) => match map.remove(&key).map(|entry| entry.value) {
+                            Some(RedisValue::String(s)) => Reply::Bulk(s),
+                            _ => Reply::Null,
                         },
-                        None => {
-                            stream.write_resp(Resp::null_bulk())?;
+                        Some(_) => Reply::WRONGTYPE,
+                        None => Reply::Null,
+                    }
+                }
+                Command::Getex { key, expiry } => match expiry_time(expiry.as_ref(), &command_name) {
+                    Err(reply) => reply,
+                    Ok(expires_at) => {
+                        let mut map = db.lock().unwrap();
+                        let _ = get_valid_entry(&mut map, &key);
+
+                        match map.get_mut(&key) {
+                            Some(entry) => match &entry.value {
+                                RedisValue::String(s) => {
+                                    let reply = Reply::bulk(s.as_str());
+                                    if expiry.is_some() {
+                                        entry.expires_at = expires_at;
+                                    }
+                                    reply
+                                }
+                                _ => Reply::WRONGTYPE,
+                            },
+                            None => Reply::Null,
+                        }
+                    }
+                },
+                Command::Lcs { key1, key2, len, idx, min_match_len, with_match_len } => {
+                    let mut map = db.lock().unwrap();
+
+                    // Missing keys are empty strings
+                    let mut values = Vec::new();
+                    for key in [&key1, &key2] {
+                        match get_valid_entry(&mut map, key).map(|entry| &entry.value) {
+                            Some(RedisValue::String(s)) => values.push(s.clone()),
+                            Some(_) => break,
+                            None => values.push(String::new()),
+                        }
+                    }
+
+                    if values.len() < 2 {
+                        Reply::error("ERR The specified keys must contain string values")
+                    } else if len && idx {
+                        Reply::error("ERR If you want both the length and indexes, please just use IDX.")
+                    } else if (values[0].len() + 1).saturating_mul(values[1].len() + 1).saturating_mul(4)
+                        > config::proto_max_bulk_len()
+                    {
+                        Reply::error("ERR Insufficient memory, transient memory for LCS exceeds proto-max-bulk-len")
+                    } else {
+                        let (common, matches) = lcs(values[0].as_bytes(), values[1].as_bytes(), min_match_len);
+                        if idx {
+                            let matches = matches
+                                .into_iter()
+                                .map(|((a_start, a_end), (b_start, b_end))| {
+                                    let mut reply = vec![
+                                        Reply::Array(vec![Reply::integer(a_start), Reply::integer(a_end)]),
+                                        Reply::Array(vec![Reply::integer(b_start), Reply::integer(b_end)]),
+                                    ];
+                                    if with_match_len {
+                                        reply.push(Reply::integer(a_end - a_start + 1));
+                                    }
+                                    Reply::Array(reply)
+                                })
+                                .collect();
+                            Reply::Map(vec![
+                                (Reply::bulk("matches"), Reply::Array(matches)),
+                                (Reply::bulk("len"), Reply::integer(common.len())),
+                            ])
+                        } else if len {
+                            Reply::integer(common.len())
+                        } else {
+                            Reply::bulk(String::from_utf8_lossy(&common))
                         }
                     }
                 }
+                Command::Del(keys) => {
+                    let mut map = db.lock().unwrap();
+
+                    // Expired keys don't count as deleted
+                    let deleted = keys
+                        .iter()
+                        .filter(|key| get_valid_entry(&mut map, key).is_some() && map.remove(key).is_some())
+                        .count();
+                    Reply::integer(deleted)
+                }
+                Command::Unlink(keys) => {
+                    let mut map = db.lock().unwrap();
+
+                    let mut removed = Vec::new();
+                    for key in &keys {
+                        if get_valid_entry(&mut map, key).is_some() {
+                            removed.extend(map.remove(key));
+                        }
+                    }
+                    drop(map);
+
+                    // Big values are dropped on the lazyfree thread, so nobody waits on the lock meanwhile
+                    let unlinked = removed.len();
+                    for entry in removed {
+                        let effort = entry.value.free_effort();
+                        lazyfree::free(entry, effort);
+                    }
+                    Reply::integer(unlinked)
+                }
+                Command::Exists(keys) => {
+                    let mut map = db.lock().unwrap();
+
+                    // A key named twice counts twice
+                    let existing = keys.iter().filter(|key| get_valid_entry(&mut map, key).is_some()).count();
+                    Reply::integer(existing)
+                }
+                Command::Keys(pattern) => {
+                    let map = db.lock().unwrap();
+
+                    let now = unix_time_ms();
+                    let keys = map
+                        .iter()
+                        .filter(|(_, entry)| entry.expires_at.is_none_or(|at| at >= now))
+                        .filter(|(key, _)| glob::matches(pattern.as_bytes(), key.as_bytes()))
+                        .map(|(key, _)| key.as_str());
+                    Reply::bulks(keys)
+                }
+                Command::Scan { cursor, pattern, count, type_name } => {
+                    let map = db.lock().unwrap();
+
+                    let now = unix_time_ms();
+                    let (cursor, entries) = map.scan(cursor, count);
+                    let keys = entries
+                        .into_iter()
+                        .filter(|(_, entry)| entry.expires_at.is_none_or(|at| at >= now))
+                        .filter(|(key, _)| pattern.as_ref().is_none_or(|pattern| glob::matches(pattern.as_bytes(), key.as_bytes())))
+                        .filter(|(_, entry)| type_name.as_ref().is_none_or(|name| name.eq_ignore_ascii_case(entry.value.type_name())))
+                        .map(|(key, _)| key.as_str());
+                    Reply::Array(vec![Reply::bulk(cursor.to_string()), Reply::bulks(keys)])
+                }
+                Command::Rename { key, new_key, nx } => {
+                    let mut map = db.lock().unwrap();
+
+                    if get_valid_entry(&mut map, &key).is_none() {
+                        Reply::error("ERR no such key")
+                    } else if nx && get_valid_entry(&mut map, &new_key).is_some() {
+                        Reply::Integer(0)
+                    } else {
+                        // The entry moves as a whole, TTL included
+                        if key != new_key {
+                            let entry = map.remove(&key).unwrap();
+                            map.insert(new_key.clone(), entry);
+                            blocked.signal(&new_key); // A list may have moved under a key BLPOP is waiting on
+                        }
+                        if nx { Reply::Integer(1) } else { Reply::OK }
+                    }
+                }
+                Command::Randomkey => {
+                    let mut map = db.lock().unwrap();
+
+                    // Expired keys drawn along the way are deleted, so this ends even if they all are
+                    loop {
+                        let Some(key) = map.random_key().cloned() else {
+                            break Reply::Null;
+                        };
+                        if get_valid_entry(&mut map, &key).is_some() {
+                            break Reply::Bulk(key);
+                        }
+                    }
+                }
+                Command::Touch(keys) => {
+                    let mut map = db.lock().unwrap();
+
+                    // get_valid_entry updates the access time of the ones that exist
+                    let touched = keys.iter().filter(|key| get_valid_entry(&mut map, key).is_some()).count();
+                    Reply::integer(touched)
+                }
+                Command::ObjectEncoding(key) => {
+                    let mut map = db.lock().unwrap();
+                    expire_if_needed(&mut map, &key);
+
+                    match map.get(&key) {
+                        Some(entry) => Reply::bulk(entry.value.encoding()),
+                        None => Reply::Null,
+                    }
+                }
+                Command::ObjectRefcount(key) => {
+                    let mut map = db.lock().unwrap();
+                    expire_if_needed(&mut map, &key);
+
+                    match map.get(&key) {
+                        // Redis shares small integers between keys and reports them as never freed
+                        Some(entry) if entry.value.is_shared_integer() => Reply::Integer(i32::MAX as i64),
+                        Some(_) => Reply::Integer(1),
+                        None => Reply::Null,
+                    }
+                }
+                Command::ObjectIdletime(key) => {
+                    let mut map = db.lock().unwrap();
+                    expire_if_needed(&mut map, &key);
+
+                    match map.get(&key) {
+                        Some(entry) => Reply::integer(entry.last_access.elapsed().as_secs()),
+                        None => Reply::Null,
+                    }
+                }
+                Command::ObjectFreq(key) => {
+                    let mut map = db.lock().unwrap();
+                    expire_if_needed(&mut map, &key);
+
+                    match map.get(&key) {
+                        Some(_) => Reply::error(
+                            "ERR An LFU maxmemory policy is not selected, access frequency not tracked. \
+                             Please note that when switching between policies at runtime LRU and LFU data \
+                             will take some time to adjust.",
+                        ),
+                        None => Reply::Null,
+                    }
+                }
+                Command::MemoryUsage { key, samples } => {
+                    let mut map = db.lock().unwrap();
+                    expire_if_needed(&mut map, &key);
+
+                    match map.get(&key) {
+                        Some(entry) => {
+                            let overhead = std::mem::size_of::<Entry>() + std::mem::size_of::<String>() + key.len();
+                            Reply::integer(overhead + entry.value.memory_usage(samples))
+                        }
+                        None => Reply::Null,
+                    }
+                }
+                Command::Dump(key) => {
+                    let mut map = db.lock().unwrap();
+
+                    match get_valid_entry(&mut map, &key) {
+                        Some(entry) => match dump::dump(&entry.value) {
+                            Some(payload) => Reply::Bulk(payload),
+                            None => Reply::error("ERR DUMP is not supported for this type"),
+                        },
+                        None => Reply::Null,
+                    }
+                }
+                Command::Restore { key, ttl, payload, replace, absttl, idletime } => {
+                    let mut map = db.lock().unwrap();
+
+                    if ttl < 0 {
+                        Reply::error("ERR Invalid TTL value, must be >= 0")
+                    } else if !replace && get_valid_entry(&mut map, &key).is_some() {
+                        Reply::error("BUSYKEY Target key name already exists.")
+                    } else {
+                        match dump::restore(&payload) {
+                            Ok(value) => {
+                                let expires_at = match ttl {
+                                    0 => None,
+                                    ttl if absttl => Some(ttl),
+                                    ttl => Some(unix_time_ms().saturating_add(ttl)),
+                                };
+                                // A TTL that has already run out restores nothing, but still replaces
+                                if expires_at.is_some_and(|at| at <= unix_time_ms()) {
+                                    map.remove(&key);
+                                } else {
+                                    let idle = Duration::from_secs(idletime.unwrap_or(0));
+                                    let last_access = Instant::now().checked_sub(idle).unwrap_or_else(Instant::now);
+                                    map.insert(key.clone(), Entry { value, expires_at, last_access, flags: 0 });
+                                    blocked.signal(&key); // The key may be a list BLPOP is waiting on
+                                }
+                                Reply::OK
+                            }
+                            Err(dump::RestoreError::Checksum) => {
+                                Reply::error("ERR DUMP payload version or checksum are wrong")
+                            }
+                            Err(dump::RestoreError::Format) => Reply::error("ERR Bad data format"),
+                        }
+                    }
+                }
+                Command::Ttl { key, millis } => {
+                    let mut map = db.lock().unwrap();
+
+                    match get_valid_entry(&mut map, &key).map(remaining_ttl_ms) {
+                        Some(Some(ms)) if millis => Reply::Integer(ms),
+                        // Rounded to the nearest second, as Redis does
+                        Some(Some(ms)) => Reply::Integer((ms + 500) / 1000),
+                        Some(None) => Reply::Integer(-1),
+                        None => Reply::Integer(-2),
+                    }
+                }
+                Command::Expiretime { key, millis } => {
+                    let mut map = db.lock().unwrap();
+
+                    match get_valid_entry(&mut map, &key).map(|entry| entry.expires_at) {
+                        Some(Some(at)) if millis => Reply::Integer(at),
+                        Some(Some(at)) => Reply::Integer(at / 1000),
+                        Some(None) => Reply::Integer(-1),
+                        None => Reply::Integer(-2),
+                    }
+                }
+                Command::Expire { key, expiry, nx, xx, gt, lt } => {
+                    let at = expiry.parts().and_then(|(value, unit_ms, absolute)| expiry_time_ms(value, unit_ms, absolute));
+
+                    if nx && (xx || gt || lt) {
+                        Reply::error("ERR NX and XX, GT or LT options at the same time are not compatible")
+                    } else if gt && lt {
+                        Reply::error("ERR GT and LT options at the same time are not compatible")
+                    } else if let Some(at) = at {
+                        let mut map = db.lock().unwrap();
+                        let _ = get_valid_entry(&mut map, &key);
+
+                        match map.get_mut(&key) {
+                            Some(entry) => {
+                                let allowed = match entry.expires_at {
+                                    Some(current) => !nx && (!gt || at > current) && (!lt || at < current),
+                                    None => !xx && !gt,
+                                };
+                                if !allowed {
+                                    Reply::Integer(0)
+                                } else if at <= unix_time_ms() {
+                                    // A time that has already passed deletes the key
+                                    map.remove(&key);
+                                    Reply::Integer(1)
+                                } else {
+                                    entry.expires_at = Some(at);
+                                    Reply::Integer(1)
+                                }
+                            }
+                            None => Reply::Integer(0),
+                        }
+                    } else {
+                        Reply::error(format!("ERR invalid expire time in '{}' command", command_name))
+                    }
+                }
                 Command::Rpush { key, values } => {
                     let mut map = db.lock().unwrap();
+                    let _ = get_valid_entry(&mut map, &key);
 
-                    let entry = map.entry(key).or_insert(Entry {
-                        value: RedisValue::List(Vec::new()),
-                        created_at: Instant::now(),
-                        expires_in: None,
+                    let entry = map.entry(key.clone()).or_insert(Entry {
+                        value: RedisValue::List(VecDeque::new()),
+                        expires_at: None,
+                        last_access: Instant::now(),
+                        flags: 0,
                     });
 
                     if let RedisValue::List(ref mut list) = entry.value {
                         for val in values {
-                            list.push(val);
+                            list.push_back(val);
                         }
-                        // RESP Integer format: ":<number>\r\n"
-                        stream.write_resp(Resp::integer(list.len()))?;
-
-                        cv.notify_all(); // Wake up any BLPOP waiters
+                        blocked.signal(&key); // Wake up any BLPOP/BRPOP waiters
+                        Reply::integer(list.len())
                     } else {
                         // Technically Redis returns an error if you RPUSH to a key
                         // that already holds a String, but for now, we can just return an error.
-                        stream.write_resp(Resp::error(
-                            "WRONGTYPE Operation against a key holding the wrong kind of value",
-                        ))?;
+                        Reply::WRONGTYPE
                     }
                 }
                 Command::Lpush { key, values } => {
                     let mut map = db.lock().unwrap();
+                    let _ = get_valid_entry(&mut map, &key);
 
-                    let entry = map.entry(key).or_insert(Entry {
-                        value: RedisValue::List(Vec::new()),
-                        created_at: Instant::now(),
-                        expires_in: None,
+                    let entry = map.entry(key.clone()).or_insert(Entry {
+                        value: RedisValue::List(VecDeque::new()),
+                        expires_at: None,
+                        last_access: Instant::now(),
+                        flags: 0,
                     });
 
                     if let RedisValue::List(ref mut list) = entry.value {
                         for val in values {
-                            list.insert(0, val);
+                            list.push_front(val);
                         }
-                        stream.write_resp(Resp::integer(list.len()))?;
-
-                        cv.notify_all(); // Wake up any BLPOP waiters
+                        blocked.signal(&key); // Wake up any BLPOP/BRPOP waiters
+                        Reply::integer(list.len())
                     } else {
-                        stream.write_resp(Resp::error(
-                            "WRONGTYPE Operation against a key holding the wrong kind of value",
-                        ))?;
+                        Reply::WRONGTYPE
                     }
                 }
                 Command::Lrange { key, start, stop } => {
-                    let db_lock = db.lock().unwrap();
+                    let mut db_lock = db.lock().unwrap();
 
-                    match db_lock.get(&key) {
+                    match get_valid_entry(&mut db_lock, &key) {
                         Some(entry) => {
                             if let RedisValue::List(ref list) = entry.value {
                                 let len = list.len() as i64;
 
-                                // Normalize and clamp in one step per variable
-                                let start_idx = (if start < 0 { len + start } else { start })
-                                    .clamp(0, len)
-                                    as usize;
-                                let stop_idx = (if stop < 0 { len + stop } else { stop })
-                                    .clamp(0, len - 1)
-                                    as usize;
+                                // Normalize and clamp in one step per variable
+                                let start_idx = (if start < 0 { len + start } else { start })
+                                    .clamp(0, len)
+                                    as usize;
+                                let stop_idx = (if stop < 0 { len + stop } else { stop })
+                                    .clamp(0, len - 1)
+                                    as usize;
+
+                                if start_idx >= list.len() || start_idx > stop_idx {
+                                    Reply::EMPTY_ARRAY
+                                } else {
+                                    Reply::bulks(list.range(start_idx..=stop_idx))
+                                }
+                            } else {
+                                // If the key is a String, Redis returns an error
+                                Reply::WRONGTYPE
+                            }
+                        }
+                        // If list doesn't exist, return empty array
+                        None => Reply::EMPTY_ARRAY,
+                    }
+                }
+                Command::Ltrim { key, start, stop } => {
+                    let mut map = db.lock().unwrap();
+                    let _ = get_valid_entry(&mut map, &key);
+
+                    match map.get_mut(&key).map(|entry| &mut entry.value) {
+                        Some(RedisValue::List(list)) => {
+                            // Same range rules as LRANGE; everything outside it goes
+                            let len = list.len() as i64;
+                            let start_idx = (if start < 0 { len + start } else { start }).clamp(0, len) as usize;
+                            let stop_idx = (if stop < 0 { len + stop } else { stop }).min(len - 1);
+
+                            if stop_idx < start_idx as i64 {
+                                map.remove(&key);
+                            } else {
+                                list.truncate(stop_idx as usize + 1);
+                                list.drain(..start_idx);
+                            }
+                            Reply::OK
+                        }
+                        Some(_) => Reply::WRONGTYPE,
+                        None => Reply::OK,
+                    }
+                }
+                Command::Llen(key) => {
+                    let mut db_lock = db.lock().unwrap();
+
+                    match get_valid_entry(&mut db_lock, &key) {
+                        Some(entry) => {
+                            if let RedisValue::List(ref list) = entry.value {
+                                Reply::integer(list.len())
+                            } else {
+                                Reply::WRONGTYPE
+                            }
+                        }
+                        // Redis returns 0 for non-existent keys
+                        None => Reply::Integer(0),
+                    }
+                }
+                Command::Lindex { key, index } => {
+                    let mut map = db.lock().unwrap();
+
+                    match get_valid_entry(&mut map, &key).map(|entry| &entry.value) {
+                        Some(RedisValue::List(list)) => {
+                            match list_position(list.len(), index).map(|index| &list[index]) {
+                                Some(item) => Reply::bulk(item),
+                                None => Reply::Null,
+                            }
+                        }
+                        Some(_) => Reply::WRONGTYPE,
+                        None => Reply::Null,
+                    }
+                }
+                Command::Lset { key, index, value } => {
+                    let mut map = db.lock().unwrap();
+                    let _ = get_valid_entry(&mut map, &key);
+
+                    match map.get_mut(&key).map(|entry| &mut entry.value) {
+                        Some(RedisValue::List(list)) => {
+                            match list_position(list.len(), index).map(|index| &mut list[index]) {
+                                Some(item) => {
+                                    *item = value;
+                                    Reply::OK
+                                }
+                                None => Reply::error("ERR index out of range"),
+                            }
+                        }
+                        Some(_) => Reply::WRONGTYPE,
+                        None => Reply::error("ERR no such key"),
+                    }
+                }
+                Command::Lpos { rank: 0, .. } => Reply::error(
+                    "ERR RANK can't be zero: use 1 to start from the first match, 2 from the second ... or use negative to start from the end of the list",
+                ),
+                Command::Lpos { key, element, rank, count, maxlen } => {
+                    let mut map = db.lock().unwrap();
+
+                    match get_valid_entry(&mut map, &key).map(|entry| &entry.value) {
+                        Some(RedisValue::List(list)) => {
+                            let len = list.len();
+                            let scanned = if maxlen == 0 { len } else { maxlen.min(len) };
+                            let mut matches = (0..scanned)
+                                .map(|i| if rank > 0 { i } else { len - 1 - i })
+                                .filter(|&i| list[i] == element)
+                                .skip(rank.unsigned_abs() as usize - 1);
+
+                            match count {
+                                None => matches.next().map_or(Reply::Null, Reply::integer),
+                                Some(0) => Reply::Array(matches.map(Reply::integer).collect()),
+                                Some(count) => Reply::Array(matches.take(count).map(Reply::integer).collect()),
+                            }
+                        }
+                        Some(_) => Reply::WRONGTYPE,
+                        None if count.is_some() => Reply::EMPTY_ARRAY,
+                        None => Reply::Null,
+                    }
+                }
+                Command::Linsert { key, after, pivot, element } => {
+                    let mut map = db.lock().unwrap();
+                    let _ = get_valid_entry(&mut map, &key);
+
+                    match map.get_mut(&key).map(|entry| &mut entry.value) {
+                        Some(RedisValue::List(list)) => match list.iter().position(|item| *item == pivot) {
+                            Some(index) => {
+                                list.insert(if after { index + 1 } else { index }, element);
+                                Reply::integer(list.len())
+                            }
+                            None => Reply::Integer(-1),
+                        },
+                        Some(_) => Reply::WRONGTYPE,
+                        None => Reply::Integer(0),
+                    }
+                }
+                Command::Lrem { key, count, element } => {
+                    let mut map = db.lock().unwrap();
+                    let _ = get_valid_entry(&mut map, &key);
+
+                    match map.get_mut(&key).map(|entry| &mut entry.value) {
+                        Some(RedisValue::List(list)) => {
+                            let total = list.iter().filter(|item| **item == element).count();
+                            let to_remove = if count == 0 { total } else { total.min(count.unsigned_abs() as usize) };
+
+                            // Removing from the tail is the same as sparing the first matches
+                            let mut spared = if count < 0 { total - to_remove } else { 0 };
+                            let mut removed = 0;
+                            list.retain(|item| {
+                                if *item != element || removed == to_remove {
+                                    true
+                                } else if spared > 0 {
+                                    spared -= 1;
+                                    true
+                                } else {
+                                    removed += 1;
+                                    false
+                                }
+                            });
+                            delete_if_empty(&mut map, &key);
+                            Reply::integer(removed)
+                        }
+                        Some(_) => Reply::WRONGTYPE,
+                        None => Reply::Integer(0),
+                    }
+                }
+                Command::Pop { key, end, count } => {
+                    let mut db_lock = db.lock().unwrap();
+                    let _ = get_valid_entry(&mut db_lock, &key);
+
+                    let reply = match db_lock.get_mut(&key) {
+                        Some(entry) => {
+                            if let RedisValue::List(ref mut list) = entry.value {
+                                match count {
+                                    // Without a count, just the element (or null if the list is empty)
+                                    None => pop_list(list, end, 1).pop().map_or(Reply::Null, Reply::Bulk),
+                                    Some(num) => Reply::bulks(pop_list(list, end, num)),
+                                }
+                            } else {
+                                Reply::WRONGTYPE
+                            }
+                        }
+                        None => Reply::Null,
+                    };
+                    delete_if_empty(&mut db_lock, &key);
+                    reply
+                }
+                Command::Bpop { keys, end, timeout } => {
+                    let map = db.lock().unwrap();
+
+                    let reply = blocked.serve(map, &keys, blocking::timeout_from_secs(timeout), |map, is_next| {
+                        // The first non-empty list no client blocked before us is waiting on
+                        keys.iter().find_map(|key| {
+                            let _ = get_valid_entry(map, key);
+                            match map.get_mut(key).map(|entry| &mut entry.value) {
+                                Some(RedisValue::List(list)) if !list.is_empty() && is_next(key) => {
+                                    // BLPOP and BRPOP return a 2-element array: [key, value]
+                                    let val = pop_list(list, end, 1).pop().unwrap();
+                                    delete_if_empty(map, key);
+                                    Some(Reply::bulks([key.clone(), val]))
+                                }
+                                _ => None,
+                            }
+                        })
+                    });
+                    reply.unwrap_or(Reply::NullArray)
+                }
+                Command::Lmpop { keys, end, count, timeout } => {
+                    let mut map = db.lock().unwrap();
+
+                    // Pop from the first non-empty list
+                    let pop = |map: &mut Dict<Entry>, is_next: &dyn Fn(&str) -> bool| {
+                        for key in &keys {
+                            let _ = get_valid_entry(map, key);
+                            match map.get_mut(key).map(|entry| &mut entry.value) {
+                                Some(RedisValue::List(list)) if !list.is_empty() && is_next(key) => {
+                                    let popped = pop_list(list, end, count);
+                                    delete_if_empty(map, key);
+                                    return Some(Reply::Array(vec![Reply::bulk(key), Reply::bulks(popped)]));
+                                }
+                                Some(RedisValue::List(_)) | None => {}
+                                Some(_) => return Some(Reply::WRONGTYPE),
+                            }
+                        }
+                        None
+                    };
+
+                    // LMPOP doesn't wait at all
+                    let reply = match timeout {
+                        Some(timeout) => blocked.serve(map, &keys, blocking::timeout_from_secs(timeout), pop),
+                        None => pop(&mut map, &|_| true),
+                    };
+                    reply.unwrap_or(Reply::NullArray)
+                }
+                Command::Blmove { source, destination, from, to, timeout } => {
+                    let map = db.lock().unwrap();
+
+                    let reply = blocked.serve(map, std::slice::from_ref(&source), blocking::timeout_from_secs(timeout), |map, is_next| {
+                        let _ = get_valid_entry(map, &source);
+                        let _ = get_valid_entry(map, &destination);
+                        let destination_is_list =
+                            matches!(map.get(&destination).map(|entry| &entry.value), None | Some(RedisValue::List(_)));
+
+                        match map.get_mut(&source).map(|entry| &mut entry.value) {
+                            Some(RedisValue::List(list)) if !list.is_empty() && is_next(&source) => {
+                                if !destination_is_list {
+                                    return Some(Reply::WRONGTYPE);
+                                }
+                                let item = pop_list(list, from, 1).pop().unwrap();
+                                delete_if_empty(map, &source);
+
+                                let entry = map.entry(destination.clone()).or_insert(Entry {
+                                    value: RedisValue::List(VecDeque::new()),
+                                    expires_at: None,
+                                    last_access: Instant::now(),
+                                    flags: 0,
+                                });
+                                if let RedisValue::List(ref mut list) = entry.value {
+                                    match to {
+                                        ListEnd::Left => list.push_front(item.clone()),
+                                        ListEnd::Right => list.push_back(item.clone()),
+                                    }
+                                }
+                                blocked.signal(&destination); // Someone may be waiting on the destination
+                                Some(Reply::Bulk(item))
+                            }
+                            Some(RedisValue::List(_)) | None => None,
+                            Some(_) => Some(Reply::WRONGTYPE),
+                        }
+                    });
+                    reply.unwrap_or(Reply::Null)
+                }
+                Command::Hset { key, pairs } => {
+                    let mut map = db.lock().unwrap();
+                    let _ = get_valid_entry(&mut map, &key);
+
+                    let entry = map.entry(key).or_insert_with(|| Entry {
+                        value: RedisValue::Hash(RedisHash::new()),
+                        expires_at: None,
+                        last_access: Instant::now(),
+                        flags: 0,
+                    });
+
+                    match entry.value {
+                        RedisValue::Hash(ref mut hash) => {
+                            // Only fields that weren't there before count
+                            let added = pairs.into_iter().map(|(field, value)| hash.insert(field, value)).filter(Option::is_none).count();
+                            Reply::integer(added)
+                        }
+                        _ => Reply::WRONGTYPE,
+                    }
+                }
+                Command::Hget { key, field } => {
+                    let mut map = db.lock().unwrap();
+
+                    match get_valid_entry(&mut map, &key).map(|entry| &entry.value) {
+                        Some(RedisValue::Hash(hash)) => hash.get(&field).map_or(Reply::Null, Reply::bulk),
+                        Some(_) => Reply::WRONGTYPE,
+                        None => Reply::Null,
+                    }
+                }
+                Command::Hdel { key, fields } => {
+                    let mut map = db.lock().unwrap();
+                    let _ = get_valid_entry(&mut map, &key);
+
+                    match map.get_mut(&key).map(|entry| &mut entry.value) {
+                        Some(RedisValue::Hash(hash)) => {
+                            let removed = fields.iter().filter(|field| hash.remove(field).is_some()).count();
+                            delete_if_empty(&mut map, &key);
+                            Reply::integer(removed)
+                        }
+                        Some(_) => Reply::WRONGTYPE,
+                        None => Reply::Integer(0),
+                    }
+                }
+                Command::Hgetall(key) => {
+                    let mut map = db.lock().unwrap();
+
+                    match get_valid_entry(&mut map, &key).map(|entry| &entry.value) {
+                        // A map for RESP3 clients, [field, value, ...] for RESP2 ones
+                        Some(RedisValue::Hash(hash)) => {
+                            Reply::Map(hash.iter().map(|(field, value)| (Reply::bulk(field), Reply::bulk(value))).collect())
+                        }
+                        Some(_) => Reply::WRONGTYPE,
+                        None => Reply::Map(Vec::new()),
+                    }
+                }
+                Command::Hmget { key, fields } => {
+                    let mut map = db.lock().unwrap();
+
+                    // A null for each missing field
+                    match get_valid_entry(&mut map, &key).map(|entry| &entry.value) {
+                        Some(RedisValue::Hash(hash)) => {
+                            Reply::Array(fields.iter().map(|field| hash.get(field).map_or(Reply::Null, Reply::bulk)).collect())
+                        }
+                        Some(_) => Reply::WRONGTYPE,
+                        None => Reply::Array(vec![Reply::Null; fields.len()]),
+                    }
+                }
+                Command::Hkeys(key) => {
+                    let mut map = db.lock().unwrap();
+
+                    match get_valid_entry(&mut map, &key).map(|entry| &entry.value) {
+                        Some(RedisValue::Hash(hash)) => Reply::bulks(hash.keys()),
+                        Some(_) => Reply::WRONGTYPE,
+                        None => Reply::EMPTY_ARRAY,
+                    }
+                }
+                Command::Hvals(key) => {
+                    let mut map = db.lock().unwrap();
+
+                    match get_valid_entry(&mut map, &key).map(|entry| &entry.value) {
+                        Some(RedisValue::Hash(hash)) => Reply::bulks(hash.values()),
+                        Some(_) => Reply::WRONGTYPE,
+                        None => Reply::EMPTY_ARRAY,
+                    }
+                }
+                Command::Hlen(key) => {
+                    let mut map = db.lock().unwrap();
+
+                    match get_valid_entry(&mut map, &key).map(|entry| &entry.value) {
+                        Some(RedisValue::Hash(hash)) => Reply::integer(hash.len()),
+                        Some(_) => Reply::WRONGTYPE,
+                        None => Reply::Integer(0),
+                    }
+                }
+                Command::Hsetnx { key, field, value } => {
+                    let mut map = db.lock().unwrap();
+                    let _ = get_valid_entry(&mut map, &key);
+
+                    let entry = map.entry(key).or_insert_with(|| Entry {
+                        value: RedisValue::Hash(RedisHash::new()),
+                        expires_at: None,
+                        last_access: Instant::now(),
+                        flags: 0,
+                    });
+
+                    match entry.value {
+                        RedisValue::Hash(ref hash) if hash.contains_key(&field) => Reply::Integer(0),
+                        RedisValue::Hash(ref mut hash) => {
+                            hash.insert(field, value);
+                            Reply::Integer(1)
+                        }
+                        _ => Reply::WRONGTYPE,
+                    }
+                }
+                Command::Hexists { key, field } => {
+                    let mut map = db.lock().unwrap();
+
+                    match get_valid_entry(&mut map, &key).map(|entry| &entry.value) {
+                        Some(RedisValue::Hash(hash)) => Reply::Integer(hash.contains_key(&field) as i64),
+                        Some(_) => Reply::WRONGTYPE,
+                        None => Reply::Integer(0),
+                    }
+                }
+                Command::Hstrlen { key, field } => {
+                    let mut map = db.lock().unwrap();
+
+                    match get_valid_entry(&mut map, &key).map(|entry| &entry.value) {
+                        Some(RedisValue::Hash(hash)) => Reply::integer(hash.get(&field).map_or(0, String::len)),
+                        Some(_) => Reply::WRONGTYPE,
+                        None => Reply::Integer(0),
+                    }
+                }
+                Command::Hrandfield { count: Some(count), .. } if !random_count_in_range(count) => {
+                    Reply::error("ERR value is out of range")
+                }
+                Command::Hrandfield { key, count, withvalues } => {
+                    let mut map = db.lock().unwrap();
+
+                    match get_valid_entry(&mut map, &key).map(|entry| &entry.value) {
+                        Some(RedisValue::Hash(hash)) => {
+                            let pairs: Vec<_> = hash.iter().collect();
+                            match count {
+                                // Without a count, just a field (or null if the hash is empty)
+                                None => random_picks(&pairs, 1).first().map_or(Reply::Null, |(field, _)| Reply::bulk(*field)),
+                                Some(count) => {
+                                    let picks = random_picks(&pairs, count);
+                                    if !withvalues {
+                                        Reply::bulks(picks.into_iter().map(|(field, _)| field))
+                                    } else if resp::protocol() == 3 {
+                                        // [field, value] pairs for RESP3 clients
+                                        Reply::Array(picks.into_iter().map(|(field, value)| Reply::bulks([field, value])).collect())
+                                    } else {
+                                        Reply::bulks(picks.into_iter().flat_map(|(field, value)| [field, value]))
+                                    }
+                                }
+                            }
+                        }
+                        Some(_) => Reply::WRONGTYPE,
+                        None if count.is_some() => Reply::EMPTY_ARRAY,
+                        None => Reply::Null,
+                    }
+                }
+                Command::Hscan { key, cursor, pattern, count, novalues } => {
+                    let mut map = db.lock().unwrap();
+
+                    match get_valid_entry(&mut map, &key).map(|entry| &entry.value) {
+                        Some(RedisValue::Hash(hash)) => {
+                            let (cursor, pairs) = hash.scan(cursor, count);
+                            let items = pairs
+                                .into_iter()
+                                .filter(|(field, _)| pattern.as_ref().is_none_or(|pattern| glob::matches(pattern.as_bytes(), field.as_bytes())))
+                                .flat_map(|(field, value)| if novalues { vec![field] } else { vec![field, value.as_str()] });
+                            Reply::Array(vec![Reply::bulk(cursor.to_string()), Reply::bulks(items)])
+                        }
+                        Some(_) => Reply::WRONGTYPE,
+                        None => Reply::Array(vec![Reply::bulk("0"), Reply::EMPTY_ARRAY]),
+                    }
+                }
+                Command::Hincrby { increment: None, .. } => {
+                    Reply::error("ERR value is not an integer or out of range")
+                }
+                Command::Hincrby { key, field, increment: Some(increment) } => {
+                    let mut map = db.lock().unwrap();
+                    let _ = get_valid_entry(&mut map, &key);
+
+                    // Work out the new value before touching the hash, so errors leave it as it was
+                    let result = match map.get(&key).map(|entry| &entry.value) {
+                        Some(RedisValue::Hash(hash)) => match hash.get(&field) {
+                            Some(value) => value.parse::<i64>().map_err(|_| Reply::error("ERR hash value is not an integer")),
+                            None => Ok(0),
+                        },
+                        Some(_) => Err(Reply::WRONGTYPE),
+                        None => Ok(0),
+                    }
+                    .and_then(|n| n.checked_add(increment).ok_or(Reply::error("ERR increment or decrement would overflow")));
+
+                    match result {
+                        Ok(n) => {
+                            let entry = map.entry(key).or_insert_with(|| Entry {
+                                value: RedisValue::Hash(RedisHash::new()),
+                                expires_at: None,
+                                last_access: Instant::now(),
+                                flags: 0,
+                            });
+                            if let RedisValue::Hash(ref mut hash) = entry.value {
+                                hash.update(field, n.to_string());
+                            }
+                            Reply::Integer(n)
+                        }
+                        Err(reply) => reply,
+                    }
+                }
+                Command::Hincrbyfloat { increment: None, .. } => Reply::error("ERR value is not a valid float"),
+                Command::Hincrbyfloat { key, field, increment: Some(increment) } => {
+                    let mut map = db.lock().unwrap();
+                    let _ = get_valid_entry(&mut map, &key);
+
+                    // Work out the new value before touching the hash, so errors leave it as it was
+                    let result = match map.get(&key).map(|entry| &entry.value) {
+                        Some(RedisValue::Hash(hash)) => match hash.get(&field) {
+                            Some(value) => value.parse::<f64>().map_err(|_| Reply::error("ERR hash value is not a float")),
+                            None => Ok(0.0),
+                        },
+                        Some(_) => Err(Reply::WRONGTYPE),
+                        None => Ok(0.0),
+                    }
+                    .map(|n| n + increment)
+                    .and_then(|n| {
+                        if n.is_finite() { Ok(n) } else { Err(Reply::error("ERR increment would produce NaN or Infinity")) }
+                    });
+
+                    match result {
+                        Ok(n) => {
+                            // Same formatting as INCRBYFLOAT
+                            let value = n.to_string();
+                            let entry = map.entry(key).or_insert_with(|| Entry {
+                                value: RedisValue::Hash(RedisHash::new()),
+                                expires_at: None,
+                                last_access: Instant::now(),
+                                flags: 0,
+                            });
+                            if let RedisValue::Hash(ref mut hash) = entry.value {
+                                hash.update(field, value.clone());
+                            }
+                            Reply::Bulk(value)
+                        }
+                        Err(reply) => reply,
+                    }
+                }
+                Command::Hexpire { key, expiry, nx, xx, gt, lt, fields } => {
+                    let at = expiry.parts().and_then(|(value, unit_ms, absolute)| expiry_time_ms(value, unit_ms, absolute));
+
+                    if nx && (xx || gt || lt) {
+                        Reply::error("ERR NX and XX, GT or LT options at the same time are not compatible")
+                    } else if gt && lt {
+                        Reply::error("ERR GT and LT options at the same time are not compatible")
+                    } else if let Some(at) = at {
+                        let mut map = db.lock().unwrap();
+                        let _ = get_valid_entry(&mut map, &key);
+
+                        // Per field: -2 no such field, 0 condition not met, 1 set, 2 deleted
+                        match map.get_mut(&key).map(|entry| &mut entry.value) {
+                            Some(RedisValue::Hash(hash)) => {
+                                let now = unix_time_ms();
+                                let results: Vec<Reply> = fields
+                                    .iter()
+                                    .map(|field| {
+                                        if !hash.contains_key(field) {
+                                            return Reply::Integer(-2);
+                                        }
+                                        let allowed = match hash.expires_at(field) {
+                                            Some(current) => !nx && (!gt || at > current) && (!lt || at < current),
+                                            None => !xx && !gt,
+                                        };
+                                        if !allowed {
+                                            Reply::Integer(0)
+                                        } else if at <= now {
+                                            // A time that has already passed deletes the field
+                                            hash.remove(field);
+                                            Reply::Integer(2)
+                                        } else {
+                                            hash.set_expires_at(field, at);
+                                            Reply::Integer(1)
+                                        }
+                                    })
+                                    .collect();
+                                delete_if_empty(&mut map, &key);
+                                Reply::Array(results)
+                            }
+                            Some(_) => Reply::WRONGTYPE,
+                            None => Reply::Array(vec![Reply::Integer(-2); fields.len()]),
+                        }
+                    } else {
+                        Reply::error(format!("ERR invalid expire time in '{}' command", command_name))
+                    }
+                }
+                Command::Httl { key, millis, fields } => {
+                    let mut map = db.lock().unwrap();
+
+                    // Per field: -2 no such field, -1 no TTL, otherwise the time left
+                    match get_valid_entry(&mut map, &key).map(|entry| &entry.value) {
+                        Some(RedisValue::Hash(hash)) => {
+                            let now = unix_time_ms();
+                            Reply::Array(
+                                fields
+                                    .iter()
+                                    .map(|field| match hash.expires_at(field).map(|at| (at - now).max(0)) {
+                                        Some(ms) if millis => Reply::Integer(ms),
+                                        // Rounded to the nearest second, like TTL
+                                        Some(ms) => Reply::Integer((ms + 500) / 1000),
+                                        None if hash.contains_key(field) => Reply::Integer(-1),
+                                        None => Reply::Integer(-2),
+                                    })
+                                    .collect(),
+                            )
+                        }
+                        Some(_) => Reply::WRONGTYPE,
+                        None => Reply::Array(vec![Reply::Integer(-2); fields.len()]),
+                    }
+                }
+                Command::Hpersist { key, fields } => {
+                    let mut map = db.lock().unwrap();
+                    let _ = get_valid_entry(&mut map, &key);
+
+                    // Per field: -2 no such field, -1 no TTL to remove, 1 removed
+                    match map.get_mut(&key).map(|entry| &mut entry.value) {
+                        Some(RedisValue::Hash(hash)) => Reply::Array(
+                            fields
+                                .iter()
+                                .map(|field| {
+                                    if !hash.contains_key(field) {
+                                        Reply::Integer(-2)
+                                    } else if hash.persist(field) {
+                                        Reply::Integer(1)
+                                    } else {
+                                        Reply::Integer(-1)
+                                    }
+                                })
+                                .collect(),
+                        ),
+                        Some(_) => Reply::WRONGTYPE,
+                        None => Reply::Array(vec![Reply::Integer(-2); fields.len()]),
+                    }
+                }
+                Command::Sadd { key, members } => {
+                    let mut map = db.lock().unwrap();
+                    let _ = get_valid_entry(&mut map, &key);
+
+                    let entry = map.entry(key).or_insert_with(|| Entry {
+                        value: RedisValue::Set(RedisSet::new()),
+                        expires_at: None,
+                        last_access: Instant::now(),
+                        flags: 0,
+                    });
+
+                    match entry.value {
+                        RedisValue::Set(ref mut set) => {
+                            // Only members that weren't there before count
+                            let added = members.into_iter().map(|member| set.insert(member)).filter(|&added| added).count();
+                            Reply::integer(added)
+                        }
+                        _ => Reply::WRONGTYPE,
+                    }
+                }
+                Command::Srem { key, members } => {
+                    let mut map = db.lock().unwrap();
+                    let _ = get_valid_entry(&mut map, &key);
+
+                    match map.get_mut(&key).map(|entry| &mut entry.value) {
+                        Some(RedisValue::Set(set)) => {
+                            let removed = members.iter().filter(|member| set.remove(member)).count();
+                            delete_if_empty(&mut map, &key);
+                            Reply::integer(removed)
+                        }
+                        Some(_) => Reply::WRONGTYPE,
+                        None => Reply::Integer(0),
+                    }
+                }
+                Command::Smembers(key) => {
+                    let mut map = db.lock().unwrap();
+
+                    // A set for RESP3 clients, an array for RESP2 ones
+                    match get_valid_entry(&mut map, &key).map(|entry| &entry.value) {
+                        Some(RedisValue::Set(set)) => Reply::Set(set.iter().map(Reply::bulk).collect()),
+                        Some(_) => Reply::WRONGTYPE,
+                        None => Reply::Set(Vec::new()),
+                    }
+                }
+                Command::Sismember { key, member } => {
+                    let mut map = db.lock().unwrap();
+
+                    match get_valid_entry(&mut map, &key).map(|entry| &entry.value) {
+                        Some(RedisValue::Set(set)) => Reply::Integer(set.contains(&member) as i64),
+                        Some(_) => Reply::WRONGTYPE,
+                        None => Reply::Integer(0),
+                    }
+                }
+                Command::Smismember { key, members } => {
+                    let mut map = db.lock().unwrap();
+
+                    // A 1 or 0 for each member
+                    match get_valid_entry(&mut map, &key).map(|entry| &entry.value) {
+                        Some(RedisValue::Set(set)) => {
+                            Reply::Array(members.iter().map(|member| Reply::Integer(set.contains(member) as i64)).collect())
+                        }
+                        Some(_) => Reply::WRONGTYPE,
+                        None => Reply::Array(vec![Reply::Integer(0); members.len()]),
+                    }
+                }
+                Command::Scard(key) => {
+                    let mut map = db.lock().unwrap();
+
+                    match get_valid_entry(&mut map, &key).map(|entry| &entry.value) {
+                        Some(RedisValue::Set(set)) => Reply::integer(set.len()),
+                        Some(_) => Reply::WRONGTYPE,
+                        None => Reply::Integer(0),
+                    }
+                }
+                Command::Smove { source, destination, member } => {
+                    let mut map = db.lock().unwrap();
+                    let _ = get_valid_entry(&mut map, &source);
+                    let _ = get_valid_entry(&mut map, &destination);
+
+                    // Both keys are checked before anything moves, though like Redis a missing
+                    // source is answered before the destination's type is looked at
+                    let in_source = match map.get(&source).map(|entry| &entry.value) {
+                        Some(RedisValue::Set(set)) => Ok(Some(set.contains(&member))),
+                        Some(_) => Err(Reply::WRONGTYPE),
+                        None => Ok(None),
+                    };
+                    let destination_ok = matches!(map.get(&destination).map(|entry| &entry.value), Some(RedisValue::Set(_)) | None);
 
-                                if start_idx >= list.len() || start_idx > stop_idx {
-                                    stream.write_resp(Resp::array(0))?;
-                                } else {
-                                    let elements = &list[start_idx..=stop_idx];
+                    match in_source {
+                        Ok(None) => Reply::Integer(0),
+                        Err(reply) => reply,
+                        Ok(_) if !destination_ok => Reply::WRONGTYPE,
+                        Ok(Some(false)) => Reply::Integer(0),
+                        Ok(Some(true)) if source == destination => Reply::Integer(1),
+                        Ok(Some(true)) => {
+                            if let Some(RedisValue::Set(set)) = map.get_mut(&source).map(|entry| &mut entry.value) {
+                                set.remove(&member);
+                            }
+                            delete_if_empty(&mut map, &source);
 
-                                    // Encode as RESP Array: *<count>\r\n
-                                    let mut response = Resp::array(elements.len());
-                                    for el in elements {
-                                        response.push_str(&Resp::bulk_string(el));
-                                    }
-                                    stream.write_resp(response)?;
-                                }
-                            } else {
-                                // If the key is a String, Redis returns an error
-                                stream.write_resp(Resp::error("WRONGTYPE Operation against a key holding the wrong kind of value"))?;
+                            let entry = map.entry(destination).or_insert_with(|| Entry {
+                                value: RedisValue::Set(RedisSet::new()),
+                                expires_at: None,
+                                last_access: Instant::now(),
+                                flags: 0,
+                            });
+                            if let RedisValue::Set(ref mut set) = entry.value {
+                                set.insert(member);
                             }
+                            Reply::Integer(1)
+                        }
+                    }
+                }
+                Command::Sintercard { keys, limit } => {
+                    let mut map = db.lock().unwrap();
+
+                    let empty = RedisSet::new();
+                    match sets_at(&mut map, &keys, &empty) {
+                        Ok(sets) => {
+                            // Stops counting once the limit is reached, rather than building the intersection
+                            let smallest = sets.iter().min_by_key(|set| set.len()).unwrap();
+                            let common = smallest.iter().filter(|member| sets.iter().all(|set| set.contains(member)));
+                            Reply::integer(if limit == 0 { common.count() } else { common.take(limit).count() })
                         }
-                        None => {
-                            // If list doesn't exist, return empty array
-                            stream.write_resp(Resp::array(0))?;
+                        Err(reply) => reply,
+                    }
+                }
+                Command::Sscan { key, cursor, pattern, count } => {
+                    let mut map = db.lock().unwrap();
+
+                    match get_valid_entry(&mut map, &key).map(|entry| &entry.value) {
+                        Some(RedisValue::Set(set)) => {
+                            let (cursor, members) = set.scan(cursor, count);
+                            let items = members
+                                .into_iter()
+                                .filter(|member| pattern.as_ref().is_none_or(|pattern| glob::matches(pattern.as_bytes(), member.as_bytes())));
+                            Reply::Array(vec![Reply::bulk(cursor.to_string()), Reply::bulks(items)])
                         }
+                        Some(_) => Reply::WRONGTYPE,
+                        None => Reply::Array(vec![Reply::bulk("0"), Reply::EMPTY_ARRAY]),
                     }
                 }
-                Command::Llen(key) => {
-                    let db_lock = db.lock().unwrap();
+                Command::Setop { op, keys, destination } => {
+                    let mut map = db.lock().unwrap();
 
-                    match db_lock.get(&key) {
-                        Some(entry) => {
-                            if let RedisValue::List(ref list) = entry.value {
-                                stream.write_resp(Resp::integer(list.len()))?;
+                    match (combine_sets(&mut map, op, &keys), destination) {
+                        (Ok(set), None) => Reply::Set(set.iter().map(Reply::bulk).collect()),
+                        (Ok(set), Some(destination)) => {
+                            // Whatever was there is replaced, or deleted if the result is empty
+                            let len = set.len();
+                            if set.is_empty() {
+                                map.remove(&destination);
                             } else {
-                                stream.write_resp(Resp::error("WRONGTYPE Operation against a key holding the wrong kind of value"))?;
+                                map.insert(destination, Entry { value: RedisValue::Set(set), expires_at: None, last_access: Instant::now(), flags: 0 });
                             }
+                            Reply::integer(len)
                         }
-                        None => {
-                            // Redis returns 0 for non-existent keys
-                            stream.write_resp(Resp::integer(0))?;
-                        }
+                        (Err(reply), _) => reply,
                     }
                 }
-                Command::Lpop { key, count } => {
-                    let mut db_lock = db.lock().unwrap();
+                Command::Spop { key, count } => {
+                    let mut map = db.lock().unwrap();
+                    let _ = get_valid_entry(&mut map, &key);
 
-                    match db_lock.get_mut(&key) {
-                        Some(entry) => {
-                            if let RedisValue::List(ref mut list) = entry.value {
-                                match count {
-                                    None => {
-                                        // LPOP without count
-                                        if list.is_empty() {
-                                            // List exists but is empty
-                                            stream.write_resp(Resp::null_bulk())?;
-                                        } else {
-                                            // Remove the first element
-                                            let val = list.remove(0);
-                                            stream.write_resp(Resp::bulk_string(&val))?;
-                                        }
-                                    }
-                                    Some(num) => {
-                                        // LPOP with count
-                                        let take_count = std::cmp::min(num, list.len());
-                                        // Remove the first 'n' elements from the vector
-                                        let popped_elements: Vec<String> =
-                                            list.drain(0..take_count).collect();
-
-                                        let mut response = Resp::array(popped_elements.len());
-                                        for el in popped_elements {
-                                            response.push_str(&Resp::bulk_string(&el));
-                                        }
-                                        stream.write_resp(response)?;
+                    match map.get_mut(&key).map(|entry| &mut entry.value) {
+                        Some(RedisValue::Set(set)) => {
+                            let reply = match count {
+                                // Without a count, just a member
+                                None => set.pop_random().map_or(Reply::Null, Reply::Bulk),
+                                // Asking for at least the whole set takes it as it is
+                                Some(count) if count >= set.len() => Reply::Set(std::mem::take(set).iter().map(Reply::bulk).collect()),
+                                Some(count) => {
+                                    let members: Vec<Cow<str>> = set.iter().collect();
+                                    let picks: Vec<String> = random_picks(&members, count as i64).into_iter().map(Cow::into_owned).collect();
+                                    for member in &picks {
+                                        set.remove(member);
                                     }
+                                    Reply::Set(picks.into_iter().map(Reply::Bulk).collect())
                                 }
-                            } else {
-                                stream.write_resp(Resp::error("WRONGTYPE Operation against a key holding the wrong kind of value"))?;
-                            }
-                        }
-                        None => {
-                            stream.write_resp(Resp::null_bulk())?;
+                            };
+                            delete_if_empty(&mut map, &key);
+                            reply
                         }
+                        Some(_) => Reply::WRONGTYPE,
+                        None if count.is_some() => Reply::Set(Vec::new()),
+                        None => Reply::Null,
                     }
                 }
-                Command::Blpop { keys, timeout } => {
+                Command::Srandmember { count: Some(count), .. } if !random_count_in_range(count) => {
+                    Reply::error("ERR value is out of range")
+                }
+                Command::Srandmember { key, count } => {
                     let mut map = db.lock().unwrap();
 
-                    let timeout_duration = Duration::from_secs_f64(timeout);
-                    let start_time = Instant::now();
+                    match get_valid_entry(&mut map, &key).map(|entry| &entry.value) {
+                        Some(RedisValue::Set(set)) => match count {
+                            // Without a count, just a member (or null if the set is empty)
+                            None => set.random_member().map_or(Reply::Null, Reply::bulk),
+                            Some(count) => Reply::bulks(random_picks(&set.iter().collect::<Vec<_>>(), count)),
+                        },
+                        Some(_) => Reply::WRONGTYPE,
+                        None if count.is_some() => Reply::EMPTY_ARRAY,
+                        None => Reply::Null,
+                    }
+                }
+                Command::Zadd { members: None, .. } => Reply::error("ERR value is not a valid float"),
+                Command::Zadd { key, nx, xx, gt, lt, ch, incr, members: Some(members) } => {
+                    if nx && xx {
+                        Reply::error("ERR XX and NX options at the same time are not compatible")
+                    } else if (gt || lt) && nx || gt && lt {
+                        Reply::error("ERR GT, LT, and/or NX options at the same time are not compatible")
+                    } else if incr && members.len() != 1 {
+                        Reply::error("ERR INCR option supports a single increment-element pair")
+                    } else {
+                        let mut map = db.lock().unwrap();
+                        let _ = get_valid_entry(&mut map, &key);
 
-                    loop {
-                        // Try to find a non-empty list
-                        for key in &keys {
-                            if let Some(Entry {
-                                value: RedisValue::List(list),
-                                ..
-                            }) = map.get_mut(key)
-                            {
-                                if !list.is_empty() {
-                                    let val = list.remove(0);
-                                    // BLPOP returns a 2-element array: [key, value]
-                                    let mut response = Resp::array(2);
-                                    response.push_str(&Resp::bulk_string(key));
-                                    response.push_str(&Resp::bulk_string(&val));
-
-                                    stream.write_resp(response)?;
-                                    return Ok(());
+                        let entry = map.entry(key.clone()).or_insert_with(|| Entry {
+                            value: RedisValue::SortedSet(SortedSet::new()),
+                            expires_at: None,
+                            last_access: Instant::now(),
+                            flags: 0,
+                        });
+
+                        let reply = match entry.value {
+                            RedisValue::SortedSet(ref mut zset) => 'add: {
+                                let (mut added, mut updated) = (0, 0);
+                                let mut last_score = None;
+                                for (score, member) in members {
+                                    let current = zset.score(&member);
+                                    let score = if incr { current.unwrap_or(0.0) + score } else { score };
+                                    if score.is_nan() {
+                                        break 'add Reply::error("ERR resulting score is not a number (NaN)");
+                                    }
+
+                                    // GT and LT only hold back updates; new members still get in
+                                    let allowed = match current {
+                                        Some(current) => !nx && (!gt || score > current) && (!lt || score < current),
+                                        None => !xx,
+                                    };
+                                    if !allowed {
+                                        continue;
+                                    }
+                                    match current {
+                                        None => added += 1,
+                                        Some(current) if current != score => updated += 1,
+                                        Some(_) => {}
+                                    }
+                                    zset.insert(member, score);
+                                    last_score = Some(score);
+                                }
+
+                                if incr {
+                                    // Null if the flags held the update back
+                                    last_score.map_or(Reply::Null, Reply::Double)
+                                } else if ch {
+                                    Reply::integer(added + updated)
+                                } else {
+                                    Reply::integer(added)
                                 }
                             }
-                        }
+                            _ => Reply::WRONGTYPE,
+                        };
+                        // XX on a missing key mustn't leave an empty one behind
+                        delete_if_empty(&mut map, &key);
+                        reply
+                    }
+                }
+                Command::Zscore { key, member } => {
+                    let mut map = db.lock().unwrap();
 
-                        // Check if we already timed out
-                        let elapsed = start_time.elapsed();
-                        if timeout > 0.0 && elapsed >= timeout_duration {
-                            stream.write_resp(Resp::null_array())?; // Redis returns Null Bulk String on timeout
-                            return Ok(());
-                        }
+                    match get_valid_entry(&mut map, &key).map(|entry| &entry.value) {
+                        Some(RedisValue::SortedSet(zset)) => zset.score(&member).map_or(Reply::Null, Reply::Double),
+                        Some(_) => Reply::WRONGTYPE,
+                        None => Reply::Null,
+                    }
+                }
+                Command::Zrange { key, start, stop, rev, withscores } => {
+                    let mut map = db.lock().unwrap();
 
-                        // Wait to be notified or for timeout
-                        if timeout == 0.0 {
-                            map = cv.wait(map).unwrap();
-                        } else {
-                            let remaining = timeout_duration - elapsed;
-                            let (new_map, _) = cv.wait_timeout(map, remaining).unwrap();
-                            map = new_map;
-                        }
+                    match get_valid_entry(&mut map, &key).map(|entry| &entry.value) {
+                        Some(RedisValue::SortedSet(zset)) => match index_range(zset.len(), start, stop) {
+                            Some(range) => {
+                                let (skip, take) = (*range.start(), range.count());
+                                let items: Vec<_> = if rev {
+                                    zset.iter().rev().skip(skip).take(take).collect()
+                                } else {
+                                    zset.iter().skip(skip).take(take).collect()
+                                };
+                                scored_members_reply(items, withscores)
+                            }
+                            None => Reply::EMPTY_ARRAY,
+                        },
+                        Some(_) => Reply::WRONGTYPE,
+                        None => Reply::EMPTY_ARRAY,
                     }
                 }
                 Command::Xadd { key, id, fields } => {
                     let mut db_lock = db.lock().unwrap();
+                    let _ = get_valid_entry(&mut db_lock, &key);
 
-                    let entry = db_lock.entry(key).or_insert(Entry {
+                    let entry = db_lock.entry(key.clone()).or_insert(Entry {
                         value: RedisValue::Stream(Vec::new()),
-                        created_at: Instant::now(),
-                        expires_in: None,
+                        expires_at: None,
+                        last_access: Instant::now(),
+                        flags: 0,
                     });
 
                     if let RedisValue::Stream(ref mut entries) = entry.value {
@@ -448,34 +2822,26 @@ fn handle_connection(mut stream: TcpStream, db: Db, cv: Cv) -> IoResult<()> {
                             (ms, seq)
                         };
 
-                        let mut is_valid = true;
-
                         // Validate against "0-0"
                         if id != "*" && ms == 0 && seq == 0 {
-                            stream.write_resp(Resp::error("ERR The ID specified in XADD must be greater than 0-0"))?;
-                            is_valid = false;
-                        }
-                        else if let Some(last) = last_entry {
+                            Reply::error("ERR The ID specified in XADD must be greater than 0-0")
+                        } else if let Some(last) = last_entry
                             // Must be strictly greater than the top item
-                            let is_greater = ms > last.id_ms || (ms == last.id_ms && seq > last.id_seq);
-                            if !is_greater {
-                                stream.write_resp(Resp::error("ERR The ID specified in XADD is equal or smaller than the target stream top item"))?;
-                                is_valid = false;
-                            }
-                        }
-
-                        if is_valid {
+                            && !(ms > last.id_ms || (ms == last.id_ms && seq > last.id_seq))
+                        {
+                            Reply::error("ERR The ID specified in XADD is equal or smaller than the target stream top item")
+                        } else {
                             entries.push(StreamEntry { id_ms: ms, id_seq: seq, fields });
-
-                            let response_id = format!("{}-{}", ms, seq);
-                            stream.write_resp(Resp::bulk_string(&response_id))?;
+                            blocked.signal_all(&key); // Every XREAD BLOCK on the stream gets to see it
+                            Reply::Bulk(format!("{}-{}", ms, seq))
                         }
                     } else {
-                        stream.write_resp(Resp::error("WRONGTYPE Operation against a key holding the wrong kind of value"))?;
+                        Reply::WRONGTYPE
                     }
                 }
                 Command::Xrange { key, start, end } => {
-                    let db_lock = db.lock().unwrap();
+                    let mut db_lock = db.lock().unwrap();
+                    let _ = get_valid_entry(&mut db_lock, &key);
 
                     // Helper to parse inclusive range boundaries
                     let parse_boundary = |s: &str, is_start: bool| -> (u64, u64) {
@@ -496,69 +2862,40 @@ fn handle_connection(mut stream: TcpStream, db: Db, cv: Cv) -> IoResult<()> {
 
                     if let Some(Entry { value: RedisValue::Stream(entries), .. }) = db_lock.get(&key) {
                         // Filter entries based on the range
-                        let filtered: Vec<&StreamEntry> = entries
-                            .iter()
-                            .filter(|e| {
-                                let after_start = e.id_ms > start_ms || (e.id_ms == start_ms && e.id_seq >= start_seq);
-                                let before_end = e.id_ms < end_ms || (e.id_ms == end_ms && e.id_seq <= end_seq);
-                                after_start && before_end
-                            })
-                            .collect();
-
-                        // Construct the nested RESP array
-                        let mut response = Resp::array(filtered.len());
-                        for entry in filtered {
-                            // Each entry is a 2-element array: [ID, [fields]]
-                            response.push_str(&Resp::array(2));
-                            response.push_str(&Resp::bulk_string(&format!("{}-{}", entry.id_ms, entry.id_seq)));
-
-                            // Fields are an array of strings: [key1, val1, key2, val2...]
-                            response.push_str(&Resp::array(entry.fields.len() * 2));
-                            for (f, v) in &entry.fields {
-                                response.push_str(&Resp::bulk_string(f));
-                                response.push_str(&Resp::bulk_string(v));
-                            }
-                        }
-                        stream.write_resp(response)?;
+                        let filtered = entries.iter().filter(|e| {
+                            let after_start = e.id_ms > start_ms || (e.id_ms == start_ms && e.id_seq >= start_seq);
+                            let before_end = e.id_ms < end_ms || (e.id_ms == end_ms && e.id_seq <= end_seq);
+                            after_start && before_end
+                        });
+                        Reply::Array(filtered.map(stream_entry_reply).collect())
                     } else {
-                        stream.write_resp(Resp::array(0))?;
+                        Reply::EMPTY_ARRAY
                     }
                 }
                 Command::Xread { keys, mut ids, block_timeout } => {
-                    let start_time = std::time::Instant::now();
-
-                    // Distinguish between no block, blocking with a timeout, and blocking indefinitely (0)
-                    let is_blocking = block_timeout.is_some();
-                    let is_indefinite = block_timeout == Some(0);
-                    let timeout = block_timeout.map(std::time::Duration::from_millis);
+                    let mut db_lock = db.lock().unwrap();
 
-                    // Resolve '$' IDs before entering the polling loop
-                    {
-                        let db_lock = db.lock().unwrap();
-                        for (i, key) in keys.iter().enumerate() {
-                            if ids[i] == "$" {
-                                if let Some(Entry { value: RedisValue::Stream(entries), .. }) = db_lock.get(key) {
-                                    if let Some(last) = entries.last() {
-                                        // Replace '$' with the actual highest ID currently in the stream
-                                        ids[i] = format!("{}-{}", last.id_ms, last.id_seq);
-                                    } else {
-                                        // Stream exists but is empty
-                                        ids[i] = "0-0".to_string();
-                                    }
+                    // Resolve '$' IDs before reading anything
+                    for (i, key) in keys.iter().enumerate() {
+                        if ids[i] == "$" {
+                            if let Some(Entry { value: RedisValue::Stream(entries), .. }) = get_valid_entry(&mut db_lock, key) {
+                                if let Some(last) = entries.last() {
+                                    // Replace '$' with the actual highest ID currently in the stream
+                                    ids[i] = format!("{}-{}", last.id_ms, last.id_seq);
                                 } else {
-                                    // Stream doesn't exist yet
+                                    // Stream exists but is empty
                                     ids[i] = "0-0".to_string();
                                 }
+                            } else {
+                                // Stream doesn't exist yet
+                                ids[i] = "0-0".to_string();
                             }
                         }
                     }
 
-                    let mut final_response = String::new();
-                    let mut data_found = false;
-
-                    loop {
-                        let db_lock = db.lock().unwrap();
-                        let mut response_array = Vec::new();
+                    // Entries past each ID, if any stream has some
+                    let read = |db_lock: &mut Dict<Entry>, _: &dyn Fn(&str) -> bool| {
+                        let mut streams = Vec::new();
 
                         for (i, key) in keys.iter().enumerate() {
                             // ids[i] now contains either the explicit ID or the resolved '$' ID
@@ -569,243 +2906,1749 @@ fn handle_connection(mut stream: TcpStream, db: Db, cv: Cv) -> IoResult<()> {
                             let start_ms = parts[0].parse::<u64>().unwrap_or(0);
                             let start_seq = parts.get(1).and_then(|s| s.parse::<u64>().ok()).unwrap_or(0);
 
-                            if let Some(Entry { value: RedisValue::Stream(entries), .. }) = db_lock.get(key) {
+                            if let Some(Entry { value: RedisValue::Stream(entries), .. }) = get_valid_entry(db_lock, key) {
                                 // Filter: Strictly GREATER than start_id
-                                let filtered: Vec<&StreamEntry> = entries
+                                let filtered: Vec<Reply> = entries
                                     .iter()
                                     .filter(|e| {
                                         e.id_ms > start_ms || (e.id_ms == start_ms && e.id_seq > start_seq)
                                     })
+                                    .map(stream_entry_reply)
                                     .collect();
 
                                 if !filtered.is_empty() {
                                     // Stream result: [key, [entries]]
-                                    let mut stream_res = Resp::array(2);
-                                    stream_res.push_str(&Resp::bulk_string(key));
-
-                                    // Entries array
-                                    let mut entries_res = Resp::array(filtered.len());
-                                    for entry in filtered {
-                                        let mut entry_data = Resp::array(2);
-                                        entry_data.push_str(&Resp::bulk_string(&format!("{}-{}", entry.id_ms, entry.id_seq)));
-
-                                        let mut fields_res = Resp::array(entry.fields.len() * 2);
-                                        for (f, v) in &entry.fields {
-                                            fields_res.push_str(&Resp::bulk_string(f));
-                                            fields_res.push_str(&Resp::bulk_string(v));
+                                    streams.push(Reply::Array(vec![Reply::bulk(key.as_str()), Reply::Array(filtered)]));
+                                }
+                            }
+                        }
+
+                        // Check if we captured anything across any of the requested streams
+                        (!streams.is_empty()).then_some(Reply::Array(streams))
+                    };
+
+                    // Without BLOCK, return straight away; BLOCK 0 waits indefinitely
+                    let reply = match block_timeout {
+                        Some(ms) => {
+                            let timeout = (ms > 0).then(|| Duration::from_millis(ms));
+                            blocked.serve(db_lock, &keys, timeout, read)
+                        }
+                        None => read(&mut db_lock, &|_| true),
+                    };
+                    reply.unwrap_or(Reply::NullArray)
+                }
+                Command::Type(key) => {
+                    let mut map = db.lock().unwrap();
+
+                    let type_name = match get_valid_entry(&mut map, &key) {
+                        Some(entry) => entry.value.type_name(),
+                        None => "none",
+                    };
+                    Reply::SimpleString(type_name.into())
+                }
+                Command::BfReserve { key, error_rate, capacity, expansion, nonscaling } => {
+                    let mut map = db.lock().unwrap();
+
+                    if !(error_rate > 0.0 && error_rate < 1.0) {
+                        Reply::error("ERR (0 < error rate range < 1)")
+                    } else if capacity == 0 {
+                        Reply::error("ERR (capacity should be larger than 0)")
+                    } else if expansion == 0 {
+                        Reply::error("ERR expansion should be greater or equal to 1")
+                    } else if !bloom::fits(capacity, error_rate) {
+                        Reply::error("ERR capacity is too large for this error rate")
+                    } else if get_valid_entry(&mut map, &key).is_some() {
+                        Reply::error("ERR item exists")
+                    } else {
+                        map.insert(
+                            key,
+                            Entry {
+                                value: RedisValue::Bloom(ScalableBloom::new(error_rate, capacity, expansion, nonscaling)),
+                                expires_at: None,
+                                last_access: Instant::now(),
+                                flags: 0,
+                            },
+                        );
+                        Reply::OK
+                    }
+                }
+                Command::BfAdd { key, items, multi } => {
+                    let mut map = db.lock().unwrap();
+                    let _ = get_valid_entry(&mut map, &key);
+
+                    let entry = map.entry(key).or_insert_with(|| Entry {
+                        value: RedisValue::Bloom(ScalableBloom::new(
+                            bloom::DEFAULT_ERROR_RATE,
+                            bloom::DEFAULT_CAPACITY,
+                            bloom::DEFAULT_EXPANSION,
+                            false,
+                        )),
+                        expires_at: None,
+                        last_access: Instant::now(),
+                        flags: 0,
+                    });
+
+                    if let RedisValue::Bloom(ref mut filter) = entry.value {
+                        let mut replies: Vec<Reply> = items
+                            .iter()
+                            .map(|item| match filter.add(item) {
+                                Ok(added) => Reply::Boolean(added),
+                                Err(BloomError::Full) => Reply::error("ERR non scaling filter is full"),
+                                Err(BloomError::TooLarge) => Reply::error("ERR filter is full and too large to grow"),
+                            })
+                            .collect();
+                        // BF.ADD takes exactly one item and answers with a bare reply
+                        if multi { Reply::Array(replies) } else { replies.remove(0) }
+                    } else {
+                        Reply::WRONGTYPE
+                    }
+                }
+                Command::BfExists { key, item } => {
+                    let mut map = db.lock().unwrap();
+
+                    match get_valid_entry(&mut map, &key) {
+                        Some(Entry { value: RedisValue::Bloom(filter), .. }) => Reply::Boolean(filter.exists(&item)),
+                        Some(_) => Reply::WRONGTYPE,
+                        // A missing filter can't contain anything
+                        None => Reply::Boolean(false),
+                    }
+                }
+                Command::BfInfo { key, field } => {
+                    let mut map = db.lock().unwrap();
+
+                    match get_valid_entry(&mut map, &key) {
+                        Some(Entry { value: RedisValue::Bloom(filter), .. }) => {
+                            let fields = [
+                                ("Capacity", filter.capacity() as usize),
+                                ("Size", filter.size_in_bytes()),
+                                ("Number of filters", filter.num_filters()),
+                                ("Number of items inserted", filter.num_items() as usize),
+                                ("Expansion rate", filter.expansion() as usize),
+                            ];
+
+                            match field {
+                                None => Reply::Map(
+                                    fields
+                                        .iter()
+                                        .map(|&(name, value)| (Reply::SimpleString(name.into()), Reply::integer(value)))
+                                        .collect(),
+                                ),
+                                Some(field) => {
+                                    let index = match field.to_uppercase().as_str() {
+                                        "CAPACITY" => Some(0),
+                                        "SIZE" => Some(1),
+                                        "FILTERS" => Some(2),
+                                        "ITEMS" => Some(3),
+                                        "EXPANSION" => Some(4),
+                                        _ => None,
+                                    };
+                                    match index {
+                                        Some(i) => Reply::Array(vec![Reply::integer(fields[i].1)]),
+                                        None => Reply::error("ERR Invalid information value"),
+                                    }
+                                }
+                            }
+                        }
+                        Some(_) => Reply::WRONGTYPE,
+                        None => Reply::error("ERR not found"),
+                    }
+                }
+                Command::ClientSetinfo { attr, value } => {
+                    let name = attr.to_uppercase();
+
+                    if name != "LIB-NAME" && name != "LIB-VER" {
+                        Reply::error(format!("ERR Unrecognized option '{}'", attr))
+                    } else if value.chars().any(|c| c <= ' ' || c > '~') {
+                        // Same restriction Redis applies so CLIENT LIST stays parseable
+                        Reply::error(format!(
+                            "ERR {} cannot contain spaces, newlines or special characters.",
+                            name.to_lowercase()
+                        ))
+                    } else {
+                        let mut clients = clients.lock().unwrap();
+                        let info = clients.get_mut(&client_id).unwrap();
+                        let value = (!value.is_empty()).then_some(value);
+                        if name == "LIB-NAME" {
+                            info.lib_name = value;
+                        } else {
+                            info.lib_ver = value;
+                        }
+                        Reply::OK
+                    }
+                }
+                Command::Hello { protover, setname } => {
+                    let version = match protover.as_deref().map(str::parse::<u8>) {
+                        None => Ok(resp::protocol()),
+                        Some(Ok(v @ (2 | 3))) => Ok(v),
+                        Some(Ok(_)) => Err("NOPROTO unsupported protocol version"),
+                        Some(Err(_)) => Err("ERR Protocol version is not an integer or out of range"),
+                    };
+
+                    match version {
+                        Ok(version) => {
+                            resp::set_protocol(version);
+                            if let Some(name) = setname {
+                                clients.lock().unwrap().get_mut(&client_id).unwrap().name = Some(name);
+                            }
+
+                            Reply::Map(vec![
+                                (Reply::bulk("server"), Reply::bulk("redis")),
+                                (Reply::bulk("version"), Reply::bulk(REDIS_VERSION)),
+                                (Reply::bulk("proto"), Reply::integer(version)),
+                                (Reply::bulk("id"), Reply::integer(client_id)),
+                                (Reply::bulk("mode"), Reply::bulk("standalone")),
+                                (Reply::bulk("role"), Reply::bulk("master")),
+                                (Reply::bulk("modules"), Reply::EMPTY_ARRAY),
+                            ])
+                        }
+                        Err(msg) => Reply::error(msg),
+                    }
+                }
+                Command::ClientList => {
+                    let clients = clients.lock().unwrap();
+
+                    let mut ids: Vec<&u64> = clients.keys().collect();
+                    ids.sort();
+                    Reply::Bulk(ids.into_iter().map(|id| clients[id].describe(*id)).collect())
+                }
+                Command::ClientInfo => {
+                    let clients = clients.lock().unwrap();
+                    Reply::Bulk(clients[&client_id].describe(client_id))
+                }
+                Command::Readonly | Command::Readwrite => {
+                    // Replica reads are a cluster feature; standalone Redis rejects both the same way
+                    Reply::error("ERR This instance has cluster support disabled")
+                }
+                Command::TopkReserve { key, k, width, depth, decay } => {
+                    let mut map = db.lock().unwrap();
+
+                    if k == 0 || width == 0 || depth == 0 {
+                        Reply::error("ERR TopK: invalid k, width or depth")
+                    } else if !sketch::dimensions_fit(width, depth) {
+                        Reply::error("ERR TopK: width * depth is too large")
+                    } else if !(decay > 0.0 && decay <= 1.0) {
+                        Reply::error("ERR TopK: decay must be a value between 0 and 1")
+                    } else if get_valid_entry(&mut map, &key).is_some() {
+                        Reply::error("ERR TopK: key already exists")
+                    } else {
+                        map.insert(
+                            key,
+                            Entry {
+                                value: RedisValue::TopK(TopK::new(k, width, depth, decay)),
+                                expires_at: None,
+                                last_access: Instant::now(),
+                                flags: 0,
+                            },
+                        );
+                        Reply::OK
+                    }
+                }
+                Command::TopkAdd { key, items } => {
+                    let mut map = db.lock().unwrap();
+                    let _ = get_valid_entry(&mut map, &key);
+
+                    match map.get_mut(&key) {
+                        Some(Entry { value: RedisValue::TopK(topk), .. }) => {
+                            // One reply per item: the item it expelled from the list, or null
+                            Reply::Array(items.iter().map(|item| topk.add(item).map_or(Reply::Null, Reply::Bulk)).collect())
+                        }
+                        Some(_) => Reply::WRONGTYPE,
+                        None => Reply::error("ERR TopK: key does not exist"),
+                    }
+                }
+                Command::TopkQuery { key, items } => {
+                    let mut map = db.lock().unwrap();
+
+                    match get_valid_entry(&mut map, &key) {
+                        Some(Entry { value: RedisValue::TopK(topk), .. }) => {
+                            Reply::Array(items.iter().map(|item| Reply::integer(topk.contains(item) as u8)).collect())
+                        }
+                        Some(_) => Reply::WRONGTYPE,
+                        None => Reply::error("ERR TopK: key does not exist"),
+                    }
+                }
+                Command::TopkList { key, with_count } => {
+                    let mut map = db.lock().unwrap();
+
+                    match get_valid_entry(&mut map, &key) {
+                        Some(Entry { value: RedisValue::TopK(topk), .. }) => {
+                            let mut replies = Vec::new();
+                            for (item, count) in topk.list() {
+                                replies.push(Reply::bulk(item));
+                                if with_count {
+                                    replies.push(Reply::integer(count));
+                                }
+                            }
+                            Reply::Array(replies)
+                        }
+                        Some(_) => Reply::WRONGTYPE,
+                        None => Reply::error("ERR TopK: key does not exist"),
+                    }
+                }
+                Command::CmsInitbydim { key, width, depth } => {
+                    let mut map = db.lock().unwrap();
+
+                    if width == 0 || depth == 0 {
+                        Reply::error("ERR CMS: invalid width/depth")
+                    } else if !sketch::dimensions_fit(width, depth) {
+                        Reply::error("ERR CMS: width * depth is too large")
+                    } else if get_valid_entry(&mut map, &key).is_some() {
+                        Reply::error("ERR CMS: key already exists")
+                    } else {
+                        map.insert(
+                            key,
+                            Entry {
+                                value: RedisValue::CountMinSketch(CountMinSketch::new(width, depth)),
+                                expires_at: None,
+                                last_access: Instant::now(),
+                                flags: 0,
+                            },
+                        );
+                        Reply::OK
+                    }
+                }
+                Command::CmsIncrby { key, increments } => {
+                    let mut map = db.lock().unwrap();
+                    let _ = get_valid_entry(&mut map, &key);
+
+                    match map.get_mut(&key) {
+                        Some(Entry { value: RedisValue::CountMinSketch(cms), .. }) => Reply::Array(
+                            increments
+                                .iter()
+                                .map(|(item, increment)| Reply::integer(cms.increment(item, *increment)))
+                                .collect(),
+                        ),
+                        Some(_) => Reply::WRONGTYPE,
+                        None => Reply::error("ERR CMS: key does not exist"),
+                    }
+                }
+                Command::CmsQuery { key, items } => {
+                    let mut map = db.lock().unwrap();
+
+                    match get_valid_entry(&mut map, &key) {
+                        Some(Entry { value: RedisValue::CountMinSketch(cms), .. }) => {
+                            Reply::Array(items.iter().map(|item| Reply::integer(cms.query(item))).collect())
+                        }
+                        Some(_) => Reply::WRONGTYPE,
+                        None => Reply::error("ERR CMS: key does not exist"),
+                    }
+                }
+                Command::TsCreate { key, retention, labels } => {
+                    let mut map = db.lock().unwrap();
+
+                    if get_valid_entry(&mut map, &key).is_some() {
+                        Reply::error("ERR TSDB: key already exists")
+                    } else {
+                        map.insert(
+                            key,
+                            Entry {
+                                value: RedisValue::TimeSeries(TimeSeries::new(retention, labels)),
+                                expires_at: None,
+                                last_access: Instant::now(),
+                                flags: 0,
+                            },
+                        );
+                        Reply::OK
+                    }
+                }
+                Command::TsAdd { key, timestamp, value, retention, labels } => {
+                    let mut map = db.lock().unwrap();
+
+                    let _ = get_valid_entry(&mut map, &key);
+
+                    // Creation options only apply when TS.ADD creates the series
+                    let entry = map.entry(key).or_insert_with(|| Entry {
+                        value: RedisValue::TimeSeries(TimeSeries::new(retention.unwrap_or(0), labels)),
+                        expires_at: None,
+                        last_access: Instant::now(),
+                        flags: 0,
+                    });
+
+                    if let RedisValue::TimeSeries(ref mut series) = entry.value {
+                        let timestamp = timestamp.unwrap_or_else(|| {
+                            SystemTime::now()
+                                .duration_since(SystemTime::UNIX_EPOCH)
+                                .unwrap()
+                                .as_millis() as u64
+                        });
+
+                        match series.add(timestamp, value) {
+                            Ok(()) => Reply::integer(timestamp),
+                            Err(TimeSeriesError::TooOld) => Reply::error("ERR TSDB: Timestamp is older than retention"),
+                            Err(TimeSeriesError::Duplicate) => Reply::error(
+                                "ERR TSDB: Error at upsert, update is not supported when DUPLICATE_POLICY is set to BLOCK mode",
+                            ),
+                        }
+                    } else {
+                        Reply::WRONGTYPE
+                    }
+                }
+                Command::TsRange { key, range } => {
+                    let mut map = db.lock().unwrap();
+
+                    match get_valid_entry(&mut map, &key) {
+                        Some(Entry { value: RedisValue::TimeSeries(series), .. }) => {
+                            let mut samples = series.range(range.from, range.to, range.aggregation);
+                            if let Some(count) = range.count {
+                                samples.truncate(count);
+                            }
+                            ts_samples_reply(&samples)
+                        }
+                        Some(_) => Reply::WRONGTYPE,
+                        None => Reply::error("ERR TSDB: the key does not exist"),
+                    }
+                }
+                Command::TsMrange { range, with_labels, filters } => {
+                    let map = db.lock().unwrap();
+
+                    let mut matching: Vec<(&String, &TimeSeries)> = map
+                        .iter()
+                        .filter_map(|(key, entry)| match &entry.value {
+                            RedisValue::TimeSeries(series)
+                                if filters.iter().all(|f| f.matches(&series.labels)) =>
+                            {
+                                Some((key, series))
+                            }
+                            _ => None,
+                        })
+                        .collect();
+                    matching.sort_by(|a, b| a.0.cmp(b.0));
+
+                    // Each series is [key, [[label, value], ...], [samples]]
+                    let mut replies = Vec::new();
+                    for (key, series) in matching {
+                        let labels = if with_labels {
+                            series.labels.iter().map(|(label, value)| Reply::bulks([label, value])).collect()
+                        } else {
+                            Vec::new()
+                        };
+
+                        let mut samples = series.range(range.from, range.to, range.aggregation);
+                        if let Some(count) = range.count {
+                            samples.truncate(count);
+                        }
+                        replies.push(Reply::Array(vec![
+                            Reply::bulk(key.as_str()),
+                            Reply::Array(labels),
+                            ts_samples_reply(&samples),
+                        ]));
+                    }
+                    Reply::Array(replies)
+                }
+                Command::LatencyHistogram(commands) => {
+                    let histograms = latency::snapshot(&commands);
+
+                    // {name: {calls: n, histogram_usec: {bucket: cumulative count, ...}}, ...}
+                    let mut replies = Vec::new();
+                    for (name, histogram) in histograms {
+                        let buckets = histogram
+                            .cumulative()
+                            .into_iter()
+                            .map(|(usec, count)| (Reply::integer(usec), Reply::integer(count)))
+                            .collect();
+                        replies.push((
+                            Reply::Bulk(name),
+                            Reply::Map(vec![
+                                (Reply::bulk("calls"), Reply::integer(histogram.calls)),
+                                (Reply::bulk("histogram_usec"), Reply::Map(buckets)),
+                            ]),
+                        ));
+                    }
+                    Reply::Map(replies)
+                }
+                Command::Info(section) => {
+                    let section = section.map(|s| s.to_lowercase());
+                    let mut response = String::new();
+
+                    if matches!(section.as_deref(), None | Some("persistence") | Some("all") | Some("default")) {
+                        response.push_str("# Persistence\r\n");
+                        for (name, value) in loading::info_fields() {
+                            response.push_str(&format!("{}:{}\r\n", name, value));
+                        }
+                    }
+                    if matches!(section.as_deref(), None | Some("memory") | Some("all") | Some("default")) {
+                        response.push_str("# Memory\r\n");
+                        for (name, value) in bufpool::info_fields().into_iter().chain(lazyfree::info_fields()) {
+                            response.push_str(&format!("{}:{}\r\n", name, value));
+                        }
+                    }
+                    if matches!(section.as_deref(), None | Some("stats") | Some("all") | Some("default")) {
+                        response.push_str("# Stats\r\n");
+                        for (name, value) in expire::info_fields() {
+                            response.push_str(&format!("{}:{}\r\n", name, value));
+                        }
+                    }
+                    Reply::Bulk(response)
+                }
+                Command::Vadd { key, vector, element, attributes } => {
+                    let mut map = db.lock().unwrap();
+                    let _ = get_valid_entry(&mut map, &key);
+
+                    let dim = vector.len();
+                    let entry = map.entry(key).or_insert_with(|| Entry {
+                        value: RedisValue::VectorSet(VectorSet::new(dim)),
+                        expires_at: None,
+                        last_access: Instant::now(),
+                        flags: 0,
+                    });
+
+                    match entry.value {
+                        RedisValue::VectorSet(ref mut set) if set.dim() != dim => Reply::error(format!(
+                            "ERR Vector dimension mismatch - got {} but set has {}",
+                            dim,
+                            set.dim()
+                        )),
+                        RedisValue::VectorSet(ref mut set) => Reply::integer(set.add(element, vector, attributes) as u8),
+                        _ => Reply::WRONGTYPE,
+                    }
+                }
+                Command::Vsim { key, query, with_scores, count, metric } => {
+                    let mut map = db.lock().unwrap();
+
+                    match get_valid_entry(&mut map, &key) {
+                        Some(Entry { value: RedisValue::VectorSet(set), .. }) => {
+                            let query = match query {
+                                VectorQuery::Values(values) => Some(values),
+                                VectorQuery::Element(element) => set.embedding(&element).map(|v| v.to_vec()),
+                            };
+
+                            match query {
+                                Some(query) if query.len() != set.dim() => Reply::error(format!(
+                                    "ERR Vector dimension mismatch - got {} but set has {}",
+                                    query.len(),
+                                    set.dim()
+                                )),
+                                Some(query) => {
+                                    let mut replies = Vec::new();
+                                    for (element, score) in set.similar(&query, count, metric) {
+                                        replies.push(Reply::bulk(element));
+                                        if with_scores {
+                                            replies.push(Reply::Double(score));
                                         }
-                                        entry_data.push_str(&fields_res);
-                                        entries_res.push_str(&entry_data);
                                     }
-                                    stream_res.push_str(&entries_res);
-                                    response_array.push(stream_res);
+                                    Reply::Array(replies)
                                 }
+                                None => Reply::error("ERR element not found in set"),
                             }
                         }
+                        Some(_) => Reply::WRONGTYPE,
+                        None => Reply::EMPTY_ARRAY,
+                    }
+                }
+                Command::Vcard(key) => {
+                    let mut map = db.lock().unwrap();
+
+                    match get_valid_entry(&mut map, &key) {
+                        Some(Entry { value: RedisValue::VectorSet(set), .. }) => Reply::integer(set.len()),
+                        Some(_) => Reply::WRONGTYPE,
+                        None => Reply::Integer(0),
+                    }
+                }
+                Command::Vdim(key) => {
+                    let mut map = db.lock().unwrap();
 
-                        // Check if we captured anything across any of the requested streams
-                        if !response_array.is_empty() {
-                            // Wrap the collected streams into the final RESP array
-                            let mut final_resp = Resp::array(response_array.len());
-                            for s in response_array {
-                                final_resp.push_str(&s);
-                            }
-                            final_response = final_resp;
-                            data_found = true;
-                            break; // Break loop to send data back
-                        }
-
-                        // Drop the db_lock explicitly so other incoming connection threads (like XADD) can mutate the DB
-                        drop(db_lock);
-
-                        // If no data was found, decide if we should block or time out
-                        if is_blocking {
-                            if !is_indefinite {
-                                if let Some(t) = timeout {
-                                    if start_time.elapsed() >= t {
-                                        // Timeout expired, break out to return Null Array
-                                        break;
-                                    }
-                                }
+                    match get_valid_entry(&mut map, &key) {
+                        Some(Entry { value: RedisValue::VectorSet(set), .. }) => Reply::integer(set.dim()),
+                        Some(_) => Reply::WRONGTYPE,
+                        None => Reply::error("ERR key does not exist"),
+                    }
+                }
+                Command::Vrem { key, element } => {
+                    let mut map = db.lock().unwrap();
+                    let _ = get_valid_entry(&mut map, &key);
+
+                    match map.get_mut(&key) {
+                        Some(Entry { value: RedisValue::VectorSet(set), .. }) => {
+                            let removed = set.remove(&element);
+                            // Like other aggregate types, the key goes away with its last element
+                            if set.is_empty() {
+                                map.remove(&key);
                             }
-                            // Sleep briefly to yield execution to other threads
-                            std::thread::sleep(std::time::Duration::from_millis(50));
-                        } else {
-                            // No block specified, immediately break out
-                            break;
+                            Reply::integer(removed as u8)
                         }
+                        Some(_) => Reply::WRONGTYPE,
+                        None => Reply::Integer(0),
                     }
+                }
+                Command::Vemb { key, element } => {
+                    let mut map = db.lock().unwrap();
 
-                    // Send the resulting payload back over the TCP stream
-                    if data_found {
-                        stream.write_resp(final_response)?;
-                    } else {
-                        stream.write_resp(Resp::null_array().to_string())?;
+                    match get_valid_entry(&mut map, &key) {
+                        Some(Entry { value: RedisValue::VectorSet(set), .. }) => match set.embedding(&element) {
+                            Some(vector) => Reply::bulks(vector.iter().map(|v| v.to_string())),
+                            None => Reply::Null,
+                        },
+                        Some(_) => Reply::WRONGTYPE,
+                        None => Reply::Null,
                     }
                 }
-                Command::Type(key) => {
+                Command::Vgetattr { key, element } => {
                     let mut map = db.lock().unwrap();
 
-                    let response = match get_valid_entry(&mut map, &key) {
-                        Some(entry) => entry.value.type_name(),
-                        None => "none",
-                    };
+                    match get_valid_entry(&mut map, &key) {
+                        Some(Entry { value: RedisValue::VectorSet(set), .. }) => match set.attributes(&element) {
+                            Some(Some(attributes)) => Reply::bulk(attributes),
+                            _ => Reply::Null,
+                        },
+                        Some(_) => Reply::WRONGTYPE,
+                        None => Reply::Null,
+                    }
+                }
+                Command::Vsetattr { key, element, attributes } => {
+                    let mut map = db.lock().unwrap();
+                    let _ = get_valid_entry(&mut map, &key);
 
-                    stream.write_resp(Resp::string(response))?;
+                    match map.get_mut(&key) {
+                        Some(Entry { value: RedisValue::VectorSet(set), .. }) => {
+                            Reply::integer(set.set_attributes(&element, attributes) as u8)
+                        }
+                        Some(_) => Reply::WRONGTYPE,
+                        None => Reply::Integer(0),
+                    }
                 }
-            }
+            };
+            stream.write_reply(&reply)?;
+
+            latency::record(&command_name, started.elapsed());
+        } else {
+            // The command exists and has enough arguments, but they didn't make sense
+            stream.write_reply(&Reply::error("ERR syntax error"))?;
         }
     }
     Ok(())
 }
 
-fn parse_message(input: &str) -> Option<Command> {
-    let lines: Vec<&str> = input.split("\r\n").collect();
-
-    // Simple check: Is this an array?
-    if !lines[0].starts_with('*') {
-        return None;
-    }
-
-    // Redis commands are usually the 3rd element in the array
-    // (*2, $4, ECHO...) -> index 2 is the command name
-    let command_name = lines.get(2)?.to_uppercase();
+fn parse_message(request: &Request) -> Option<Command> {
+    let command_name = request.name.to_uppercase();
+    let args: Vec<&str> = request.args.iter().map(String::as_str).collect();
 
     match command_name.as_str() {
         "PING" => Some(Command::Ping),
         "ECHO" => {
-            // The value for ECHO is at index 4
-            let content = lines.get(4)?;
+            let content = args.first()?;
             Some(Command::Echo(content.to_string()))
         }
         "SET" => {
-            let key = lines.get(4)?.to_string();
-            let value = lines.get(6)?.to_string();
-            let mut px = None;
+            let key = args.first()?.to_string();
+            let value = args.get(1)?.to_string();
+            let mut expiry = None;
+            let mut condition = None;
+            let mut get = false;
 
-            if let Some(pos) = lines.iter().position(|&p| p.to_uppercase() == "PX") {
-                // Skip the next line ($3) and get the one after (number)
-                if let Some(ms_str) = lines.get(pos + 2) {
-                    px = ms_str.parse::<u64>().ok();
+            let mut options = Options::new(&args[2..]);
+            while let Some(option) = options.next_option() {
+                match option.as_str() {
+                    // Only one of the expiry options may be given
+                    "EX" if expiry.is_none() => expiry = Some(Expiry::Ex(options.parse()?)),
+                    "PX" if expiry.is_none() => expiry = Some(Expiry::Px(options.parse()?)),
+                    "EXAT" if expiry.is_none() => expiry = Some(Expiry::ExAt(options.parse()?)),
+                    "PXAT" if expiry.is_none() => expiry = Some(Expiry::PxAt(options.parse()?)),
+                    "KEEPTTL" if expiry.is_none() => expiry = Some(Expiry::KeepTtl),
+                    "GET" => get = true,
+                    // NX and XX exclude each other
+                    "NX" if condition != Some(SetCondition::IfExists) => condition = Some(SetCondition::IfMissing),
+                    "XX" if condition != Some(SetCondition::IfMissing) => condition = Some(SetCondition::IfExists),
+                    _ => return None,
                 }
             }
 
-            Some(Command::Set { key, value, px })
+            Some(Command::Set { key, value, expiry, condition, get })
+        }
+        "SETNX" => {
+            let key = args.first()?.to_string();
+            let value = args.get(1)?.to_string();
+            Some(Command::Setnx { key, value })
+        }
+        "SETEX" | "PSETEX" => {
+            let key = args.first()?.to_string();
+            let ttl = args.get(1)?.parse::<i64>().ok()?;
+            let value = args.get(2)?.to_string();
+            let expiry = if command_name == "SETEX" { Expiry::Ex(ttl) } else { Expiry::Px(ttl) };
+            Some(Command::Set { key, value, expiry: Some(expiry), condition: None, get: false })
+        }
+        "GETSET" => {
+            let key = args.first()?.to_string();
+            let value = args.get(1)?.to_string();
+            Some(Command::Set { key, value, expiry: None, condition: None, get: true })
         }
         "GET" => {
-            let key = lines.get(4)?.to_string();
+            let key = args.first()?.to_string();
             Some(Command::Get(key))
         }
-        "RPUSH" => {
-            let key = lines.get(4)?.to_string();
-            let mut values = Vec::new();
-            // Starting from index 6, every 2nd line is a new value (skip the $ metadata)
-            let mut i = 6;
-            while let Some(val) = lines.get(i) {
-                values.push(val.to_string());
-                i += 2;
+        "LCS" => {
+            let key1 = args.first()?.to_string();
+            let key2 = args.get(1)?.to_string();
+            let (mut len, mut idx, mut min_match_len, mut with_match_len) = (false, false, 0, false);
+
+            let mut options = Options::new(&args[2..]);
+            while let Some(option) = options.next_option() {
+                match option.as_str() {
+                    "LEN" => len = true,
+                    "IDX" => idx = true,
+                    // Negative lengths mean no minimum
+                    "MINMATCHLEN" => min_match_len = options.parse::<i64>()?.max(0) as usize,
+                    "WITHMATCHLEN" => with_match_len = true,
+                    _ => return None,
+                }
+            }
+
+            Some(Command::Lcs { key1, key2, len, idx, min_match_len, with_match_len })
+        }
+        "GETEX" => {
+            let key = args.first()?.to_string();
+            let mut expiry = None;
+
+            let mut options = Options::new(&args[1..]);
+            while let Some(option) = options.next_option() {
+                match option.as_str() {
+                    // Only one of the expiry options may be given
+                    "EX" if expiry.is_none() => expiry = Some(Expiry::Ex(options.parse()?)),
+                    "PX" if expiry.is_none() => expiry = Some(Expiry::Px(options.parse()?)),
+                    "EXAT" if expiry.is_none() => expiry = Some(Expiry::ExAt(options.parse()?)),
+                    "PXAT" if expiry.is_none() => expiry = Some(Expiry::PxAt(options.parse()?)),
+                    "PERSIST" if expiry.is_none() => expiry = Some(Expiry::Persist),
+                    _ => return None,
+                }
+            }
+
+            Some(Command::Getex { key, expiry })
+        }
+        "GETDEL" => {
+            let key = args.first()?.to_string();
+            Some(Command::Getdel(key))
+        }
+        "INCR" | "DECR" => {
+            let key = args.first()?.to_string();
+            let increment = if command_name == "INCR" { 1 } else { -1 };
+            Some(Command::Incrby { key, increment: Some(increment) })
+        }
+        "APPEND" => {
+            let key = args.first()?.to_string();
+            let value = args.get(1)?.to_string();
+            Some(Command::Append { key, value })
+        }
+        "STRLEN" => {
+            let key = args.first()?.to_string();
+            Some(Command::Strlen(key))
+        }
+        "GETRANGE" => {
+            let key = args.first()?.to_string();
+            let start = args.get(1)?.parse::<i64>().ok()?;
+            let end = args.get(2)?.parse::<i64>().ok()?;
+            Some(Command::Getrange { key, start, end })
+        }
+        "SETRANGE" => {
+            let key = args.first()?.to_string();
+            let offset = args.get(1)?.parse::<i64>().ok()?;
+            let value = args.get(2)?.to_string();
+            Some(Command::Setrange { key, offset, value })
+        }
+        "INCRBYFLOAT" => {
+            let key = args.first()?.to_string();
+            let increment = args.get(1)?.parse::<f64>().ok();
+            Some(Command::Incrbyfloat { key, increment })
+        }
+        "INCRBY" | "DECRBY" => {
+            let key = args.first()?.to_string();
+            let increment = args.get(1)?.parse::<i64>().ok();
+            // DECRBY -9223372036854775808 can't be negated
+            let increment = if command_name == "INCRBY" { increment } else { increment.and_then(i64::checked_neg) };
+            Some(Command::Incrby { key, increment })
+        }
+        "DEL" => {
+            let keys = args.iter().map(|s| s.to_string()).collect();
+            Some(Command::Del(keys))
+        }
+        "UNLINK" => {
+            let keys = args.iter().map(|s| s.to_string()).collect();
+            Some(Command::Unlink(keys))
+        }
+        "EXISTS" => {
+            let keys = args.iter().map(|s| s.to_string()).collect();
+            Some(Command::Exists(keys))
+        }
+        "KEYS" => {
+            let pattern = args.first()?.to_string();
+            Some(Command::Keys(pattern))
+        }
+        "SCAN" => {
+            let cursor = args.first()?.parse::<u64>().ok()?;
+            let mut pattern = None;
+            let mut count = 10;
+            let mut type_name = None;
+
+            let mut options = Options::new(&args[1..]);
+            while let Some(option) = options.next_option() {
+                match option.as_str() {
+                    "MATCH" => pattern = Some(options.value()?.to_string()),
+                    "COUNT" => count = options.parse::<usize>().filter(|&count| count > 0)?,
+                    "TYPE" => type_name = Some(options.value()?.to_string()),
+                    _ => return None,
+                }
+            }
+
+            Some(Command::Scan { cursor, pattern, count, type_name })
+        }
+        "RENAME" | "RENAMENX" => {
+            let key = args.first()?.to_string();
+            let new_key = args.get(1)?.to_string();
+            Some(Command::Rename { key, new_key, nx: command_name == "RENAMENX" })
+        }
+        "RANDOMKEY" => Some(Command::Randomkey),
+        "TOUCH" => {
+            let keys = args.iter().map(|s| s.to_string()).collect();
+            Some(Command::Touch(keys))
+        }
+        "DUMP" => {
+            let key = args.first()?.to_string();
+            Some(Command::Dump(key))
+        }
+        "RESTORE" => {
+            let key = args.first()?.to_string();
+            let ttl = args.get(1)?.parse::<i64>().ok()?;
+            let payload = args.get(2)?.to_string();
+            let (mut replace, mut absttl, mut idletime) = (false, false, None);
+
+            let mut options = Options::new(&args[3..]);
+            while let Some(option) = options.next_option() {
+                match option.as_str() {
+                    "REPLACE" => replace = true,
+                    "ABSTTL" => absttl = true,
+                    "IDLETIME" => idletime = Some(options.parse::<u64>()?),
+                    // There's no LFU to seed
+                    "FREQ" => {
+                        options.parse::<u8>()?;
+                    }
+                    _ => return None,
+                }
+            }
+
+            Some(Command::Restore { key, ttl, payload, replace, absttl, idletime })
+        }
+        "TTL" | "PTTL" => {
+            let key = args.first()?.to_string();
+            Some(Command::Ttl { key, millis: command_name == "PTTL" })
+        }
+        "EXPIRETIME" | "PEXPIRETIME" => {
+            let key = args.first()?.to_string();
+            Some(Command::Expiretime { key, millis: command_name == "PEXPIRETIME" })
+        }
+        "EXPIRE" | "PEXPIRE" | "EXPIREAT" | "PEXPIREAT" => {
+            let key = args.first()?.to_string();
+            let value = args.get(1)?.parse::<i64>().ok()?;
+            let expiry = match command_name.as_str() {
+                "EXPIRE" => Expiry::Ex(value),
+                "PEXPIRE" => Expiry::Px(value),
+                "EXPIREAT" => Expiry::ExAt(value),
+                _ => Expiry::PxAt(value),
+            };
+            let (mut nx, mut xx, mut gt, mut lt) = (false, false, false, false);
+
+            // Conflicting flags are reported by the handler, with Redis' own messages
+            let mut options = Options::new(&args[2..]);
+            while let Some(option) = options.next_option() {
+                match option.as_str() {
+                    "NX" => nx = true,
+                    "XX" => xx = true,
+                    "GT" => gt = true,
+                    "LT" => lt = true,
+                    _ => return None,
+                }
             }
+
+            Some(Command::Expire { key, expiry, nx, xx, gt, lt })
+        }
+        "RPUSH" => {
+            let key = args.first()?.to_string();
+            let values = args[1..].iter().map(|s| s.to_string()).collect();
             Some(Command::Rpush { key, values })
         }
         "LPUSH" => {
-            let key = lines.get(4)?.to_string();
-            let mut values = Vec::new();
-            // Starting from index 6, every 2nd line is a new value (skip the $ metadata)
-            let mut i = 6;
-            while let Some(val) = lines.get(i) {
-                values.push(val.to_string());
-                i += 2;
-            }
+            let key = args.first()?.to_string();
+            let values = args[1..].iter().map(|s| s.to_string()).collect();
             Some(Command::Lpush { key, values })
         }
         "LRANGE" => {
-            let key = lines.get(4)?.to_string();
-            let start = lines.get(6)?.parse::<i64>().ok()?;
-            let stop = lines.get(8)?.parse::<i64>().ok()?;
+            let key = args.first()?.to_string();
+            let start = args.get(1)?.parse::<i64>().ok()?;
+            let stop = args.get(2)?.parse::<i64>().ok()?;
             Some(Command::Lrange { key, start, stop })
         }
+        "LTRIM" => {
+            let key = args.first()?.to_string();
+            let start = args.get(1)?.parse::<i64>().ok()?;
+            let stop = args.get(2)?.parse::<i64>().ok()?;
+            Some(Command::Ltrim { key, start, stop })
+        }
         "LLEN" => {
-            let key = lines.get(4)?.to_string();
+            let key = args.first()?.to_string();
             Some(Command::Llen(key))
         }
-        "LPOP" => {
-            let key = lines.get(4)?.to_string();
-            let count = lines.get(6).and_then(|s| s.parse::<usize>().ok());
-            Some(Command::Lpop { key, count })
+        "LINDEX" => {
+            let key = args.first()?.to_string();
+            let index = args.get(1)?.parse::<i64>().ok()?;
+            Some(Command::Lindex { key, index })
+        }
+        "LSET" => {
+            let key = args.first()?.to_string();
+            let index = args.get(1)?.parse::<i64>().ok()?;
+            let value = args.get(2)?.to_string();
+            Some(Command::Lset { key, index, value })
+        }
+        "LPOS" => {
+            let key = args.first()?.to_string();
+            let element = args.get(1)?.to_string();
+            let mut rank = 1;
+            let mut count = None;
+            let mut maxlen = 0;
+
+            let mut options = Options::new(&args[2..]);
+            while let Some(option) = options.next_option() {
+                match option.as_str() {
+                    "RANK" => rank = options.parse::<i64>()?,
+                    "COUNT" => count = Some(options.parse::<usize>()?),
+                    "MAXLEN" => maxlen = options.parse::<usize>()?,
+                    _ => return None,
+                }
+            }
+
+            Some(Command::Lpos { key, element, rank, count, maxlen })
+        }
+        "LINSERT" => {
+            let key = args.first()?.to_string();
+            let after = match args.get(1)?.to_uppercase().as_str() {
+                "BEFORE" => false,
+                "AFTER" => true,
+                _ => return None,
+            };
+            let pivot = args.get(2)?.to_string();
+            let element = args.get(3)?.to_string();
+            Some(Command::Linsert { key, after, pivot, element })
+        }
+        "LREM" => {
+            let key = args.first()?.to_string();
+            let count = args.get(1)?.parse::<i64>().ok()?;
+            let element = args.get(2)?.to_string();
+            Some(Command::Lrem { key, count, element })
+        }
+        "LPOP" | "RPOP" => {
+            let key = args.first()?.to_string();
+            let end = if command_name == "LPOP" { ListEnd::Left } else { ListEnd::Right };
+            let count = args.get(1).and_then(|s| s.parse::<usize>().ok());
+            Some(Command::Pop { key, end, count })
+        }
+        "BLPOP" | "BRPOP" => {
+            // Keys come first; the timeout is the last argument
+            let (timeout, keys) = args.split_last()?;
+            let timeout = timeout.parse::<f64>().ok().filter(|&timeout| timeout >= 0.0)?;
+            let keys = keys.iter().map(|s| s.to_string()).collect();
+            let end = if command_name == "BLPOP" { ListEnd::Left } else { ListEnd::Right };
+            Some(Command::Bpop { keys, end, timeout })
+        }
+        "LMPOP" | "BLMPOP" => {
+            // BLMPOP takes the timeout before the keys
+            let (timeout, args) = if command_name == "BLMPOP" {
+                (Some(args.first()?.parse::<f64>().ok().filter(|&timeout| timeout >= 0.0)?), &args[1..])
+            } else {
+                (None, &args[..])
+            };
+            let numkeys = args.first()?.parse::<usize>().ok().filter(|&numkeys| numkeys > 0)?;
+            let keys: Vec<String> = args.get(1..=numkeys)?.iter().map(|s| s.to_string()).collect();
+            let end = ListEnd::parse(args.get(numkeys + 1)?)?;
+            let mut count = 1;
+
+            let mut options = Options::new(&args[numkeys + 2..]);
+            while let Some(option) = options.next_option() {
+                match option.as_str() {
+                    "COUNT" => count = options.parse::<usize>().filter(|&count| count > 0)?,
+                    _ => return None,
+                }
+            }
+
+            Some(Command::Lmpop { keys, end, count, timeout })
+        }
+        "BLMOVE" => {
+            let source = args.first()?.to_string();
+            let destination = args.get(1)?.to_string();
+            let from = ListEnd::parse(args.get(2)?)?;
+            let to = ListEnd::parse(args.get(3)?)?;
+            let timeout = args.get(4)?.parse::<f64>().ok().filter(|&timeout| timeout >= 0.0)?;
+            Some(Command::Blmove { source, destination, from, to, timeout })
+        }
+        "BRPOPLPUSH" => {
+            let source = args.first()?.to_string();
+            let destination = args.get(1)?.to_string();
+            let timeout = args.get(2)?.parse::<f64>().ok().filter(|&timeout| timeout >= 0.0)?;
+            Some(Command::Blmove { source, destination, from: ListEnd::Right, to: ListEnd::Left, timeout })
+        }
+        "HSET" => {
+            let key = args.first()?.to_string();
+            let rest = &args[1..];
+            if rest.is_empty() || !rest.len().is_multiple_of(2) {
+                return None;
+            }
+            let pairs = rest.chunks(2).map(|pair| (pair[0].to_string(), pair[1].to_string())).collect();
+            Some(Command::Hset { key, pairs })
+        }
+        "HGET" => {
+            let key = args.first()?.to_string();
+            let field = args.get(1)?.to_string();
+            Some(Command::Hget { key, field })
+        }
+        "HDEL" => {
+            let key = args.first()?.to_string();
+            let fields = args[1..].iter().map(|s| s.to_string()).collect();
+            Some(Command::Hdel { key, fields })
+        }
+        "HGETALL" => {
+            let key = args.first()?.to_string();
+            Some(Command::Hgetall(key))
+        }
+        "HMGET" => {
+            let key = args.first()?.to_string();
+            let fields = args[1..].iter().map(|s| s.to_string()).collect();
+            Some(Command::Hmget { key, fields })
+        }
+        "HKEYS" => {
+            let key = args.first()?.to_string();
+            Some(Command::Hkeys(key))
+        }
+        "HVALS" => {
+            let key = args.first()?.to_string();
+            Some(Command::Hvals(key))
+        }
+        "HLEN" => {
+            let key = args.first()?.to_string();
+            Some(Command::Hlen(key))
+        }
+        "HSETNX" => {
+            let key = args.first()?.to_string();
+            let field = args.get(1)?.to_string();
+            let value = args.get(2)?.to_string();
+            Some(Command::Hsetnx { key, field, value })
+        }
+        "HEXISTS" => {
+            let key = args.first()?.to_string();
+            let field = args.get(1)?.to_string();
+            Some(Command::Hexists { key, field })
+        }
+        "HSTRLEN" => {
+            let key = args.first()?.to_string();
+            let field = args.get(1)?.to_string();
+            Some(Command::Hstrlen { key, field })
+        }
+        "HRANDFIELD" => {
+            let key = args.first()?.to_string();
+            let count = args.get(1).map(|count| count.parse::<i64>()).transpose().ok()?;
+            let withvalues = match args.get(2) {
+                Some(arg) if arg.eq_ignore_ascii_case("WITHVALUES") => true,
+                Some(_) => return None,
+                None => false,
+            };
+            if args.len() > 3 {
+                return None;
+            }
+            Some(Command::Hrandfield { key, count, withvalues })
+        }
+        "HSCAN" => {
+            let key = args.first()?.to_string();
+            let cursor = args.get(1)?.parse::<u64>().ok()?;
+            let mut pattern = None;
+            let mut count = 10;
+            let mut novalues = false;
+
+            let mut options = Options::new(&args[2..]);
+            while let Some(option) = options.next_option() {
+                match option.as_str() {
+                    "MATCH" => pattern = Some(options.value()?.to_string()),
+                    "COUNT" => count = options.parse::<usize>().filter(|&count| count > 0)?,
+                    "NOVALUES" => novalues = true,
+                    _ => return None,
+                }
+            }
+
+            Some(Command::Hscan { key, cursor, pattern, count, novalues })
+        }
+        "HINCRBY" => {
+            let key = args.first()?.to_string();
+            let field = args.get(1)?.to_string();
+            let increment = args.get(2)?.parse::<i64>().ok();
+            Some(Command::Hincrby { key, field, increment })
+        }
+        "HINCRBYFLOAT" => {
+            let key = args.first()?.to_string();
+            let field = args.get(1)?.to_string();
+            let increment = args.get(2)?.parse::<f64>().ok();
+            Some(Command::Hincrbyfloat { key, field, increment })
+        }
+        "HEXPIRE" | "HPEXPIRE" | "HEXPIREAT" | "HPEXPIREAT" => {
+            let key = args.first()?.to_string();
+            let value = args.get(1)?.parse::<i64>().ok()?;
+            let expiry = match command_name.as_str() {
+                "HEXPIRE" => Expiry::Ex(value),
+                "HPEXPIRE" => Expiry::Px(value),
+                "HEXPIREAT" => Expiry::ExAt(value),
+                _ => Expiry::PxAt(value),
+            };
+            let (mut nx, mut xx, mut gt, mut lt) = (false, false, false, false);
+
+            // The condition comes before FIELDS, which takes the rest of the arguments
+            let mut options = Options::new(&args[2..]);
+            let fields = loop {
+                match options.next_option()?.as_str() {
+                    "NX" => nx = true,
+                    "XX" => xx = true,
+                    "GT" => gt = true,
+                    "LT" => lt = true,
+                    "FIELDS" => break parse_hash_fields(options.rest())?,
+                    _ => return None,
+                }
+            };
+
+            Some(Command::Hexpire { key, expiry, nx, xx, gt, lt, fields })
+        }
+        "HTTL" | "HPTTL" => {
+            let key = args.first()?.to_string();
+            if !args.get(1)?.eq_ignore_ascii_case("FIELDS") {
+                return None;
+            }
+            let fields = parse_hash_fields(&args[2..])?;
+            Some(Command::Httl { key, millis: command_name == "HPTTL", fields })
+        }
+        "HPERSIST" => {
+            let key = args.first()?.to_string();
+            if !args.get(1)?.eq_ignore_ascii_case("FIELDS") {
+                return None;
+            }
+            let fields = parse_hash_fields(&args[2..])?;
+            Some(Command::Hpersist { key, fields })
+        }
+        "SADD" => {
+            let key = args.first()?.to_string();
+            let members = args[1..].iter().map(|s| s.to_string()).collect();
+            Some(Command::Sadd { key, members })
+        }
+        "SREM" => {
+            let key = args.first()?.to_string();
+            let members = args[1..].iter().map(|s| s.to_string()).collect();
+            Some(Command::Srem { key, members })
+        }
+        "SMEMBERS" => {
+            let key = args.first()?.to_string();
+            Some(Command::Smembers(key))
+        }
+        "SISMEMBER" => {
+            let key = args.first()?.to_string();
+            let member = args.get(1)?.to_string();
+            Some(Command::Sismember { key, member })
+        }
+        "SMISMEMBER" => {
+            let key = args.first()?.to_string();
+            let members = args[1..].iter().map(|s| s.to_string()).collect();
+            Some(Command::Smismember { key, members })
         }
-        "BLPOP" => {
-            let mut keys = Vec::new();
-            let mut i = 4;
+        "SCARD" => {
+            let key = args.first()?.to_string();
+            Some(Command::Scard(key))
+        }
+        "SMOVE" => {
+            let source = args.first()?.to_string();
+            let destination = args.get(1)?.to_string();
+            let member = args.get(2)?.to_string();
+            Some(Command::Smove { source, destination, member })
+        }
+        "SINTERCARD" => {
+            let numkeys = args.first()?.parse::<usize>().ok().filter(|&numkeys| numkeys > 0)?;
+            let keys = args.get(1..=numkeys)?.iter().map(|s| s.to_string()).collect();
+            let mut limit = 0;
+
+            let mut options = Options::new(&args[numkeys + 1..]);
+            while let Some(option) = options.next_option() {
+                match option.as_str() {
+                    "LIMIT" => limit = options.parse::<usize>()?,
+                    _ => return None,
+                }
+            }
+
+            Some(Command::Sintercard { keys, limit })
+        }
+        "SSCAN" => {
+            let key = args.first()?.to_string();
+            let cursor = args.get(1)?.parse::<u64>().ok()?;
+            let mut pattern = None;
+            let mut count = 10;
+
+            let mut options = Options::new(&args[2..]);
+            while let Some(option) = options.next_option() {
+                match option.as_str() {
+                    "MATCH" => pattern = Some(options.value()?.to_string()),
+                    "COUNT" => count = options.parse::<usize>().filter(|&count| count > 0)?,
+                    _ => return None,
+                }
+            }
+
+            Some(Command::Sscan { key, cursor, pattern, count })
+        }
+        "SINTER" | "SUNION" | "SDIFF" => {
+            let op = match command_name.as_str() {
+                "SINTER" => SetOp::Inter,
+                "SUNION" => SetOp::Union,
+                _ => SetOp::Diff,
+            };
+            let keys = args.iter().map(|s| s.to_string()).collect();
+            Some(Command::Setop { op, keys, destination: None })
+        }
+        "SINTERSTORE" | "SUNIONSTORE" | "SDIFFSTORE" => {
+            let op = match command_name.as_str() {
+                "SINTERSTORE" => SetOp::Inter,
+                "SUNIONSTORE" => SetOp::Union,
+                _ => SetOp::Diff,
+            };
+            let destination = args.first()?.to_string();
+            let keys = args[1..].iter().map(|s| s.to_string()).collect();
+            Some(Command::Setop { op, keys, destination: Some(destination) })
+        }
+        "SPOP" => {
+            let key = args.first()?.to_string();
+            let count = args.get(1).map(|count| count.parse::<usize>()).transpose().ok()?;
+            if args.len() > 2 {
+                return None;
+            }
+            Some(Command::Spop { key, count })
+        }
+        "SRANDMEMBER" => {
+            let key = args.first()?.to_string();
+            let count = args.get(1).map(|count| count.parse::<i64>()).transpose().ok()?;
+            if args.len() > 2 {
+                return None;
+            }
+            Some(Command::Srandmember { key, count })
+        }
+        "ZADD" => {
+            let key = args.first()?.to_string();
+            let (mut nx, mut xx, mut gt, mut lt, mut ch, mut incr) = (false, false, false, false, false, false);
 
-            // Filter out empty lines caused by the split at the end
-            let filtered_lines: Vec<&str> =
-                lines.iter().filter(|s| !s.is_empty()).cloned().collect();
+            // Flags come first, and the score/member pairs take the rest. Conflicts are
+            // reported by the handler.
+            let mut options = Options::new(&args[1..]);
+            loop {
+                if options.next_is("NX") {
+                    nx = true;
+                } else if options.next_is("XX") {
+                    xx = true;
+                } else if options.next_is("GT") {
+                    gt = true;
+                } else if options.next_is("LT") {
+                    lt = true;
+                } else if options.next_is("CH") {
+                    ch = true;
+                } else if options.next_is("INCR") {
+                    incr = true;
+                } else {
+                    break;
+                }
+            }
 
-            // The timeout is the very last valid element
-            let timeout_str = filtered_lines.last()?;
-            let timeout = timeout_str.parse::<f64>().ok()?;
+            let pairs = options.rest();
+            if pairs.is_empty() || !pairs.len().is_multiple_of(2) {
+                return None;
+            }
+            let members = pairs
+                .chunks(2)
+                .map(|pair| Some((pair[0].parse::<f64>().ok().filter(|score| !score.is_nan())?, pair[1].to_string())))
+                .collect();
+            Some(Command::Zadd { key, nx, xx, gt, lt, ch, incr, members })
+        }
+        "ZSCORE" => {
+            let key = args.first()?.to_string();
+            let member = args.get(1)?.to_string();
+            Some(Command::Zscore { key, member })
+        }
+        "ZRANGE" => {
+            let key = args.first()?.to_string();
+            let start = args.get(1)?.parse::<i64>().ok()?;
+            let stop = args.get(2)?.parse::<i64>().ok()?;
+            let (mut rev, mut withscores) = (false, false);
 
-            // Keys are between index 4 and the last element
-            // In filtered_lines, indices are 0: *N, 1: $len, 2: BLPOP, 3: $len, 4: key1...
-            while i < filtered_lines.len() - 1 {
-                keys.push(filtered_lines.get(i)?.to_string());
-                i += 2;
+            let mut options = Options::new(&args[3..]);
+            while let Some(option) = options.next_option() {
+                match option.as_str() {
+                    "REV" => rev = true,
+                    "WITHSCORES" => withscores = true,
+                    _ => return None,
+                }
             }
 
-            Some(Command::Blpop { keys, timeout })
+            Some(Command::Zrange { key, start, stop, rev, withscores })
         }
         "XADD" => {
-            let key = lines.get(4)?.to_string();
-            let id = lines.get(6)?.to_string();
-            let mut fields = HashMap::new();
-            let mut i = 8;
-            while i < lines.len() - 1 {
-                let field = lines.get(i)?.to_string();
-                let value = lines.get(i + 2)?.to_string();
-                fields.insert(field, value);
-                i += 4; // Skip field metadata, field, value metadata, value
+            let key = args.first()?.to_string();
+            let id = args.get(1)?.to_string();
+            let pairs = &args[2..];
+            if pairs.is_empty() || !pairs.len().is_multiple_of(2) {
+                return None;
             }
+            let fields = pairs.chunks(2).map(|pair| (pair[0].to_string(), pair[1].to_string())).collect();
             Some(Command::Xadd { key, id, fields })
         }
         "XRANGE" => {
-            let key = lines.get(4)?.to_string();
-            let start = lines.get(6)?.to_string();
-            let end = lines.get(8)?.to_string();
+            let key = args.first()?.to_string();
+            let start = args.get(1)?.to_string();
+            let end = args.get(2)?.to_string();
             Some(Command::Xrange { key, start, end })
         }
         "XREAD" => {
             let mut block_timeout = None;
-            let mut streams_index = 4; // Default index for STREAMS if no BLOCK is present
 
-            // Check if the next argument is "BLOCK"
-            if lines.get(4).map(|s| s.to_uppercase()) == Some("BLOCK".to_string()) {
-                if let Some(block_ms_str) = lines.get(6) {
-                    block_timeout = block_ms_str.parse::<u64>().ok();
+            // Options come before STREAMS, which takes the rest of the arguments
+            let mut options = Options::new(&args);
+            loop {
+                match options.next_option()?.as_str() {
+                    "BLOCK" => block_timeout = Some(options.parse::<u64>()?),
+                    "STREAMS" => break,
+                    _ => return None,
                 }
-                streams_index = 8; // "STREAMS" moves further down the array
             }
 
-            // Collect all remaining keys and IDs after "STREAMS"
-            let mut args = Vec::new();
-            let mut i = streams_index + 2;
-            while let Some(arg) = lines.get(i) {
-                args.push(arg.to_string());
-                i += 2; // RESP layout: $len\r\nValue\r\n -> skip 2 to get next value
+            // The keys come first, then one ID per key
+            let rest = options.rest();
+            if rest.is_empty() || !rest.len().is_multiple_of(2) {
+                return None;
             }
-
-            let num_streams = args.len() / 2;
-            let (keys, ids) = args.split_at(num_streams);
+            let (keys, ids) = rest.split_at(rest.len() / 2);
 
             Some(Command::Xread {
-                keys: keys.to_vec(),
-                ids: ids.to_vec(),
+                keys: keys.iter().map(|s| s.to_string()).collect(),
+                ids: ids.iter().map(|s| s.to_string()).collect(),
                 block_timeout,
             })
         }
         "TYPE" => {
-            let key = lines.get(4)?.to_string();
+            let key = args.first()?.to_string();
             Some(Command::Type(key))
         }
+        "BF.RESERVE" => {
+            let key = args.first()?.to_string();
+            let error_rate = args.get(1)?.parse::<f64>().ok()?;
+            let capacity = args.get(2)?.parse::<u64>().ok()?;
+            let mut expansion = bloom::DEFAULT_EXPANSION;
+            let mut nonscaling = false;
+
+            // Optional trailing flags: [EXPANSION expansion] [NONSCALING]
+            let mut options = Options::new(&args[3..]);
+            while let Some(option) = options.next_option() {
+                match option.as_str() {
+                    "EXPANSION" => expansion = options.parse::<u32>()?,
+                    "NONSCALING" => nonscaling = true,
+                    _ => return None,
+                }
+            }
+
+            Some(Command::BfReserve { key, error_rate, capacity, expansion, nonscaling })
+        }
+        "BF.ADD" => {
+            let key = args.first()?.to_string();
+            let item = args.get(1)?.to_string();
+            Some(Command::BfAdd { key, items: vec![item], multi: false })
+        }
+        "BF.MADD" => {
+            let key = args.first()?.to_string();
+            let items = args[1..].iter().map(|s| s.to_string()).collect();
+            Some(Command::BfAdd { key, items, multi: true })
+        }
+        "BF.EXISTS" => {
+            let key = args.first()?.to_string();
+            let item = args.get(1)?.to_string();
+            Some(Command::BfExists { key, item })
+        }
+        "BF.INFO" => {
+            let key = args.first()?.to_string();
+            let field = args.get(1).map(|s| s.to_string());
+            Some(Command::BfInfo { key, field })
+        }
+        "CLIENT" => match args.first()?.to_uppercase().as_str() {
+            "SETINFO" => {
+                let attr = args.get(1)?.to_string();
+                let value = args.get(2)?.to_string();
+                Some(Command::ClientSetinfo { attr, value })
+            }
+            "LIST" => Some(Command::ClientList),
+            "INFO" => Some(Command::ClientInfo),
+            _ => None,
+        },
+        "OBJECT" => {
+            let key = args.get(1)?.to_string();
+            match args.first()?.to_uppercase().as_str() {
+                "ENCODING" => Some(Command::ObjectEncoding(key)),
+                "REFCOUNT" => Some(Command::ObjectRefcount(key)),
+                "IDLETIME" => Some(Command::ObjectIdletime(key)),
+                "FREQ" => Some(Command::ObjectFreq(key)),
+                _ => None,
+            }
+        }
+        "MEMORY" => match args.first()?.to_uppercase().as_str() {
+            "USAGE" => {
+                let key = args.get(1)?.to_string();
+                let mut samples = 5;
+
+                let mut options = Options::new(&args[2..]);
+                while let Some(option) = options.next_option() {
+                    match option.as_str() {
+                        "SAMPLES" => samples = options.parse::<usize>()?,
+                        _ => return None,
+                    }
+                }
+
+                Some(Command::MemoryUsage { key, samples })
+            }
+            _ => None,
+        },
+        "LATENCY" => match args.first()?.to_uppercase().as_str() {
+            "HISTOGRAM" => {
+                let commands = args[1..].iter().map(|s| s.to_string()).collect();
+                Some(Command::LatencyHistogram(commands))
+            }
+            _ => None,
+        },
+        "HELLO" => {
+            let protover = args.first().map(|s| s.to_string());
+            let mut setname = None;
+
+            let mut options = Options::new(args.get(1..).unwrap_or_default());
+            while let Some(option) = options.next_option() {
+                match option.as_str() {
+                    // Accepted and ignored: there are no users or passwords to check
+                    "AUTH" => {
+                        options.value()?;
+                        options.value()?;
+                    }
+                    "SETNAME" => setname = Some(options.value()?.to_string()),
+                    _ => return None,
+                }
+            }
+            Some(Command::Hello { protover, setname })
+        }
+        "INFO" => {
+            let section = args.first().map(|s| s.to_string());
+            Some(Command::Info(section))
+        }
+        "VADD" => {
+            let key = args.first()?.to_string();
+
+            // Only the VALUES form is supported; FP32 blobs aren't valid UTF-8 once decoded
+            if !args.get(1)?.eq_ignore_ascii_case("VALUES") {
+                return None;
+            }
+            let (vector, rest) = parse_vector_values(&args[2..])?;
+            let element = rest.first()?.to_string();
+
+            let mut attributes = None;
+            let mut options = Options::new(&rest[1..]);
+            while let Some(option) = options.next_option() {
+                match option.as_str() {
+                    "SETATTR" => attributes = Some(options.value()?.to_string()),
+                    // Graph and quantization tuning don't apply to the exhaustive index
+                    "CAS" | "NOQUANT" | "Q8" | "BIN" => {}
+                    "EF" | "M" => {
+                        options.parse::<usize>()?;
+                    }
+                    _ => return None,
+                }
+            }
+
+            Some(Command::Vadd { key, vector, element, attributes })
+        }
+        "VSIM" => {
+            let key = args.first()?.to_string();
+
+            let (query, rest) = match args.get(1)?.to_uppercase().as_str() {
+                "ELE" => (VectorQuery::Element(args.get(2)?.to_string()), &args[3..]),
+                "VALUES" => {
+                    let (vector, rest) = parse_vector_values(&args[2..])?;
+                    (VectorQuery::Values(vector), rest)
+                }
+                _ => return None,
+            };
+
+            let mut with_scores = false;
+            let mut count = 10;
+            let mut metric = Metric::Cosine;
+            let mut options = Options::new(rest);
+            while let Some(option) = options.next_option() {
+                match option.as_str() {
+                    "WITHSCORES" => with_scores = true,
+                    "COUNT" => count = options.parse::<usize>()?,
+                    "METRIC" => {
+                        metric = match options.next_option()?.as_str() {
+                            "COSINE" => Metric::Cosine,
+                            "L2" => Metric::L2,
+                            _ => return None,
+                        }
+                    }
+                    "EF" => {
+                        options.parse::<usize>()?;
+                    }
+                    _ => return None,
+                }
+            }
+
+            Some(Command::Vsim { key, query, with_scores, count, metric })
+        }
+        "VCARD" => {
+            let key = args.first()?.to_string();
+            Some(Command::Vcard(key))
+        }
+        "VDIM" => {
+            let key = args.first()?.to_string();
+            Some(Command::Vdim(key))
+        }
+        "VREM" => {
+            let key = args.first()?.to_string();
+            let element = args.get(1)?.to_string();
+            Some(Command::Vrem { key, element })
+        }
+        "VEMB" => {
+            let key = args.first()?.to_string();
+            let element = args.get(1)?.to_string();
+            Some(Command::Vemb { key, element })
+        }
+        "VGETATTR" => {
+            let key = args.first()?.to_string();
+            let element = args.get(1)?.to_string();
+            Some(Command::Vgetattr { key, element })
+        }
+        "VSETATTR" => {
+            let key = args.first()?.to_string();
+            let element = args.get(1)?.to_string();
+            let attributes = args.get(2)?.to_string();
+            Some(Command::Vsetattr { key, element, attributes })
+        }
+        "READONLY" => Some(Command::Readonly),
+        "READWRITE" => Some(Command::Readwrite),
+        "TOPK.RESERVE" => {
+            let key = args.first()?.to_string();
+            let k = args.get(1)?.parse::<usize>().ok()?;
+            // width, depth and decay are optional but must be given together
+            let (width, depth, decay) = match args.get(2) {
+                Some(width) => (
+                    width.parse::<usize>().ok()?,
+                    args.get(3)?.parse::<usize>().ok()?,
+                    args.get(4)?.parse::<f64>().ok()?,
+                ),
+                None => (sketch::DEFAULT_TOPK_WIDTH, sketch::DEFAULT_TOPK_DEPTH, sketch::DEFAULT_TOPK_DECAY),
+            };
+            Some(Command::TopkReserve { key, k, width, depth, decay })
+        }
+        "TOPK.ADD" | "TOPK.QUERY" => {
+            let key = args.first()?.to_string();
+            let items: Vec<String> = args[1..].iter().map(|s| s.to_string()).collect();
+            if items.is_empty() {
+                return None;
+            }
+            if command_name == "TOPK.ADD" {
+                Some(Command::TopkAdd { key, items })
+            } else {
+                Some(Command::TopkQuery { key, items })
+            }
+        }
+        "TOPK.LIST" => {
+            let key = args.first()?.to_string();
+            let mut options = Options::new(&args[1..]);
+            let with_count = options.next_is("WITHCOUNT");
+            if !options.rest().is_empty() {
+                return None;
+            }
+            Some(Command::TopkList { key, with_count })
+        }
+        "CMS.INITBYDIM" => {
+            let key = args.first()?.to_string();
+            let width = args.get(1)?.parse::<usize>().ok()?;
+            let depth = args.get(2)?.parse::<usize>().ok()?;
+            Some(Command::CmsInitbydim { key, width, depth })
+        }
+        "CMS.INCRBY" => {
+            let key = args.first()?.to_string();
+            let pairs = &args[1..];
+            if pairs.is_empty() || !pairs.len().is_multiple_of(2) {
+                return None;
+            }
+            let increments = pairs
+                .chunks(2)
+                .map(|pair| Some((pair[0].to_string(), pair[1].parse::<u64>().ok()?)))
+                .collect::<Option<Vec<_>>>()?;
+            Some(Command::CmsIncrby { key, increments })
+        }
+        "CMS.QUERY" => {
+            let key = args.first()?.to_string();
+            let items: Vec<String> = args[1..].iter().map(|s| s.to_string()).collect();
+            if items.is_empty() {
+                return None;
+            }
+            Some(Command::CmsQuery { key, items })
+        }
+        "TS.CREATE" => {
+            let key = args.first()?.to_string();
+            let (retention, labels) = parse_ts_create_options(&args[1..])?;
+            Some(Command::TsCreate { key, retention: retention.unwrap_or(0), labels })
+        }
+        "TS.ADD" => {
+            let key = args.first()?.to_string();
+            let timestamp = match *args.get(1)? {
+                "*" => None,
+                ts => Some(ts.parse::<u64>().ok()?),
+            };
+            let value = args.get(2)?.parse::<f64>().ok()?;
+            let (retention, labels) = parse_ts_create_options(&args[3..])?;
+            Some(Command::TsAdd { key, timestamp, value, retention, labels })
+        }
+        "TS.RANGE" => {
+            let key = args.first()?.to_string();
+            let (range, rest) = parse_ts_range_args(&args[1..])?;
+            if !rest.is_empty() {
+                return None;
+            }
+            Some(Command::TsRange { key, range })
+        }
+        "TS.MRANGE" => {
+            let (range, rest) = parse_ts_range_args(&args)?;
+
+            // WITHLABELS must come before FILTER, which consumes the rest of the arguments
+            let mut options = Options::new(rest);
+            let with_labels = options.next_is("WITHLABELS");
+            if !options.next_is("FILTER") {
+                return None;
+            }
+            let filters = options
+                .rest()
+                .iter()
+                .map(|f| LabelFilter::parse(f))
+                .collect::<Option<Vec<_>>>()?;
+            if filters.is_empty() {
+                return None;
+            }
+
+            Some(Command::TsMrange { range, with_labels, filters })
+        }
         _ => None,
     }
 }
+
+// Parses [RETENTION ms] [LABELS label value ...] shared by TS.CREATE and TS.ADD
+fn parse_ts_create_options(args: &[&str]) -> Option<(Option<u64>, Labels)> {
+    let mut retention = None;
+    let mut labels = Vec::new();
+
+    let mut options = Options::new(args);
+    while let Some(option) = options.next_option() {
+        match option.as_str() {
+            "RETENTION" => retention = Some(options.parse::<u64>()?),
+            "LABELS" => {
+                // Labels are the remaining label/value pairs
+                let pairs = options.rest();
+                if pairs.is_empty() || !pairs.len().is_multiple_of(2) {
+                    return None;
+                }
+                for pair in pairs.chunks(2) {
+                    labels.push((pair[0].to_string(), pair[1].to_string()));
+                }
+                break;
+            }
+            _ => return None,
+        }
+    }
+
+    Some((retention, labels))
+}
+
+// Parses the "numfields field ..." after FIELDS in HEXPIRE and friends, which must
+// name every remaining argument
+fn parse_hash_fields(args: &[&str]) -> Option<Vec<String>> {
+    let (count, fields) = args.split_first()?;
+    let count = count.parse::<usize>().ok().filter(|&count| count > 0)?;
+    (fields.len() == count).then(|| fields.iter().map(|s| s.to_string()).collect())
+}
+
+// Parses "from to [COUNT n] [AGGREGATION type bucket]", returning any unconsumed arguments
+fn parse_ts_range_args<'a>(args: &'a [&'a str]) -> Option<(TsRangeArgs, &'a [&'a str])> {
+    let from = match *args.first()? {
+        "-" => 0,
+        s => s.parse::<u64>().ok()?,
+    };
+    let to = match *args.get(1)? {
+        "+" => u64::MAX,
+        s => s.parse::<u64>().ok()?,
+    };
+
+    let mut count = None;
+    let mut aggregation = None;
+    let mut options = Options::new(&args[2..]);
+    loop {
+        if options.next_is("COUNT") {
+            count = Some(options.parse::<usize>()?);
+        } else if options.next_is("AGGREGATION") {
+            let kind = Aggregation::parse(options.value()?)?;
+            let bucket = options.parse::<u64>().filter(|&b| b > 0)?;
+            aggregation = Some((kind, bucket));
+        } else {
+            break;
+        }
+    }
+
+    Some((TsRangeArgs { from, to, count, aggregation }, options.rest()))
+}
+
+// Parses "num v1 ... vnum" (the part after VALUES), returning the vector and the remaining arguments
+fn parse_vector_values<'a>(args: &'a [&'a str]) -> Option<(Vec<f32>, &'a [&'a str])> {
+    let num = args.first()?.parse::<usize>().ok().filter(|&n| n > 0)?;
+    let values = args.get(1..=num)?;
+    let vector = values
+        .iter()
+        .map(|v| v.parse::<f32>().ok().filter(|v| v.is_finite()))
+        .collect::<Option<Vec<f32>>>()?;
+    Some((vector, &args[num + 1..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash_fields_must_match_their_count() {
+        assert_eq!(parse_hash_fields(&["2", "a", "b"]), Some(vec![String::from("a"), String::from("b")]));
+        assert_eq!(parse_hash_fields(&["2", "a"]), None);
+        assert_eq!(parse_hash_fields(&["1", "a", "b"]), None);
+        assert_eq!(parse_hash_fields(&["0"]), None);
+        assert_eq!(parse_hash_fields(&["x", "a"]), None);
+        assert_eq!(parse_hash_fields(&[]), None);
+    }
+
+    #[test]
+    fn ts_range_args() {
+        let (range, rest) = parse_ts_range_args(&["-", "+"]).unwrap();
+        assert_eq!((range.from, range.to, range.count), (0, u64::MAX, None));
+        assert!(range.aggregation.is_none() && rest.is_empty());
+
+        let (range, rest) = parse_ts_range_args(&["10", "20", "COUNT", "5", "AGGREGATION", "avg", "1000", "FILTER", "a=b"]).unwrap();
+        assert_eq!((range.from, range.to, range.count), (10, 20, Some(5)));
+        assert!(matches!(range.aggregation, Some((_, 1000))));
+        assert_eq!(rest, ["FILTER", "a=b"]);
+
+        assert!(parse_ts_range_args(&["10"]).is_none());
+        assert!(parse_ts_range_args(&["x", "20"]).is_none());
+        assert!(parse_ts_range_args(&["10", "20", "AGGREGATION", "avg", "0"]).is_none());
+        assert!(parse_ts_range_args(&["10", "20", "COUNT"]).is_none());
+    }
+
+    #[test]
+    fn vector_values() {
+        let (vector, rest) = parse_vector_values(&["2", "1.5", "-2", "elem"]).unwrap();
+        assert_eq!(vector, vec![1.5, -2.0]);
+        assert_eq!(rest, ["elem"]);
+
+        assert!(parse_vector_values(&["0"]).is_none());
+        assert!(parse_vector_values(&["3", "1", "2"]).is_none());
+        assert!(parse_vector_values(&["1", "nan"]).is_none());
+        assert!(parse_vector_values(&["1", "inf"]).is_none());
+        assert!(parse_vector_values(&["1", "x"]).is_none());
+    }
+}
@@ -0,0 +1,104 @@
+use std::io::{Read, Result as IoResult, Write};
+
+use crate::frame::frame_len;
+use crate::{Clients, Blocked, Db, handle_connection, loading};
+
+#[derive(Debug, Default)]
+pub struct LoadReport {
+    pub replies: usize,
+    pub errors: Vec<(usize, String)>, // (command number, message)
+}
+
+// Hands handle_connection one frame per read and inspects the reply each produced
+struct FrameStream<'a> {
+    frames: std::vec::IntoIter<&'a [u8]>,
+    unsent: &'a [u8], // Rest of the frame being handed out
+    issued: usize,
+    loaded_bytes: u64,
+    awaiting_reply: bool,
+    output: Vec<u8>,
+    report: LoadReport,
+}
+
+impl FrameStream<'_> {
+    // Accounts for the reply (or lack of one) to the last frame handed out
+    fn settle(&mut self) {
+        if !self.awaiting_reply {
+            return;
+        }
+        self.awaiting_reply = false;
+        if self.output.is_empty() {
+            self.report.errors.push((self.issued, String::from("command not recognized")));
+        } else if self.output[0] == b'-' {
+            let message = String::from_utf8_lossy(&self.output[1..]).trim_end().to_string();
+            self.report.errors.push((self.issued, message));
+        } else {
+            self.report.replies += 1;
+        }
+        self.output.clear();
+    }
+}
+
+impl Read for FrameStream<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        // Frames bigger than the reader's buffer go out in pieces; handle_connection reassembles them
+        if self.unsent.is_empty() {
+            self.settle();
+            let Some(frame) = self.frames.next() else {
+                return Ok(0);
+            };
+            self.issued += 1;
+            self.awaiting_reply = true;
+            self.unsent = frame;
+        }
+
+        let len = self.unsent.len().min(buf.len());
+        buf[..len].copy_from_slice(&self.unsent[..len]);
+        self.unsent = &self.unsent[len..];
+        self.loaded_bytes += len as u64;
+        loading::progress(self.loaded_bytes);
+        Ok(len)
+    }
+}
+
+impl Write for FrameStream<'_> {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        self.output.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        Ok(())
+    }
+}
+
+/// Executes every command in a RESP protocol stream (the format `redis-cli --pipe` consumes).
+pub fn load_commands(data: &[u8], db: Db, blocked: Blocked, clients: Clients) -> Result<LoadReport, String> {
+    let mut frames = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        match frame_len(&data[pos..]) {
+            Ok(Some(len)) => {
+                frames.push(&data[pos..pos + len]);
+                pos += len;
+            }
+            Ok(None) => return Err(format!("truncated command at byte {}", pos)),
+            Err(e) => return Err(format!("Protocol error at byte {}: {}", pos, e)),
+        }
+    }
+
+    let mut stream = FrameStream {
+        frames: frames.into_iter(),
+        unsent: &[],
+        issued: 0,
+        loaded_bytes: 0,
+        awaiting_reply: false,
+        output: Vec::new(),
+        report: LoadReport::default(),
+    };
+    loading::run_as_loader(|| handle_connection(&mut stream, String::from("mass-insert"), db, blocked, clients))
+        .map_err(|e| e.to_string())?;
+    stream.settle();
+
+    Ok(stream.report)
+}
@@ -0,0 +1,226 @@
+use std::io::{BufRead, BufReader, Read, Result as IoResult, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::{Db, Entry, RedisValue, get_valid_entry, unix_time_ms};
+
+// Exptimes above 30 days are absolute Unix timestamps in the memcached protocol
+const MAX_RELATIVE_EXPTIME: i64 = 60 * 60 * 24 * 30;
+
+// Largest value accepted by a storage command
+const MAX_VALUE_LEN: usize = 1024 * 1024;
+
+// Longest command line, newline included, that's read before the client is cut off
+const MAX_LINE_LEN: u64 = 2048;
+
+pub fn serve(listener: TcpListener, db: Db) {
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                println!("accepted new memcached connection");
+                let db_clone = Arc::clone(&db);
+                std::thread::spawn(move || {
+                    if let Err(e) = handle_memcached(stream, db_clone) {
+                        println!("memcached error: {}", e);
+                    }
+                });
+            }
+            Err(e) => {
+                println!("error: {}", e);
+            }
+        }
+    }
+}
+
+enum StoreMode {
+    Set,
+    Add,
+    Replace,
+}
+
+// Converts a memcached exptime into a Unix time in milliseconds; Err(()) means "already expired"
+fn parse_exptime(exptime: i64) -> Result<Option<i64>, ()> {
+    if exptime == 0 {
+        return Ok(None);
+    }
+    if exptime < 0 {
+        return Err(());
+    }
+    let now = unix_time_ms();
+    if exptime <= MAX_RELATIVE_EXPTIME {
+        return Ok(Some(now + exptime * 1000));
+    }
+
+    let at = exptime.saturating_mul(1000);
+    if at <= now { Err(()) } else { Ok(Some(at)) }
+}
+
+fn handle_memcached(stream: TcpStream, db: Db) -> IoResult<()> {
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+
+    loop {
+        line.clear();
+        let n = (&mut reader).take(MAX_LINE_LEN).read_line(&mut line)?;
+        if n == 0 {
+            break;
+        }
+        if n as u64 == MAX_LINE_LEN && !line.ends_with('\n') {
+            writer.write_all(b"CLIENT_ERROR line too long\r\n")?;
+            break;
+        }
+
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        let Some(&command) = parts.first() else {
+            writer.write_all(b"ERROR\r\n")?;
+            continue;
+        };
+
+        // "noreply" is always the last token of a command that accepts it
+        let noreply = parts.last() == Some(&"noreply");
+        let args = if noreply { &parts[1..parts.len() - 1] } else { &parts[1..] };
+
+        let response = match command {
+            "get" if !args.is_empty() => {
+                let mut map = db.lock().unwrap();
+                let mut response = String::new();
+                for key in args {
+                    if let Some(Entry { value: RedisValue::String(value), flags, .. }) = get_valid_entry(&mut map, key) {
+                        response.push_str(&format!("VALUE {} {} {}\r\n{}\r\n", key, flags, value.len(), value));
+                    }
+                }
+                response.push_str("END\r\n");
+                response
+            }
+            "set" | "add" | "replace" if args.len() == 4 => {
+                let mode = match command {
+                    "add" => StoreMode::Add,
+                    "replace" => StoreMode::Replace,
+                    _ => StoreMode::Set,
+                };
+                let (Ok(flags), Ok(exptime), Ok(len)) =
+                    (args[1].parse::<u32>(), args[2].parse::<i64>(), args[3].parse::<usize>())
+                else {
+                    writer.write_all(b"CLIENT_ERROR bad command line format\r\n")?;
+                    continue;
+                };
+                if len > MAX_VALUE_LEN {
+                    writer.write_all(b"SERVER_ERROR object too large for cache\r\n")?;
+                    break;
+                }
+
+                // Data block is followed by its own \r\n
+                let mut data = vec![0; len + 2];
+                reader.read_exact(&mut data)?;
+                if &data[len..] != b"\r\n" {
+                    writer.write_all(b"CLIENT_ERROR bad data chunk\r\n")?;
+                    continue;
+                }
+                // Values are kept as strings, so a binary payload would come back altered
+                data.truncate(len);
+                let Ok(value) = String::from_utf8(data) else {
+                    writer.write_all(b"CLIENT_ERROR value must be valid UTF-8\r\n")?;
+                    continue;
+                };
+
+                let mut map = db.lock().unwrap();
+                let exists = get_valid_entry(&mut map, args[0]).is_some();
+                let should_store = match mode {
+                    StoreMode::Set => true,
+                    StoreMode::Add => !exists,
+                    StoreMode::Replace => exists,
+                };
+
+                if !should_store {
+                    String::from("NOT_STORED\r\n")
+                } else {
+                    match parse_exptime(exptime) {
+                        Ok(expires_at) => {
+                            map.insert(
+                                args[0].to_string(),
+                                Entry {
+                                    value: RedisValue::String(value),
+                                    expires_at,
+                                    last_access: Instant::now(),
+                                    flags,
+                                },
+                            );
+                        }
+                        // Storing an already-expired item just evicts the old one
+                        Err(()) => {
+                            map.remove(args[0]);
+                        }
+                    }
+                    String::from("STORED\r\n")
+                }
+            }
+            "delete" if args.len() == 1 => {
+                let mut map = db.lock().unwrap();
+                if get_valid_entry(&mut map, args[0]).is_some() {
+                    map.remove(args[0]);
+                    String::from("DELETED\r\n")
+                } else {
+                    String::from("NOT_FOUND\r\n")
+                }
+            }
+            "incr" | "decr" if args.len() == 2 => {
+                let Ok(delta) = args[1].parse::<u64>() else {
+                    writer.write_all(b"CLIENT_ERROR invalid numeric delta argument\r\n")?;
+                    continue;
+                };
+
+                let mut map = db.lock().unwrap();
+                let _ = get_valid_entry(&mut map, args[0]);
+                match map.get_mut(args[0]) {
+                    Some(Entry { value: RedisValue::String(value), .. }) => match value.parse::<u64>() {
+                        Ok(current) => {
+                            // incr wraps at 64 bits, decr stops at zero
+                            let updated = if command == "incr" {
+                                current.wrapping_add(delta)
+                            } else {
+                                current.saturating_sub(delta)
+                            };
+                            *value = updated.to_string();
+                            format!("{}\r\n", updated)
+                        }
+                        Err(_) => String::from("CLIENT_ERROR cannot increment or decrement non-numeric value\r\n"),
+                    },
+                    Some(_) => String::from("CLIENT_ERROR cannot increment or decrement non-numeric value\r\n"),
+                    None => String::from("NOT_FOUND\r\n"),
+                }
+            }
+            "touch" if args.len() == 2 => {
+                let Ok(exptime) = args[1].parse::<i64>() else {
+                    writer.write_all(b"CLIENT_ERROR invalid exptime argument\r\n")?;
+                    continue;
+                };
+
+                let mut map = db.lock().unwrap();
+                if get_valid_entry(&mut map, args[0]).is_some() {
+                    match parse_exptime(exptime) {
+                        Ok(expires_at) => {
+                            let entry = map.get_mut(args[0]).unwrap();
+                            entry.expires_at = expires_at;
+                        }
+                        Err(()) => {
+                            map.remove(args[0]);
+                        }
+                    }
+                    String::from("TOUCHED\r\n")
+                } else {
+                    String::from("NOT_FOUND\r\n")
+                }
+            }
+            "version" => String::from("VERSION 1.6.0-redis-compat\r\n"),
+            "quit" => break,
+            _ => String::from("ERROR\r\n"),
+        };
+
+        if !noreply {
+            writer.write_all(response.as_bytes())?;
+        }
+    }
+    Ok(())
+}
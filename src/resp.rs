@@ -0,0 +1,134 @@
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::io::{Result as IoResult, Write};
+use std::sync::LazyLock;
+
+// Integers below this are encoded once and shared, like Redis' OBJ_SHARED_INTEGERS
+const SHARED_INTEGERS: i64 = 10000;
+
+static SHARED_INTEGER_REPLIES: LazyLock<Vec<String>> =
+    LazyLock::new(|| (0..SHARED_INTEGERS).map(|i| format!(":{}\r\n", i)).collect());
+
+thread_local! {
+    // RESP version negotiated by HELLO. Each connection runs on its own thread,
+    // so the encoder can consult this without threading it through every reply.
+    static PROTOCOL: Cell<u8> = const { Cell::new(2) };
+}
+
+pub fn protocol() -> u8 {
+    PROTOCOL.get()
+}
+
+pub fn set_protocol(version: u8) {
+    PROTOCOL.set(version);
+}
+
+/// A command's reply. Handlers build one of these; `encode` is the only place that knows the wire format.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reply {
+    SimpleString(Cow<'static, str>),
+    Error(Cow<'static, str>), // Includes the error code, e.g. "ERR ..."
+    Integer(i64),
+    Bulk(String),
+    Array(Vec<Reply>),
+    Map(Vec<(Reply, Reply)>), // Flattened into an array for RESP2 clients
+    #[allow(dead_code)] // Out-of-band data such as pub/sub messages; nothing publishes yet
+    Push(Vec<Reply>), // A plain array for RESP2 clients
+    Set(Vec<Reply>),  // A plain array for RESP2 clients
+    Double(f64),              // A bulk string for RESP2 clients
+    Boolean(bool),            // 1 or 0 for RESP2 clients
+    #[allow(dead_code)] // For integers beyond i64; no command returns one yet
+    BigNumber(String), // A bulk string for RESP2 clients
+    Null,                     // Null bulk string
+    NullArray,
+}
+
+impl Reply {
+    pub const OK: Reply = Reply::SimpleString(Cow::Borrowed("OK"));
+    pub const PONG: Reply = Reply::SimpleString(Cow::Borrowed("PONG"));
+    pub const EMPTY_ARRAY: Reply = Reply::Array(Vec::new());
+    pub const WRONGTYPE: Reply =
+        Reply::Error(Cow::Borrowed("WRONGTYPE Operation against a key holding the wrong kind of value"));
+
+    pub fn error(msg: impl Into<Cow<'static, str>>) -> Reply {
+        Reply::Error(msg.into())
+    }
+
+    pub fn bulk(s: impl Into<String>) -> Reply {
+        Reply::Bulk(s.into())
+    }
+
+    pub fn integer(i: impl TryInto<i64>) -> Reply {
+        Reply::Integer(i.try_into().unwrap_or(i64::MAX))
+    }
+
+    pub fn bulks<S: Into<String>>(items: impl IntoIterator<Item = S>) -> Reply {
+        Reply::Array(items.into_iter().map(Reply::bulk).collect())
+    }
+
+    pub fn encode_to(&self, out: &mut impl Write) -> IoResult<()> {
+        let resp3 = protocol() == 3;
+        match self {
+            Reply::SimpleString(s) => write!(out, "+{}\r\n", s),
+            Reply::Error(msg) => write!(out, "-{}\r\n", msg),
+            Reply::Integer(i) if (0..SHARED_INTEGERS).contains(i) => {
+                out.write_all(SHARED_INTEGER_REPLIES[*i as usize].as_bytes())
+            }
+            Reply::Integer(i) => write!(out, ":{}\r\n", i),
+            Reply::Bulk(s) => {
+                write!(out, "${}\r\n", s.len())?;
+                out.write_all(s.as_bytes())?;
+                out.write_all(b"\r\n")
+            }
+            Reply::Array(items) => {
+                write!(out, "*{}\r\n", items.len())?;
+                items.iter().try_for_each(|item| item.encode_to(out))
+            }
+            Reply::Map(pairs) => {
+                if resp3 {
+                    write!(out, "%{}\r\n", pairs.len())?;
+                } else {
+                    write!(out, "*{}\r\n", pairs.len() * 2)?;
+                }
+                pairs.iter().try_for_each(|(key, value)| {
+                    key.encode_to(out)?;
+                    value.encode_to(out)
+                })
+            }
+            // RESP3 clients can tell these apart from replies, so they may arrive at any time
+            Reply::Push(items) => {
+                write!(out, "{}{}\r\n", if resp3 { '>' } else { '*' }, items.len())?;
+                items.iter().try_for_each(|item| item.encode_to(out))
+            }
+            Reply::Set(items) => {
+                write!(out, "{}{}\r\n", if resp3 { '~' } else { '*' }, items.len())?;
+                items.iter().try_for_each(|item| item.encode_to(out))
+            }
+            Reply::Double(d) if resp3 => match d {
+                d if d.is_nan() => out.write_all(b",nan\r\n"),
+                d if d.is_infinite() => write!(out, ",{}inf\r\n", if *d < 0.0 { "-" } else { "" }),
+                d => write!(out, ",{}\r\n", d),
+            },
+            Reply::Double(d) => Reply::Bulk(d.to_string()).encode_to(out),
+            Reply::Boolean(b) if resp3 => write!(out, "#{}\r\n", if *b { 't' } else { 'f' }),
+            Reply::Boolean(b) => Reply::Integer(*b as i64).encode_to(out),
+            Reply::BigNumber(n) if resp3 => write!(out, "({}\r\n", n),
+            Reply::BigNumber(n) => write!(out, "${}\r\n{}\r\n", n.len(), n),
+            // RESP3 has a single null type for both
+            Reply::Null if resp3 => out.write_all(b"_\r\n"),
+            Reply::NullArray if resp3 => out.write_all(b"_\r\n"),
+            Reply::Null => out.write_all(b"$-1\r\n"),
+            Reply::NullArray => out.write_all(b"*-1\r\n"),
+        }
+    }
+}
+
+pub trait RedisWrite {
+    fn write_reply(&mut self, reply: &Reply) -> IoResult<()>;
+}
+
+impl<W: Write> RedisWrite for W {
+    fn write_reply(&mut self, reply: &Reply) -> IoResult<()> {
+        reply.encode_to(self)
+    }
+}
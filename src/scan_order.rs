@@ -0,0 +1,47 @@
+use std::collections::BTreeMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+/// The names in a hash or set, ordered by a fixed hash of each, for HSCAN and SSCAN.
+/// The cursor is the hash to resume from (0 once done), so a page costs O(COUNT + log N)
+/// and anything there for the whole scan turns up at least once however the collection
+/// changes in between.
+#[derive(Debug, Default)]
+pub struct ScanOrder {
+    names: BTreeMap<u64, Vec<String>>, // More than one name only on a hash collision
+}
+
+fn hash_of(name: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    name.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl ScanOrder {
+    /// Adds `name`, which the caller knows isn't there yet.
+    pub fn insert(&mut self, name: &str) {
+        self.names.entry(hash_of(name)).or_default().push(name.to_string());
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        let hash = hash_of(name);
+        if let Some(names) = self.names.get_mut(&hash) {
+            names.retain(|n| n != name);
+            if names.is_empty() {
+                self.names.remove(&hash);
+            }
+        }
+    }
+
+    /// Roughly `count` names from `cursor` on, and the cursor to continue from. Names
+    /// sharing a hash always come back together.
+    pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<&str>) {
+        let mut page = Vec::new();
+        for (&hash, names) in self.names.range(cursor..) {
+            if page.len() >= count {
+                return (hash, page);
+            }
+            page.extend(names.iter().map(String::as_str));
+        }
+        (0, page)
+    }
+}
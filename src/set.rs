@@ -0,0 +1,182 @@
+use std::borrow::Cow;
+use std::collections::{HashSet, hash_set};
+use std::slice;
+
+use crate::scan_order::ScanOrder;
+use crate::{config, integer_value, random_index};
+
+/// A set's members. Like Redis' intset, a set of nothing but integers is kept as a
+/// sorted Vec<i64> until it outgrows set-max-intset-entries or gains a member that
+/// isn't an integer, and from then on it's a hash table plus the order SSCAN walks.
+#[derive(Debug)]
+pub enum RedisSet {
+    Ints(Vec<i64>), // Sorted, no duplicates
+    Members(HashSet<String>, ScanOrder),
+}
+
+impl Default for RedisSet {
+    fn default() -> Self {
+        RedisSet::Ints(Vec::new())
+    }
+}
+
+impl RedisSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            RedisSet::Ints(ints) => ints.len(),
+            RedisSet::Members(members, _) => members.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn contains(&self, member: &str) -> bool {
+        match self {
+            // Anything that isn't written exactly as an integer can't be in an intset
+            RedisSet::Ints(ints) => integer_value(member).is_some_and(|n| ints.binary_search(&n).is_ok()),
+            RedisSet::Members(members, _) => members.contains(member),
+        }
+    }
+
+    /// Adds `member`, returning whether it's new.
+    pub fn insert(&mut self, member: String) -> bool {
+        if let RedisSet::Ints(ints) = self
+            && let Some(n) = integer_value(&member)
+        {
+            let Err(position) = ints.binary_search(&n) else {
+                return false;
+            };
+            if ints.len() < config::set_max_intset_entries() {
+                ints.insert(position, n);
+                return true;
+            }
+        }
+
+        let (members, order) = self.members();
+        if members.contains(&member) {
+            return false;
+        }
+        order.insert(&member);
+        members.insert(member)
+    }
+
+    /// Removes `member`, returning whether it was there.
+    pub fn remove(&mut self, member: &str) -> bool {
+        match self {
+            RedisSet::Ints(ints) => match integer_value(member).map(|n| ints.binary_search(&n)) {
+                Some(Ok(position)) => {
+                    ints.remove(position);
+                    true
+                }
+                _ => false,
+            },
+            RedisSet::Members(members, order) => {
+                let removed = members.remove(member);
+                if removed {
+                    order.remove(member);
+                }
+                removed
+            }
+        }
+    }
+
+    /// A member picked at random, without gathering the others up first.
+    pub fn random_member(&self) -> Option<Cow<'_, str>> {
+        if self.is_empty() {
+            return None;
+        }
+        let index = random_index(self.len());
+        match self {
+            RedisSet::Ints(ints) => Some(Cow::Owned(ints[index].to_string())),
+            RedisSet::Members(members, _) => members.iter().nth(index).map(|member| Cow::Borrowed(member.as_str())),
+        }
+    }
+
+    /// Removes and returns a member picked at random.
+    pub fn pop_random(&mut self) -> Option<String> {
+        let member = self.random_member()?.into_owned();
+        self.remove(&member);
+        Some(member)
+    }
+
+    /// The members, as strings; integers are only formatted on the way out.
+    pub fn iter(&self) -> Iter<'_> {
+        match self {
+            RedisSet::Ints(ints) => Iter::Ints(ints.iter()),
+            RedisSet::Members(members, _) => Iter::Members(members.iter()),
+        }
+    }
+
+    /// One SSCAN page: members from `cursor` on, and the cursor to continue from. An
+    /// intset is small enough to come back whole, as Redis does.
+    pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<Cow<'_, str>>) {
+        match self {
+            RedisSet::Ints(_) => (0, self.iter().collect()),
+            RedisSet::Members(_, order) => {
+                let (next, members) = order.scan(cursor, count);
+                (next, members.into_iter().map(Cow::Borrowed).collect())
+            }
+        }
+    }
+
+    // The hash table and scan order, converting the intset to them first
+    fn members(&mut self) -> (&mut HashSet<String>, &mut ScanOrder) {
+        if let RedisSet::Ints(ints) = self {
+            let mut order = ScanOrder::default();
+            let members = ints
+                .iter()
+                .map(|n| {
+                    let member = n.to_string();
+                    order.insert(&member);
+                    member
+                })
+                .collect();
+            *self = RedisSet::Members(members, order);
+        }
+        match self {
+            RedisSet::Members(members, order) => (members, order),
+            RedisSet::Ints(_) => unreachable!(),
+        }
+    }
+}
+
+impl FromIterator<String> for RedisSet {
+    fn from_iter<I: IntoIterator<Item = String>>(members: I) -> Self {
+        let mut set = RedisSet::new();
+        for member in members {
+            set.insert(member);
+        }
+        set
+    }
+}
+
+pub enum Iter<'a> {
+    Ints(slice::Iter<'a, i64>),
+    Members(hash_set::Iter<'a, String>),
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = Cow<'a, str>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Iter::Ints(ints) => ints.next().map(|n| Cow::Owned(n.to_string())),
+            Iter::Members(members) => members.next().map(|member| Cow::Borrowed(member.as_str())),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self {
+            Iter::Ints(ints) => ints.size_hint(),
+            Iter::Members(members) => members.size_hint(),
+        }
+    }
+}
+
+impl ExactSizeIterator for Iter<'_> {}
@@ -0,0 +1,159 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::time::SystemTime;
+
+// TOPK.RESERVE defaults, same as RedisBloom
+pub const DEFAULT_TOPK_WIDTH: usize = 8;
+pub const DEFAULT_TOPK_DEPTH: usize = 7;
+pub const DEFAULT_TOPK_DECAY: f64 = 0.9;
+
+// Most counters (width * depth) one sketch may have; they're all allocated up front
+const MAX_CELLS: usize = 1 << 24;
+
+/// Whether a sketch of `width` by `depth` counters is small enough to create.
+pub fn dimensions_fit(width: usize, depth: usize) -> bool {
+    width.checked_mul(depth).is_some_and(|cells| cells <= MAX_CELLS)
+}
+
+fn hash_with_seed(item: &str, seed: u64) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    (seed, item).hash(&mut hasher);
+    hasher.finish()
+}
+
+#[derive(Debug)]
+pub struct CountMinSketch {
+    width: usize,
+    depth: usize,
+    counters: Vec<u64>, // depth rows of width counters
+}
+
+impl CountMinSketch {
+    /// Callers check `dimensions_fit` first.
+    pub fn new(width: usize, depth: usize) -> Self {
+        CountMinSketch { width, depth, counters: vec![0; width * depth] }
+    }
+
+    fn slot(&self, row: usize, item: &str) -> usize {
+        row * self.width + (hash_with_seed(item, row as u64) % self.width as u64) as usize
+    }
+
+    /// Adds `increment` and returns the new estimated count.
+    pub fn increment(&mut self, item: &str, increment: u64) -> u64 {
+        for row in 0..self.depth {
+            let slot = self.slot(row, item);
+            self.counters[slot] = self.counters[slot].saturating_add(increment);
+        }
+        self.query(item)
+    }
+
+    pub fn query(&self, item: &str) -> u64 {
+        (0..self.depth).map(|row| self.counters[self.slot(row, item)]).min().unwrap_or(0)
+    }
+
+    pub fn size_in_bytes(&self) -> usize {
+        self.counters.len() * std::mem::size_of::<u64>()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Bucket {
+    fingerprint: u64,
+    count: u64,
+}
+
+/// HeavyKeeper sketch plus the current top-k list, as used by RedisBloom's TOPK.
+#[derive(Debug)]
+pub struct TopK {
+    k: usize,
+    width: usize,
+    depth: usize,
+    decay: f64,
+    buckets: Vec<Bucket>,
+    top: Vec<(String, u64)>, // At most k items, unordered
+    rng_state: u64,
+}
+
+impl TopK {
+    /// Callers check `dimensions_fit` first.
+    pub fn new(k: usize, width: usize, depth: usize, decay: f64) -> Self {
+        let seed = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_nanos() as u64;
+        TopK {
+            k,
+            width,
+            depth,
+            decay,
+            buckets: vec![Bucket::default(); width * depth],
+            top: Vec::new(), // k comes from the client, so this grows as items arrive
+            rng_state: seed | 1,
+        }
+    }
+
+    // xorshift64*, mapped to [0, 1)
+    fn random(&mut self) -> f64 {
+        self.rng_state ^= self.rng_state >> 12;
+        self.rng_state ^= self.rng_state << 25;
+        self.rng_state ^= self.rng_state >> 27;
+        (self.rng_state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Counts one occurrence of `item`, returning the item it pushed out of the top-k, if any.
+    pub fn add(&mut self, item: &str) -> Option<String> {
+        let fingerprint = hash_with_seed(item, u64::MAX);
+        let mut estimate = 0;
+
+        for row in 0..self.depth {
+            let slot = row * self.width + (hash_with_seed(item, row as u64) % self.width as u64) as usize;
+            let bucket = self.buckets[slot];
+
+            let updated = if bucket.count == 0 || bucket.fingerprint == fingerprint {
+                Bucket { fingerprint, count: bucket.count + 1 }
+            } else if self.random() < self.decay.powf(bucket.count as f64) {
+                // Another item owns the bucket: decay it, taking over once it reaches zero
+                match bucket.count - 1 {
+                    0 => Bucket { fingerprint, count: 1 },
+                    count => Bucket { fingerprint: bucket.fingerprint, count },
+                }
+            } else {
+                bucket
+            };
+
+            if updated.fingerprint == fingerprint {
+                estimate = estimate.max(updated.count);
+            }
+            self.buckets[slot] = updated;
+        }
+
+        if let Some(entry) = self.top.iter_mut().find(|(name, _)| name == item) {
+            entry.1 = entry.1.max(estimate);
+            return None;
+        }
+        if self.top.len() < self.k {
+            self.top.push((item.to_string(), estimate));
+            return None;
+        }
+
+        let (min_index, &(_, min_count)) = self.top.iter().enumerate().min_by_key(|(_, (_, count))| *count)?;
+        if estimate > min_count {
+            let (expelled, _) = std::mem::replace(&mut self.top[min_index], (item.to_string(), estimate));
+            return Some(expelled);
+        }
+        None
+    }
+
+    pub fn contains(&self, item: &str) -> bool {
+        self.top.iter().any(|(name, _)| name == item)
+    }
+
+    pub fn size_in_bytes(&self) -> usize {
+        self.buckets.len() * std::mem::size_of::<Bucket>()
+            + self.top.iter().map(|(name, _)| std::mem::size_of::<(String, u64)>() + name.len()).sum::<usize>()
+    }
+
+    /// Top items, highest count first.
+    pub fn list(&self) -> Vec<(&str, u64)> {
+        let mut items: Vec<(&str, u64)> = self.top.iter().map(|(name, count)| (name.as_str(), *count)).collect();
+        items.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        items
+    }
+}
@@ -0,0 +1,60 @@
+use std::net::TcpListener;
+use std::os::fd::{FromRawFd, RawFd};
+
+// First descriptor passed by systemd (after stdin/stdout/stderr)
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+#[derive(Debug, Default)]
+pub struct ActivatedListeners {
+    pub redis: Option<TcpListener>,
+    pub ws: Option<TcpListener>,
+    pub http: Option<TcpListener>,
+    pub memcached: Option<TcpListener>,
+}
+
+/// Collects listening sockets handed over via the systemd LISTEN_FDS protocol.
+///
+/// Sockets are matched to listeners by their FileDescriptorName= (LISTEN_FDNAMES):
+/// "ws", "http" and "memcached" select the optional listeners, anything else
+/// (including unnamed sockets) becomes the RESP listener. Returns None when
+/// the process wasn't socket-activated.
+pub fn listeners_from_env() -> Result<Option<ActivatedListeners>, String> {
+    let Ok(pid) = std::env::var("LISTEN_PID") else {
+        return Ok(None);
+    };
+    // The variables may have been inherited from a socket-activated parent
+    if pid.parse::<u32>().ok() != Some(std::process::id()) {
+        return Ok(None);
+    }
+
+    let count = std::env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|n| n.parse::<RawFd>().ok())
+        .ok_or("LISTEN_FDS is missing or invalid")?;
+    let names: Vec<String> = std::env::var("LISTEN_FDNAMES")
+        .map(|names| names.split(':').map(String::from).collect())
+        .unwrap_or_default();
+
+    let mut listeners = ActivatedListeners::default();
+    for i in 0..count {
+        let fd = SD_LISTEN_FDS_START + i;
+        // Safety: systemd guarantees descriptors 3..3+LISTEN_FDS are open and owned by us
+        let listener = unsafe { TcpListener::from_raw_fd(fd) };
+
+        let slot = match names.get(i as usize).map(String::as_str) {
+            Some("ws") => &mut listeners.ws,
+            Some("http") => &mut listeners.http,
+            Some("memcached") => &mut listeners.memcached,
+            _ => &mut listeners.redis,
+        };
+        if slot.is_some() {
+            return Err(format!("fd {} duplicates an already activated listener", fd));
+        }
+        *slot = Some(listener);
+    }
+
+    if listeners.redis.is_none() {
+        return Err(String::from("no RESP listening socket among the activated sockets"));
+    }
+    Ok(Some(listeners))
+}
@@ -0,0 +1,144 @@
+use std::collections::BTreeMap;
+
+pub type Labels = Vec<(String, String)>;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Aggregation {
+    Avg,
+    Sum,
+    Min,
+    Max,
+    Count,
+    First,
+    Last,
+}
+
+impl Aggregation {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "avg" => Some(Aggregation::Avg),
+            "sum" => Some(Aggregation::Sum),
+            "min" => Some(Aggregation::Min),
+            "max" => Some(Aggregation::Max),
+            "count" => Some(Aggregation::Count),
+            "first" => Some(Aggregation::First),
+            "last" => Some(Aggregation::Last),
+            _ => None,
+        }
+    }
+
+    fn apply(&self, values: &[f64]) -> f64 {
+        match self {
+            Aggregation::Avg => values.iter().sum::<f64>() / values.len() as f64,
+            Aggregation::Sum => values.iter().sum(),
+            Aggregation::Min => values.iter().cloned().fold(f64::INFINITY, f64::min),
+            Aggregation::Max => values.iter().cloned().fold(f64::NEG_INFINITY, f64::max),
+            Aggregation::Count => values.len() as f64,
+            Aggregation::First => values[0],
+            Aggregation::Last => values[values.len() - 1],
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum LabelFilter {
+    Equals(String, String),
+    NotEquals(String, String),
+}
+
+impl LabelFilter {
+    // Accepts "label=value" and "label!=value"
+    pub fn parse(s: &str) -> Option<Self> {
+        if let Some((label, value)) = s.split_once("!=") {
+            Some(LabelFilter::NotEquals(label.to_string(), value.to_string()))
+        } else {
+            let (label, value) = s.split_once('=')?;
+            Some(LabelFilter::Equals(label.to_string(), value.to_string()))
+        }
+    }
+
+    // An empty value means "label is absent", matching RedisTimeSeries
+    pub fn matches(&self, labels: &[(String, String)]) -> bool {
+        let lookup = |label: &str| labels.iter().find(|(l, _)| l == label).map(|(_, v)| v.as_str());
+        match self {
+            LabelFilter::Equals(label, value) => lookup(label).unwrap_or("") == value,
+            LabelFilter::NotEquals(label, value) => lookup(label).unwrap_or("") != value,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TimeSeriesError {
+    TooOld,
+    Duplicate,
+}
+
+#[derive(Debug)]
+pub struct TimeSeries {
+    pub retention_ms: u64, // 0 keeps samples forever
+    pub labels: Labels,
+    samples: BTreeMap<u64, f64>,
+}
+
+impl TimeSeries {
+    pub fn new(retention_ms: u64, labels: Labels) -> Self {
+        TimeSeries { retention_ms, labels, samples: BTreeMap::new() }
+    }
+
+    pub fn size_in_bytes(&self) -> usize {
+        self.samples.len() * std::mem::size_of::<(u64, f64)>()
+            + self.labels.iter().map(|(label, value)| label.len() + value.len()).sum::<usize>()
+    }
+
+    pub fn add(&mut self, timestamp: u64, value: f64) -> Result<(), TimeSeriesError> {
+        if let Some((&last, _)) = self.samples.last_key_value() {
+            if self.retention_ms > 0 && timestamp < last.saturating_sub(self.retention_ms) {
+                return Err(TimeSeriesError::TooOld);
+            }
+            if self.samples.contains_key(&timestamp) {
+                return Err(TimeSeriesError::Duplicate);
+            }
+        }
+
+        self.samples.insert(timestamp, value);
+
+        // Trim everything that fell out of the retention window
+        if self.retention_ms > 0 {
+            let newest = *self.samples.last_key_value().unwrap().0;
+            let cutoff = newest.saturating_sub(self.retention_ms);
+            self.samples = self.samples.split_off(&cutoff);
+        }
+        Ok(())
+    }
+
+    /// Samples in [from, to], optionally downsampled into buckets of `bucket_ms`.
+    pub fn range(&self, from: u64, to: u64, aggregation: Option<(Aggregation, u64)>) -> Vec<(u64, f64)> {
+        if from > to {
+            return Vec::new();
+        }
+        let samples = self.samples.range(from..=to).map(|(&ts, &v)| (ts, v));
+
+        let Some((aggregation, bucket_ms)) = aggregation else {
+            return samples.collect();
+        };
+
+        let mut result = Vec::new();
+        let mut bucket: Option<u64> = None;
+        let mut values = Vec::new();
+        for (ts, v) in samples {
+            let start = ts - ts % bucket_ms;
+            if bucket != Some(start) {
+                if let Some(prev) = bucket {
+                    result.push((prev, aggregation.apply(&values)));
+                }
+                bucket = Some(start);
+                values.clear();
+            }
+            values.push(v);
+        }
+        if let Some(prev) = bucket {
+            result.push((prev, aggregation.apply(&values)));
+        }
+        result
+    }
+}
@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Metric {
+    Cosine,
+    L2,
+}
+
+#[derive(Debug)]
+struct VectorElement {
+    vector: Vec<f32>,
+    attributes: Option<String>, // Opaque JSON blob set via SETATTR
+}
+
+#[derive(Debug)]
+pub struct VectorSet {
+    dim: usize,
+    elements: HashMap<String, VectorElement>,
+}
+
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f64 {
+    let (mut dot, mut norm_a, mut norm_b) = (0.0f64, 0.0f64, 0.0f64);
+    for (x, y) in a.iter().zip(b) {
+        dot += *x as f64 * *y as f64;
+        norm_a += *x as f64 * *x as f64;
+        norm_b += *y as f64 * *y as f64;
+    }
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+    dot / (norm_a.sqrt() * norm_b.sqrt())
+}
+
+fn l2_distance(a: &[f32], b: &[f32]) -> f64 {
+    a.iter()
+        .zip(b)
+        .map(|(x, y)| (*x as f64 - *y as f64).powi(2))
+        .sum::<f64>()
+        .sqrt()
+}
+
+impl VectorSet {
+    pub fn new(dim: usize) -> Self {
+        VectorSet { dim, elements: HashMap::new() }
+    }
+
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    pub fn len(&self) -> usize {
+        self.elements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.elements.is_empty()
+    }
+
+    pub fn size_in_bytes(&self) -> usize {
+        self.elements
+            .iter()
+            .map(|(name, element)| {
+                name.len()
+                    + element.vector.len() * std::mem::size_of::<f32>()
+                    + element.attributes.as_ref().map_or(0, String::len)
+                    + std::mem::size_of::<VectorElement>()
+            })
+            .sum()
+    }
+
+    /// Inserts or replaces an element, returning true if it's new.
+    /// Existing attributes are kept unless new ones are supplied.
+    pub fn add(&mut self, element: String, vector: Vec<f32>, attributes: Option<String>) -> bool {
+        match self.elements.get_mut(&element) {
+            Some(existing) => {
+                existing.vector = vector;
+                if attributes.is_some() {
+                    existing.attributes = attributes;
+                }
+                false
+            }
+            None => {
+                self.elements.insert(element, VectorElement { vector, attributes });
+                true
+            }
+        }
+    }
+
+    pub fn remove(&mut self, element: &str) -> bool {
+        self.elements.remove(element).is_some()
+    }
+
+    pub fn embedding(&self, element: &str) -> Option<&[f32]> {
+        self.elements.get(element).map(|e| e.vector.as_slice())
+    }
+
+    pub fn attributes(&self, element: &str) -> Option<Option<&str>> {
+        self.elements.get(element).map(|e| e.attributes.as_deref())
+    }
+
+    /// Returns false if the element doesn't exist. An empty string clears the attributes.
+    pub fn set_attributes(&mut self, element: &str, attributes: String) -> bool {
+        match self.elements.get_mut(element) {
+            Some(e) => {
+                e.attributes = (!attributes.is_empty()).then_some(attributes);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The `count` closest elements to `query`, best first, with a similarity score in [0, 1].
+    ///
+    /// This is an exhaustive scan rather than a graph index: exact results, O(n) per query.
+    pub fn similar(&self, query: &[f32], count: usize, metric: Metric) -> Vec<(&str, f64)> {
+        let mut scored: Vec<(&str, f64)> = self
+            .elements
+            .iter()
+            .map(|(name, e)| {
+                let score = match metric {
+                    // Map cosine similarity [-1, 1] onto [0, 1] like Redis does
+                    Metric::Cosine => (cosine_similarity(query, &e.vector) + 1.0) / 2.0,
+                    Metric::L2 => 1.0 / (1.0 + l2_distance(query, &e.vector)),
+                };
+                (name.as_str(), score)
+            })
+            .collect();
+
+        // Ties are broken by name so results are stable
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        scored.truncate(count);
+        scored
+    }
+}
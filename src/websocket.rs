@@ -0,0 +1,426 @@
+use std::io::{Error, ErrorKind, Read, Result as IoResult, Write};
+use std::net::TcpStream;
+
+use crate::{config, http};
+
+// Fixed GUID from RFC 6455 used to derive Sec-WebSocket-Accept
+const WS_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xA;
+
+// Close statuses for a frame that breaks the protocol and for a message bigger than
+// we're willing to buffer
+const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+const CLOSE_MESSAGE_TOO_BIG: u16 = 1009;
+
+/// Wraps an upgraded TCP connection so RESP can be read and written as plain bytes.
+/// Reads yield the payload of incoming data frames; every write is sent as one binary
+/// frame, since replies can hold bytes that aren't valid UTF-8.
+pub struct WsStream {
+    inner: TcpStream,
+    pending: Vec<u8>,
+    closed: bool,
+}
+
+impl WsStream {
+    /// Performs the HTTP upgrade handshake and returns the framed stream. The client must
+    /// present `token`, as `Authorization: Bearer` or, since browsers can't set headers on
+    /// an upgrade, a `token` query parameter. A browser's `Origin` must be in `origins`,
+    /// so a page the user happens to visit can't drive the server.
+    pub fn accept(mut inner: TcpStream, token: Option<&str>, origins: &[String]) -> IoResult<Self> {
+        let mut request = Vec::new();
+        let mut byte = [0u8; 1];
+        while !request.ends_with(b"\r\n\r\n") {
+            if inner.read(&mut byte)? == 0 {
+                return Err(Error::new(ErrorKind::UnexpectedEof, "connection closed during handshake"));
+            }
+            request.push(byte[0]);
+            if request.len() > 8192 {
+                return Err(Error::new(ErrorKind::InvalidData, "handshake too large"));
+            }
+        }
+
+        let request = String::from_utf8_lossy(&request);
+        let header = |wanted: &str| {
+            request.lines().skip(1).find_map(|line| {
+                let (name, value) = line.split_once(':')?;
+                name.trim().eq_ignore_ascii_case(wanted).then(|| value.trim())
+            })
+        };
+        let path = request.lines().next().and_then(|line| line.split(' ').nth(1)).unwrap_or("");
+        let given_token = header("Authorization").and_then(|v| v.strip_prefix("Bearer ")).or_else(|| {
+            let (_, query) = path.split_once('?')?;
+            query.split('&').find_map(|pair| pair.strip_prefix("token="))
+        });
+
+        let rejection = if !header("Upgrade").is_some_and(|v| v.eq_ignore_ascii_case("websocket")) {
+            Some(("400 Bad Request", "not a websocket upgrade"))
+        } else if header("Sec-WebSocket-Version") != Some("13") {
+            Some(("426 Upgrade Required\r\nSec-WebSocket-Version: 13", "unsupported websocket version"))
+        } else if header("Sec-WebSocket-Key").is_none() {
+            Some(("400 Bad Request", "missing Sec-WebSocket-Key"))
+        } else if header("Origin").is_some_and(|origin| !origins.iter().any(|allowed| allowed == origin)) {
+            Some(("403 Forbidden", "origin not allowed"))
+        } else if !http::token_matches(given_token, token) {
+            Some(("401 Unauthorized", "missing or invalid token"))
+        } else {
+            None
+        };
+        if let Some((status, reason)) = rejection {
+            inner.write_all(format!("HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status).as_bytes())?;
+            return Err(Error::new(ErrorKind::InvalidData, reason));
+        }
+        let key = header("Sec-WebSocket-Key").unwrap_or("");
+
+        let accept = base64_encode(&sha1(format!("{}{}", key, WS_GUID).as_bytes()));
+        let response = format!(
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+            accept
+        );
+        inner.write_all(response.as_bytes())?;
+
+        Ok(WsStream {
+            inner,
+            pending: Vec::new(),
+            closed: false,
+        })
+    }
+
+    fn write_frame(&mut self, opcode: u8, payload: &[u8]) -> IoResult<()> {
+        // Server-to-client frames are never masked
+        let mut frame = vec![0x80 | opcode];
+        if payload.len() < 126 {
+            frame.push(payload.len() as u8);
+        } else if payload.len() <= u16::MAX as usize {
+            frame.push(126);
+            frame.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        } else {
+            frame.push(127);
+            frame.extend_from_slice(&(payload.len() as u64).to_be_bytes());
+        }
+        frame.extend_from_slice(payload);
+        self.inner.write_all(&frame)
+    }
+
+    // Reads one frame, buffering data payloads and answering control frames
+    fn read_frame(&mut self) -> IoResult<()> {
+        let mut header = [0u8; 2];
+        self.inner.read_exact(&mut header)?;
+        let opcode = header[0] & 0x0F;
+        // Clients must mask every frame they send
+        if header[1] & 0x80 == 0 {
+            self.write_frame(OPCODE_CLOSE, &CLOSE_PROTOCOL_ERROR.to_be_bytes())?;
+            self.closed = true;
+            return Err(Error::new(ErrorKind::InvalidData, "unmasked websocket frame"));
+        }
+
+        let len = match header[1] & 0x7F {
+            126 => {
+                let mut ext = [0u8; 2];
+                self.inner.read_exact(&mut ext)?;
+                u16::from_be_bytes(ext) as u64
+            }
+            127 => {
+                let mut ext = [0u8; 8];
+                self.inner.read_exact(&mut ext)?;
+                u64::from_be_bytes(ext)
+            }
+            n => n as u64,
+        };
+
+        // The length comes from the client, so check it before allocating anything. Data
+        // that isn't a command yet counts against the same limit as a plain connection's.
+        let limit = config::query_buffer_limit() as u64;
+        if len.saturating_add(self.pending.len() as u64) > limit {
+            self.write_frame(OPCODE_CLOSE, &CLOSE_MESSAGE_TOO_BIG.to_be_bytes())?;
+            self.closed = true;
+            return Err(Error::new(ErrorKind::InvalidData, "websocket frame too large"));
+        }
+
+        let mut mask = [0u8; 4];
+        self.inner.read_exact(&mut mask)?;
+
+        let mut payload = vec![0u8; len as usize];
+        self.inner.read_exact(&mut payload)?;
+        for (i, b) in payload.iter_mut().enumerate() {
+            *b ^= mask[i % 4];
+        }
+
+        match opcode {
+            OPCODE_TEXT | OPCODE_BINARY | OPCODE_CONTINUATION => self.pending.extend_from_slice(&payload),
+            OPCODE_PING => self.write_frame(OPCODE_PONG, &payload)?,
+            OPCODE_PONG => {}
+            OPCODE_CLOSE => {
+                self.write_frame(OPCODE_CLOSE, &payload)?;
+                self.closed = true;
+            }
+            _ => return Err(Error::new(ErrorKind::InvalidData, "unknown websocket opcode")),
+        }
+        Ok(())
+    }
+}
+
+impl Read for WsStream {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        while self.pending.is_empty() {
+            if self.closed {
+                return Ok(0);
+            }
+            match self.read_frame() {
+                Ok(()) => {}
+                // A client dropping the TCP connection is a normal close
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(0),
+                Err(e) => return Err(e),
+            }
+        }
+
+        let n = buf.len().min(self.pending.len());
+        buf[..n].copy_from_slice(&self.pending[..n]);
+        self.pending.drain(..n);
+        Ok(n)
+    }
+}
+
+impl Write for WsStream {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        self.write_frame(OPCODE_BINARY, buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        self.inner.flush()
+    }
+}
+
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for chunk in message.chunks(64) {
+        let mut w = [0u32; 80];
+        for i in 0..16 {
+            w[i] = u32::from_be_bytes([chunk[i * 4], chunk[i * 4 + 1], chunk[i * 4 + 2], chunk[i * 4 + 3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+
+        h[0] = h[0].wrapping_add(a);
+        h[1] = h[1].wrapping_add(b);
+        h[2] = h[2].wrapping_add(c);
+        h[3] = h[3].wrapping_add(d);
+        h[4] = h[4].wrapping_add(e);
+    }
+
+    let mut digest = [0u8; 20];
+    for (i, word) in h.iter().enumerate() {
+        digest[i * 4..i * 4 + 4].copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+fn base64_encode(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut out = String::new();
+    for chunk in data.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        out.push(ALPHABET[(n >> 18) as usize & 63] as char);
+        out.push(ALPHABET[(n >> 12) as usize & 63] as char);
+        out.push(if chunk.len() > 1 { ALPHABET[(n >> 6) as usize & 63] as char } else { '=' });
+        out.push(if chunk.len() > 2 { ALPHABET[n as usize & 63] as char } else { '=' });
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    // A connected pair of sockets: the server's end and the client's
+    fn socket_pair() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        (server, client)
+    }
+
+    fn framed(server: TcpStream) -> WsStream {
+        WsStream { inner: server, pending: Vec::new(), closed: false }
+    }
+
+    fn client_frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x12, 0x34, 0x56, 0x78];
+        let mut frame = vec![0x80 | opcode, 0x80 | payload.len() as u8];
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        frame
+    }
+
+    fn read_some(stream: &mut TcpStream) -> Vec<u8> {
+        let mut buf = [0u8; 256];
+        let n = stream.read(&mut buf).unwrap();
+        buf[..n].to_vec()
+    }
+
+    // Runs the handshake on the server side of a pair after the client sends `request`
+    fn handshake(request: &str, token: Option<&str>, origins: &[String]) -> (IoResult<WsStream>, String) {
+        let (server, mut client) = socket_pair();
+        client.write_all(request.as_bytes()).unwrap();
+        let result = WsStream::accept(server, token, origins);
+        let response = String::from_utf8(read_some(&mut client)).unwrap();
+        (result, response)
+    }
+
+    const UPGRADE: &str = "GET / HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+                           Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n";
+
+    #[test]
+    fn sha1_of_known_input() {
+        let digest = sha1(b"abc");
+        let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+        assert_eq!(hex, "a9993e364706816aba3e25717850c26c9cd0d89d");
+    }
+
+    #[test]
+    fn base64_padding() {
+        assert_eq!(base64_encode(b""), "");
+        assert_eq!(base64_encode(b"f"), "Zg==");
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+        assert_eq!(base64_encode(b"foo"), "Zm9v");
+    }
+
+    #[test]
+    fn accept_key_matches_rfc_example() {
+        // The worked example from RFC 6455 section 1.3
+        let accept = base64_encode(&sha1(format!("{}{}", "dGhlIHNhbXBsZSBub25jZQ==", WS_GUID).as_bytes()));
+        assert_eq!(accept, "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    #[test]
+    fn handshake_with_token() {
+        let (result, response) = handshake(&format!("{}Authorization: Bearer secret\r\n\r\n", UPGRADE), Some("secret"), &[]);
+        assert!(result.is_ok());
+        assert!(response.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        assert!(response.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+    }
+
+    #[test]
+    fn handshake_token_in_query() {
+        let request = format!("{}\r\n", UPGRADE.replacen("GET /", "GET /?a=1&token=secret", 1));
+        let (result, response) = handshake(&request, Some("secret"), &[]);
+        assert!(result.is_ok());
+        assert!(response.starts_with("HTTP/1.1 101"));
+    }
+
+    #[test]
+    fn handshake_rejects_bad_requests() {
+        let origins = [String::from("https://app.example")];
+        let cases = [
+            (format!("{}\r\n", UPGRADE), Some("secret"), "HTTP/1.1 401"),
+            (format!("{}Authorization: Bearer wrong\r\n\r\n", UPGRADE), Some("secret"), "HTTP/1.1 401"),
+            (format!("{}Authorization: Bearer secret\r\n\r\n", UPGRADE), None, "HTTP/1.1 401"),
+            (format!("{}Origin: https://evil.example\r\nAuthorization: Bearer secret\r\n\r\n", UPGRADE), Some("secret"), "HTTP/1.1 403"),
+            (UPGRADE.replace("Version: 13", "Version: 8") + "Authorization: Bearer secret\r\n\r\n", Some("secret"), "HTTP/1.1 426"),
+            (UPGRADE.replace("Upgrade: websocket\r\n", "") + "Authorization: Bearer secret\r\n\r\n", Some("secret"), "HTTP/1.1 400"),
+        ];
+        for (request, token, status) in cases {
+            let (result, response) = handshake(&request, token, &origins);
+            assert!(result.is_err());
+            assert!(response.starts_with(status), "{:?} got {:?}", request, response);
+        }
+
+        let request = format!("{}Origin: https://app.example\r\nAuthorization: Bearer secret\r\n\r\n", UPGRADE);
+        assert!(handshake(&request, Some("secret"), &origins).0.is_ok());
+    }
+
+    #[test]
+    fn reads_masked_frames_and_replies_in_binary() {
+        let (server, mut client) = socket_pair();
+        let mut ws = framed(server);
+
+        // A command split over a text frame and a continuation
+        client.write_all(&client_frame(OPCODE_TEXT, b"PING")).unwrap();
+        client.write_all(&client_frame(OPCODE_CONTINUATION, b"\r\n")).unwrap();
+        let mut buf = [0u8; 16];
+        let mut read = Vec::new();
+        while read.len() < 6 {
+            let n = ws.read(&mut buf).unwrap();
+            read.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(read, b"PING\r\n");
+
+        ws.write_all(b"+PONG\r\n").unwrap();
+        assert_eq!(read_some(&mut client), b"\x82\x07+PONG\r\n");
+    }
+
+    #[test]
+    fn answers_ping_and_close() {
+        let (server, mut client) = socket_pair();
+        let mut ws = framed(server);
+
+        client.write_all(&client_frame(OPCODE_PING, b"hi")).unwrap();
+        client.write_all(&client_frame(OPCODE_CLOSE, &1000u16.to_be_bytes())).unwrap();
+        let mut buf = [0u8; 16];
+        assert_eq!(ws.read(&mut buf).unwrap(), 0);
+
+        let mut replies = Vec::new();
+        while replies.len() < 8 {
+            replies.extend(read_some(&mut client));
+        }
+        assert_eq!(replies, b"\x8a\x02hi\x88\x02\x03\xe8");
+    }
+
+    #[test]
+    fn rejects_unmasked_frames() {
+        let (server, mut client) = socket_pair();
+        let mut ws = framed(server);
+
+        client.write_all(b"\x82\x06PING\r\n").unwrap();
+        let mut buf = [0u8; 16];
+        assert_eq!(ws.read(&mut buf).unwrap_err().kind(), ErrorKind::InvalidData);
+        assert_eq!(read_some(&mut client), b"\x88\x02\x03\xea");
+    }
+
+    #[test]
+    fn rejects_oversized_frames_before_reading_them() {
+        let (server, mut client) = socket_pair();
+        let mut ws = framed(server);
+
+        let mut header = vec![0x82, 0x80 | 127];
+        header.extend_from_slice(&(1u64 << 45).to_be_bytes());
+        client.write_all(&header).unwrap();
+        let mut buf = [0u8; 16];
+        assert_eq!(ws.read(&mut buf).unwrap_err().kind(), ErrorKind::InvalidData);
+        assert_eq!(read_some(&mut client), b"\x88\x02\x03\xf1");
+    }
+}
@@ -0,0 +1,73 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap};
+
+// A score as the ordered index sorts it. Sorted sets never hold NaN, and -0 is stored
+// as 0, so the total order agrees with ==.
+#[derive(Debug, Clone, Copy)]
+struct Score(f64);
+
+impl PartialEq for Score {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+/// A sorted set: members ordered by score, and by member between equal scores. The
+/// map answers score lookups and the ordered index serves ranges.
+#[derive(Debug, Default)]
+pub struct SortedSet {
+    scores: HashMap<String, f64>,
+    ordered: BTreeSet<(Score, String)>,
+}
+
+impl SortedSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.scores.capacity()
+    }
+
+    pub fn score(&self, member: &str) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    /// Adds `member` or moves it to `score`, which mustn't be NaN. Returns whether it's new.
+    pub fn insert(&mut self, member: String, score: f64) -> bool {
+        let score = score + 0.0; // Turns -0 into 0
+        let old = self.scores.insert(member.clone(), score);
+        if let Some(old) = old {
+            self.ordered.remove(&(Score(old), member.clone()));
+        }
+        self.ordered.insert((Score(score), member));
+        old.is_none()
+    }
+
+    /// Members and their scores, lowest score first.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (&String, f64)> + ExactSizeIterator {
+        self.ordered.iter().map(|(score, member)| (member, score.0))
+    }
+}